@@ -2,28 +2,523 @@
 #include "runtime.h"
 #include "output.h"
 
-static float value_as_number(Value v) {
-	if (v.type == VALUE_NUM) return v.n;
-	else if (v.type == VALUE_BOOL) return v.b;
-	else if (v.type == VALUE_STRING) {
-		char *end;
-		float n = strtof(v.s.ptr, &end);
-		if (*end == '\0') return n;
+int immortal_rc = RC_IMMORTAL;
+
+/// the length in bytes of the whitespace character at the start of `s`, or 0 if it isn't one.
+/// This is the whitespace javascript trims, in UTF-8.
+static size_t whitespace_length(const char *s) {
+	const unsigned char *u = (const unsigned char *)s;
+	if (*u == ' ' || (*u >= '\t' && *u <= '\r')) return 1;
+	// no-break space
+	if (u[0] == 0xC2 && u[1] == 0xA0) return 2;
+	// ogham space mark
+	if (u[0] == 0xE1 && u[1] == 0x9A && u[2] == 0x80) return 3;
+	// the spaces from en quad to hair space, line and paragraph separators, narrow no-break space
+	if (u[0] == 0xE2 && u[1] == 0x80 && (u[2] <= 0x8A || u[2] == 0xA8 || u[2] == 0xA9 || u[2] == 0xAF)) return 3;
+	// medium mathematical space
+	if (u[0] == 0xE2 && u[1] == 0x81 && u[2] == 0x9F) return 3;
+	// ideographic space
+	if (u[0] == 0xE3 && u[1] == 0x80 && u[2] == 0x80) return 3;
+	// byte order mark
+	if (u[0] == 0xEF && u[1] == 0xBB && u[2] == 0xBF) return 3;
+	return 0;
+}
+
+/// the length of a UTF-8 string in UTF-16 code units, which is its length in javascript
+static size_t utf16_length(const char *s) {
+	size_t len = 0;
+	for (const unsigned char *u = (const unsigned char *)s; *u; u++) {
+		// continuation bytes are part of the previous character
+		if ((*u & 0xC0) == 0x80) continue;
+		// characters outside the basic multilingual plane are surrogate pairs
+		len += *u >= 0xF0 ? 2 : 1;
+	}
+	return len;
+}
+
+/// characters from `first` to `last` (every `step`th one) whose lowercase is `delta` code points away
+typedef struct {
+	uint32_t first, last;
+	int32_t delta;
+	uint32_t step;
+} LowercaseRange;
+
+typedef struct {
+	uint32_t first, last;
+} CharRange;
+
+// The unicode data javascript's `toLowerCase` uses: the characters lowercasing to a single other character,
+// and the properties deciding wether a sigma ends a word.
+// They are generated from the unicode data of rust's standard library,
+// so that the compiler folds constants with exactly the same rules.
+static const LowercaseRange lowercase_ranges[] = {
+	{ 0x0041, 0x005A, 32, 1 }, { 0x00C0, 0x00D6, 32, 1 }, { 0x00D8, 0x00DE, 32, 1 },
+	{ 0x0100, 0x012E, 1, 2 }, { 0x0132, 0x0136, 1, 2 }, { 0x0139, 0x0147, 1, 2 },
+	{ 0x014A, 0x0176, 1, 2 }, { 0x0178, 0x0178, -121, 1 }, { 0x0179, 0x017D, 1, 2 },
+	{ 0x0181, 0x0181, 210, 1 }, { 0x0182, 0x0184, 1, 2 }, { 0x0186, 0x0186, 206, 1 },
+	{ 0x0187, 0x0187, 1, 1 }, { 0x0189, 0x018A, 205, 1 }, { 0x018B, 0x018B, 1, 1 },
+	{ 0x018E, 0x018E, 79, 1 }, { 0x018F, 0x018F, 202, 1 }, { 0x0190, 0x0190, 203, 1 },
+	{ 0x0191, 0x0191, 1, 1 }, { 0x0193, 0x0193, 205, 1 }, { 0x0194, 0x0194, 207, 1 },
+	{ 0x0196, 0x0196, 211, 1 }, { 0x0197, 0x0197, 209, 1 }, { 0x0198, 0x0198, 1, 1 },
+	{ 0x019C, 0x019C, 211, 1 }, { 0x019D, 0x019D, 213, 1 }, { 0x019F, 0x019F, 214, 1 },
+	{ 0x01A0, 0x01A4, 1, 2 }, { 0x01A6, 0x01A6, 218, 1 }, { 0x01A7, 0x01A7, 1, 1 },
+	{ 0x01A9, 0x01A9, 218, 1 }, { 0x01AC, 0x01AC, 1, 1 }, { 0x01AE, 0x01AE, 218, 1 },
+	{ 0x01AF, 0x01AF, 1, 1 }, { 0x01B1, 0x01B2, 217, 1 }, { 0x01B3, 0x01B5, 1, 2 },
+	{ 0x01B7, 0x01B7, 219, 1 }, { 0x01B8, 0x01B8, 1, 1 }, { 0x01BC, 0x01BC, 1, 1 },
+	{ 0x01C4, 0x01C4, 2, 1 }, { 0x01C5, 0x01C5, 1, 1 }, { 0x01C7, 0x01C7, 2, 1 },
+	{ 0x01C8, 0x01C8, 1, 1 }, { 0x01CA, 0x01CA, 2, 1 }, { 0x01CB, 0x01DB, 1, 2 },
+	{ 0x01DE, 0x01EE, 1, 2 }, { 0x01F1, 0x01F1, 2, 1 }, { 0x01F2, 0x01F4, 1, 2 },
+	{ 0x01F6, 0x01F6, -97, 1 }, { 0x01F7, 0x01F7, -56, 1 }, { 0x01F8, 0x021E, 1, 2 },
+	{ 0x0220, 0x0220, -130, 1 }, { 0x0222, 0x0232, 1, 2 }, { 0x023A, 0x023A, 10795, 1 },
+	{ 0x023B, 0x023B, 1, 1 }, { 0x023D, 0x023D, -163, 1 }, { 0x023E, 0x023E, 10792, 1 },
+	{ 0x0241, 0x0241, 1, 1 }, { 0x0243, 0x0243, -195, 1 }, { 0x0244, 0x0244, 69, 1 },
+	{ 0x0245, 0x0245, 71, 1 }, { 0x0246, 0x024E, 1, 2 }, { 0x0370, 0x0372, 1, 2 },
+	{ 0x0376, 0x0376, 1, 1 }, { 0x037F, 0x037F, 116, 1 }, { 0x0386, 0x0386, 38, 1 },
+	{ 0x0388, 0x038A, 37, 1 }, { 0x038C, 0x038C, 64, 1 }, { 0x038E, 0x038F, 63, 1 },
+	{ 0x0391, 0x03A1, 32, 1 }, { 0x03A3, 0x03AB, 32, 1 }, { 0x03CF, 0x03CF, 8, 1 },
+	{ 0x03D8, 0x03EE, 1, 2 }, { 0x03F4, 0x03F4, -60, 1 }, { 0x03F7, 0x03F7, 1, 1 },
+	{ 0x03F9, 0x03F9, -7, 1 }, { 0x03FA, 0x03FA, 1, 1 }, { 0x03FD, 0x03FF, -130, 1 },
+	{ 0x0400, 0x040F, 80, 1 }, { 0x0410, 0x042F, 32, 1 }, { 0x0460, 0x0480, 1, 2 },
+	{ 0x048A, 0x04BE, 1, 2 }, { 0x04C0, 0x04C0, 15, 1 }, { 0x04C1, 0x04CD, 1, 2 },
+	{ 0x04D0, 0x052E, 1, 2 }, { 0x0531, 0x0556, 48, 1 }, { 0x10A0, 0x10C5, 7264, 1 },
+	{ 0x10C7, 0x10C7, 7264, 1 }, { 0x10CD, 0x10CD, 7264, 1 }, { 0x13A0, 0x13EF, 38864, 1 },
+	{ 0x13F0, 0x13F5, 8, 1 }, { 0x1C89, 0x1C89, 1, 1 }, { 0x1C90, 0x1CBA, -3008, 1 },
+	{ 0x1CBD, 0x1CBF, -3008, 1 }, { 0x1E00, 0x1E94, 1, 2 }, { 0x1E9E, 0x1E9E, -7615, 1 },
+	{ 0x1EA0, 0x1EFE, 1, 2 }, { 0x1F08, 0x1F0F, -8, 1 }, { 0x1F18, 0x1F1D, -8, 1 },
+	{ 0x1F28, 0x1F2F, -8, 1 }, { 0x1F38, 0x1F3F, -8, 1 }, { 0x1F48, 0x1F4D, -8, 1 },
+	{ 0x1F59, 0x1F5F, -8, 2 }, { 0x1F68, 0x1F6F, -8, 1 }, { 0x1F88, 0x1F8F, -8, 1 },
+	{ 0x1F98, 0x1F9F, -8, 1 }, { 0x1FA8, 0x1FAF, -8, 1 }, { 0x1FB8, 0x1FB9, -8, 1 },
+	{ 0x1FBA, 0x1FBB, -74, 1 }, { 0x1FBC, 0x1FBC, -9, 1 }, { 0x1FC8, 0x1FCB, -86, 1 },
+	{ 0x1FCC, 0x1FCC, -9, 1 }, { 0x1FD8, 0x1FD9, -8, 1 }, { 0x1FDA, 0x1FDB, -100, 1 },
+	{ 0x1FE8, 0x1FE9, -8, 1 }, { 0x1FEA, 0x1FEB, -112, 1 }, { 0x1FEC, 0x1FEC, -7, 1 },
+	{ 0x1FF8, 0x1FF9, -128, 1 }, { 0x1FFA, 0x1FFB, -126, 1 }, { 0x1FFC, 0x1FFC, -9, 1 },
+	{ 0x2126, 0x2126, -7517, 1 }, { 0x212A, 0x212A, -8383, 1 }, { 0x212B, 0x212B, -8262, 1 },
+	{ 0x2132, 0x2132, 28, 1 }, { 0x2160, 0x216F, 16, 1 }, { 0x2183, 0x2183, 1, 1 },
+	{ 0x24B6, 0x24CF, 26, 1 }, { 0x2C00, 0x2C2F, 48, 1 }, { 0x2C60, 0x2C60, 1, 1 },
+	{ 0x2C62, 0x2C62, -10743, 1 }, { 0x2C63, 0x2C63, -3814, 1 }, { 0x2C64, 0x2C64, -10727, 1 },
+	{ 0x2C67, 0x2C6B, 1, 2 }, { 0x2C6D, 0x2C6D, -10780, 1 }, { 0x2C6E, 0x2C6E, -10749, 1 },
+	{ 0x2C6F, 0x2C6F, -10783, 1 }, { 0x2C70, 0x2C70, -10782, 1 }, { 0x2C72, 0x2C72, 1, 1 },
+	{ 0x2C75, 0x2C75, 1, 1 }, { 0x2C7E, 0x2C7F, -10815, 1 }, { 0x2C80, 0x2CE2, 1, 2 },
+	{ 0x2CEB, 0x2CED, 1, 2 }, { 0x2CF2, 0x2CF2, 1, 1 }, { 0xA640, 0xA66C, 1, 2 },
+	{ 0xA680, 0xA69A, 1, 2 }, { 0xA722, 0xA72E, 1, 2 }, { 0xA732, 0xA76E, 1, 2 },
+	{ 0xA779, 0xA77B, 1, 2 }, { 0xA77D, 0xA77D, -35332, 1 }, { 0xA77E, 0xA786, 1, 2 },
+	{ 0xA78B, 0xA78B, 1, 1 }, { 0xA78D, 0xA78D, -42280, 1 }, { 0xA790, 0xA792, 1, 2 },
+	{ 0xA796, 0xA7A8, 1, 2 }, { 0xA7AA, 0xA7AA, -42308, 1 }, { 0xA7AB, 0xA7AB, -42319, 1 },
+	{ 0xA7AC, 0xA7AC, -42315, 1 }, { 0xA7AD, 0xA7AD, -42305, 1 }, { 0xA7AE, 0xA7AE, -42308, 1 },
+	{ 0xA7B0, 0xA7B0, -42258, 1 }, { 0xA7B1, 0xA7B1, -42282, 1 }, { 0xA7B2, 0xA7B2, -42261, 1 },
+	{ 0xA7B3, 0xA7B3, 928, 1 }, { 0xA7B4, 0xA7C2, 1, 2 }, { 0xA7C4, 0xA7C4, -48, 1 },
+	{ 0xA7C5, 0xA7C5, -42307, 1 }, { 0xA7C6, 0xA7C6, -35384, 1 }, { 0xA7C7, 0xA7C9, 1, 2 },
+	{ 0xA7CB, 0xA7CB, -42343, 1 }, { 0xA7CC, 0xA7DA, 1, 2 }, { 0xA7DC, 0xA7DC, -42561, 1 },
+	{ 0xA7F5, 0xA7F5, 1, 1 }, { 0xFF21, 0xFF3A, 32, 1 }, { 0x10400, 0x10427, 40, 1 },
+	{ 0x104B0, 0x104D3, 40, 1 }, { 0x10570, 0x1057A, 39, 1 }, { 0x1057C, 0x1058A, 39, 1 },
+	{ 0x1058C, 0x10592, 39, 1 }, { 0x10594, 0x10595, 39, 1 }, { 0x10C80, 0x10CB2, 64, 1 },
+	{ 0x10D50, 0x10D65, 32, 1 }, { 0x118A0, 0x118BF, 32, 1 }, { 0x16E40, 0x16E5F, 32, 1 },
+	{ 0x16EA0, 0x16EB8, 27, 1 }, { 0x1E900, 0x1E921, 34, 1 },
+};
+static const CharRange cased_ranges[] = {
+	{ 0x0041, 0x005A }, { 0x0061, 0x007A }, { 0x00AA, 0x00AA }, { 0x00B5, 0x00B5 }, { 0x00BA, 0x00BA },
+	{ 0x00C0, 0x00D6 }, { 0x00D8, 0x00F6 }, { 0x00F8, 0x01BA }, { 0x01BC, 0x01BF }, { 0x01C4, 0x0293 },
+	{ 0x0296, 0x02AF }, { 0x0370, 0x0373 }, { 0x0376, 0x0377 }, { 0x037B, 0x037D }, { 0x037F, 0x037F },
+	{ 0x0386, 0x0386 }, { 0x0388, 0x038A }, { 0x038C, 0x038C }, { 0x038E, 0x03A1 }, { 0x03A3, 0x03F5 },
+	{ 0x03F7, 0x0481 }, { 0x048A, 0x052F }, { 0x0531, 0x0556 }, { 0x0560, 0x0588 }, { 0x10A0, 0x10C5 },
+	{ 0x10C7, 0x10C7 }, { 0x10CD, 0x10CD }, { 0x10D0, 0x10FA }, { 0x10FD, 0x10FF }, { 0x13A0, 0x13F5 },
+	{ 0x13F8, 0x13FD }, { 0x1C80, 0x1C8A }, { 0x1C90, 0x1CBA }, { 0x1CBD, 0x1CBF }, { 0x1D00, 0x1D2B },
+	{ 0x1D6B, 0x1D77 }, { 0x1D79, 0x1D9A }, { 0x1E00, 0x1F15 }, { 0x1F18, 0x1F1D }, { 0x1F20, 0x1F45 },
+	{ 0x1F48, 0x1F4D }, { 0x1F50, 0x1F57 }, { 0x1F59, 0x1F59 }, { 0x1F5B, 0x1F5B }, { 0x1F5D, 0x1F5D },
+	{ 0x1F5F, 0x1F7D }, { 0x1F80, 0x1FB4 }, { 0x1FB6, 0x1FBC }, { 0x1FBE, 0x1FBE }, { 0x1FC2, 0x1FC4 },
+	{ 0x1FC6, 0x1FCC }, { 0x1FD0, 0x1FD3 }, { 0x1FD6, 0x1FDB }, { 0x1FE0, 0x1FEC }, { 0x1FF2, 0x1FF4 },
+	{ 0x1FF6, 0x1FFC }, { 0x2102, 0x2102 }, { 0x2107, 0x2107 }, { 0x210A, 0x2113 }, { 0x2115, 0x2115 },
+	{ 0x2119, 0x211D }, { 0x2124, 0x2124 }, { 0x2126, 0x2126 }, { 0x2128, 0x2128 }, { 0x212A, 0x212D },
+	{ 0x212F, 0x2134 }, { 0x2139, 0x2139 }, { 0x213C, 0x213F }, { 0x2145, 0x2149 }, { 0x214E, 0x214E },
+	{ 0x2160, 0x217F }, { 0x2183, 0x2184 }, { 0x24B6, 0x24E9 }, { 0x2C00, 0x2C7B }, { 0x2C7E, 0x2CE4 },
+	{ 0x2CEB, 0x2CEE }, { 0x2CF2, 0x2CF3 }, { 0x2D00, 0x2D25 }, { 0x2D27, 0x2D27 }, { 0x2D2D, 0x2D2D },
+	{ 0xA640, 0xA66D }, { 0xA680, 0xA69B }, { 0xA722, 0xA76F }, { 0xA771, 0xA787 }, { 0xA78B, 0xA78E },
+	{ 0xA790, 0xA7DC }, { 0xA7F5, 0xA7F6 }, { 0xA7FA, 0xA7FA }, { 0xAB30, 0xAB5A }, { 0xAB60, 0xAB68 },
+	{ 0xAB70, 0xABBF }, { 0xFB00, 0xFB06 }, { 0xFB13, 0xFB17 }, { 0xFF21, 0xFF3A }, { 0xFF41, 0xFF5A },
+	{ 0x10400, 0x1044F }, { 0x104B0, 0x104D3 }, { 0x104D8, 0x104FB }, { 0x10570, 0x1057A }, { 0x1057C, 0x1058A },
+	{ 0x1058C, 0x10592 }, { 0x10594, 0x10595 }, { 0x10597, 0x105A1 }, { 0x105A3, 0x105B1 }, { 0x105B3, 0x105B9 },
+	{ 0x105BB, 0x105BC }, { 0x10C80, 0x10CB2 }, { 0x10CC0, 0x10CF2 }, { 0x10D50, 0x10D65 }, { 0x10D70, 0x10D85 },
+	{ 0x118A0, 0x118DF }, { 0x16E40, 0x16E7F }, { 0x16EA0, 0x16EB8 }, { 0x16EBB, 0x16ED3 }, { 0x1D400, 0x1D454 },
+	{ 0x1D456, 0x1D49C }, { 0x1D49E, 0x1D49F }, { 0x1D4A2, 0x1D4A2 }, { 0x1D4A5, 0x1D4A6 }, { 0x1D4A9, 0x1D4AC },
+	{ 0x1D4AE, 0x1D4B9 }, { 0x1D4BB, 0x1D4BB }, { 0x1D4BD, 0x1D4C3 }, { 0x1D4C5, 0x1D505 }, { 0x1D507, 0x1D50A },
+	{ 0x1D50D, 0x1D514 }, { 0x1D516, 0x1D51C }, { 0x1D51E, 0x1D539 }, { 0x1D53B, 0x1D53E }, { 0x1D540, 0x1D544 },
+	{ 0x1D546, 0x1D546 }, { 0x1D54A, 0x1D550 }, { 0x1D552, 0x1D6A5 }, { 0x1D6A8, 0x1D6C0 }, { 0x1D6C2, 0x1D6DA },
+	{ 0x1D6DC, 0x1D6FA }, { 0x1D6FC, 0x1D714 }, { 0x1D716, 0x1D734 }, { 0x1D736, 0x1D74E }, { 0x1D750, 0x1D76E },
+	{ 0x1D770, 0x1D788 }, { 0x1D78A, 0x1D7A8 }, { 0x1D7AA, 0x1D7C2 }, { 0x1D7C4, 0x1D7CB }, { 0x1DF00, 0x1DF09 },
+	{ 0x1DF0B, 0x1DF1E }, { 0x1DF25, 0x1DF2A }, { 0x1E900, 0x1E943 }, { 0x1F130, 0x1F149 }, { 0x1F150, 0x1F169 },
+	{ 0x1F170, 0x1F189 },
+};
+static const CharRange case_ignorable_ranges[] = {
+	{ 0x0027, 0x0027 }, { 0x002E, 0x002E }, { 0x003A, 0x003A }, { 0x005E, 0x005E }, { 0x0060, 0x0060 },
+	{ 0x00A8, 0x00A8 }, { 0x00AD, 0x00AD }, { 0x00AF, 0x00AF }, { 0x00B4, 0x00B4 }, { 0x00B7, 0x00B8 },
+	{ 0x02B0, 0x036F }, { 0x0374, 0x0375 }, { 0x037A, 0x037A }, { 0x0384, 0x0385 }, { 0x0387, 0x0387 },
+	{ 0x0483, 0x0489 }, { 0x0559, 0x0559 }, { 0x055F, 0x055F }, { 0x0591, 0x05BD }, { 0x05BF, 0x05BF },
+	{ 0x05C1, 0x05C2 }, { 0x05C4, 0x05C5 }, { 0x05C7, 0x05C7 }, { 0x05F4, 0x05F4 }, { 0x0600, 0x0605 },
+	{ 0x0610, 0x061A }, { 0x061C, 0x061C }, { 0x0640, 0x0640 }, { 0x064B, 0x065F }, { 0x0670, 0x0670 },
+	{ 0x06D6, 0x06DD }, { 0x06DF, 0x06E8 }, { 0x06EA, 0x06ED }, { 0x070F, 0x070F }, { 0x0711, 0x0711 },
+	{ 0x0730, 0x074A }, { 0x07A6, 0x07B0 }, { 0x07EB, 0x07F5 }, { 0x07FA, 0x07FA }, { 0x07FD, 0x07FD },
+	{ 0x0816, 0x082D }, { 0x0859, 0x085B }, { 0x0888, 0x0888 }, { 0x0890, 0x0891 }, { 0x0897, 0x089F },
+	{ 0x08C9, 0x0902 }, { 0x093A, 0x093A }, { 0x093C, 0x093C }, { 0x0941, 0x0948 }, { 0x094D, 0x094D },
+	{ 0x0951, 0x0957 }, { 0x0962, 0x0963 }, { 0x0971, 0x0971 }, { 0x0981, 0x0981 }, { 0x09BC, 0x09BC },
+	{ 0x09C1, 0x09C4 }, { 0x09CD, 0x09CD }, { 0x09E2, 0x09E3 }, { 0x09FE, 0x09FE }, { 0x0A01, 0x0A02 },
+	{ 0x0A3C, 0x0A3C }, { 0x0A41, 0x0A42 }, { 0x0A47, 0x0A48 }, { 0x0A4B, 0x0A4D }, { 0x0A51, 0x0A51 },
+	{ 0x0A70, 0x0A71 }, { 0x0A75, 0x0A75 }, { 0x0A81, 0x0A82 }, { 0x0ABC, 0x0ABC }, { 0x0AC1, 0x0AC5 },
+	{ 0x0AC7, 0x0AC8 }, { 0x0ACD, 0x0ACD }, { 0x0AE2, 0x0AE3 }, { 0x0AFA, 0x0AFF }, { 0x0B01, 0x0B01 },
+	{ 0x0B3C, 0x0B3C }, { 0x0B3F, 0x0B3F }, { 0x0B41, 0x0B44 }, { 0x0B4D, 0x0B4D }, { 0x0B55, 0x0B56 },
+	{ 0x0B62, 0x0B63 }, { 0x0B82, 0x0B82 }, { 0x0BC0, 0x0BC0 }, { 0x0BCD, 0x0BCD }, { 0x0C00, 0x0C00 },
+	{ 0x0C04, 0x0C04 }, { 0x0C3C, 0x0C3C }, { 0x0C3E, 0x0C40 }, { 0x0C46, 0x0C48 }, { 0x0C4A, 0x0C4D },
+	{ 0x0C55, 0x0C56 }, { 0x0C62, 0x0C63 }, { 0x0C81, 0x0C81 }, { 0x0CBC, 0x0CBC }, { 0x0CBF, 0x0CBF },
+	{ 0x0CC6, 0x0CC6 }, { 0x0CCC, 0x0CCD }, { 0x0CE2, 0x0CE3 }, { 0x0D00, 0x0D01 }, { 0x0D3B, 0x0D3C },
+	{ 0x0D41, 0x0D44 }, { 0x0D4D, 0x0D4D }, { 0x0D62, 0x0D63 }, { 0x0D81, 0x0D81 }, { 0x0DCA, 0x0DCA },
+	{ 0x0DD2, 0x0DD4 }, { 0x0DD6, 0x0DD6 }, { 0x0E31, 0x0E31 }, { 0x0E34, 0x0E3A }, { 0x0E46, 0x0E4E },
+	{ 0x0EB1, 0x0EB1 }, { 0x0EB4, 0x0EBC }, { 0x0EC6, 0x0EC6 }, { 0x0EC8, 0x0ECE }, { 0x0F18, 0x0F19 },
+	{ 0x0F35, 0x0F35 }, { 0x0F37, 0x0F37 }, { 0x0F39, 0x0F39 }, { 0x0F71, 0x0F7E }, { 0x0F80, 0x0F84 },
+	{ 0x0F86, 0x0F87 }, { 0x0F8D, 0x0F97 }, { 0x0F99, 0x0FBC }, { 0x0FC6, 0x0FC6 }, { 0x102D, 0x1030 },
+	{ 0x1032, 0x1037 }, { 0x1039, 0x103A }, { 0x103D, 0x103E }, { 0x1058, 0x1059 }, { 0x105E, 0x1060 },
+	{ 0x1071, 0x1074 }, { 0x1082, 0x1082 }, { 0x1085, 0x1086 }, { 0x108D, 0x108D }, { 0x109D, 0x109D },
+	{ 0x10FC, 0x10FC }, { 0x135D, 0x135F }, { 0x1712, 0x1714 }, { 0x1732, 0x1733 }, { 0x1752, 0x1753 },
+	{ 0x1772, 0x1773 }, { 0x17B4, 0x17B5 }, { 0x17B7, 0x17BD }, { 0x17C6, 0x17C6 }, { 0x17C9, 0x17D3 },
+	{ 0x17D7, 0x17D7 }, { 0x17DD, 0x17DD }, { 0x180B, 0x180F }, { 0x1843, 0x1843 }, { 0x1885, 0x1886 },
+	{ 0x18A9, 0x18A9 }, { 0x1920, 0x1922 }, { 0x1927, 0x1928 }, { 0x1932, 0x1932 }, { 0x1939, 0x193B },
+	{ 0x1A17, 0x1A18 }, { 0x1A1B, 0x1A1B }, { 0x1A56, 0x1A56 }, { 0x1A58, 0x1A5E }, { 0x1A60, 0x1A60 },
+	{ 0x1A62, 0x1A62 }, { 0x1A65, 0x1A6C }, { 0x1A73, 0x1A7C }, { 0x1A7F, 0x1A7F }, { 0x1AA7, 0x1AA7 },
+	{ 0x1AB0, 0x1ADD }, { 0x1AE0, 0x1AEB }, { 0x1B00, 0x1B03 }, { 0x1B34, 0x1B34 }, { 0x1B36, 0x1B3A },
+	{ 0x1B3C, 0x1B3C }, { 0x1B42, 0x1B42 }, { 0x1B6B, 0x1B73 }, { 0x1B80, 0x1B81 }, { 0x1BA2, 0x1BA5 },
+	{ 0x1BA8, 0x1BA9 }, { 0x1BAB, 0x1BAD }, { 0x1BE6, 0x1BE6 }, { 0x1BE8, 0x1BE9 }, { 0x1BED, 0x1BED },
+	{ 0x1BEF, 0x1BF1 }, { 0x1C2C, 0x1C33 }, { 0x1C36, 0x1C37 }, { 0x1C78, 0x1C7D }, { 0x1CD0, 0x1CD2 },
+	{ 0x1CD4, 0x1CE0 }, { 0x1CE2, 0x1CE8 }, { 0x1CED, 0x1CED }, { 0x1CF4, 0x1CF4 }, { 0x1CF8, 0x1CF9 },
+	{ 0x1D2C, 0x1D6A }, { 0x1D78, 0x1D78 }, { 0x1D9B, 0x1DFF }, { 0x1FBD, 0x1FBD }, { 0x1FBF, 0x1FC1 },
+	{ 0x1FCD, 0x1FCF }, { 0x1FDD, 0x1FDF }, { 0x1FED, 0x1FEF }, { 0x1FFD, 0x1FFE }, { 0x200B, 0x200F },
+	{ 0x2018, 0x2019 }, { 0x2024, 0x2024 }, { 0x2027, 0x2027 }, { 0x202A, 0x202E }, { 0x2060, 0x2064 },
+	{ 0x2066, 0x206F }, { 0x2071, 0x2071 }, { 0x207F, 0x207F }, { 0x2090, 0x209C }, { 0x20D0, 0x20F0 },
+	{ 0x2C7C, 0x2C7D }, { 0x2CEF, 0x2CF1 }, { 0x2D6F, 0x2D6F }, { 0x2D7F, 0x2D7F }, { 0x2DE0, 0x2DFF },
+	{ 0x2E2F, 0x2E2F }, { 0x3005, 0x3005 }, { 0x302A, 0x302D }, { 0x3031, 0x3035 }, { 0x303B, 0x303B },
+	{ 0x3099, 0x309E }, { 0x30FC, 0x30FE }, { 0xA015, 0xA015 }, { 0xA4F8, 0xA4FD }, { 0xA60C, 0xA60C },
+	{ 0xA66F, 0xA672 }, { 0xA674, 0xA67D }, { 0xA67F, 0xA67F }, { 0xA69C, 0xA69F }, { 0xA6F0, 0xA6F1 },
+	{ 0xA700, 0xA721 }, { 0xA770, 0xA770 }, { 0xA788, 0xA78A }, { 0xA7F1, 0xA7F4 }, { 0xA7F8, 0xA7F9 },
+	{ 0xA802, 0xA802 }, { 0xA806, 0xA806 }, { 0xA80B, 0xA80B }, { 0xA825, 0xA826 }, { 0xA82C, 0xA82C },
+	{ 0xA8C4, 0xA8C5 }, { 0xA8E0, 0xA8F1 }, { 0xA8FF, 0xA8FF }, { 0xA926, 0xA92D }, { 0xA947, 0xA951 },
+	{ 0xA980, 0xA982 }, { 0xA9B3, 0xA9B3 }, { 0xA9B6, 0xA9B9 }, { 0xA9BC, 0xA9BD }, { 0xA9CF, 0xA9CF },
+	{ 0xA9E5, 0xA9E6 }, { 0xAA29, 0xAA2E }, { 0xAA31, 0xAA32 }, { 0xAA35, 0xAA36 }, { 0xAA43, 0xAA43 },
+	{ 0xAA4C, 0xAA4C }, { 0xAA70, 0xAA70 }, { 0xAA7C, 0xAA7C }, { 0xAAB0, 0xAAB0 }, { 0xAAB2, 0xAAB4 },
+	{ 0xAAB7, 0xAAB8 }, { 0xAABE, 0xAABF }, { 0xAAC1, 0xAAC1 }, { 0xAADD, 0xAADD }, { 0xAAEC, 0xAAED },
+	{ 0xAAF3, 0xAAF4 }, { 0xAAF6, 0xAAF6 }, { 0xAB5B, 0xAB5F }, { 0xAB69, 0xAB6B }, { 0xABE5, 0xABE5 },
+	{ 0xABE8, 0xABE8 }, { 0xABED, 0xABED }, { 0xFB1E, 0xFB1E }, { 0xFBB2, 0xFBC2 }, { 0xFE00, 0xFE0F },
+	{ 0xFE13, 0xFE13 }, { 0xFE20, 0xFE2F }, { 0xFE52, 0xFE52 }, { 0xFE55, 0xFE55 }, { 0xFEFF, 0xFEFF },
+	{ 0xFF07, 0xFF07 }, { 0xFF0E, 0xFF0E }, { 0xFF1A, 0xFF1A }, { 0xFF3E, 0xFF3E }, { 0xFF40, 0xFF40 },
+	{ 0xFF70, 0xFF70 }, { 0xFF9E, 0xFF9F }, { 0xFFE3, 0xFFE3 }, { 0xFFF9, 0xFFFB }, { 0x101FD, 0x101FD },
+	{ 0x102E0, 0x102E0 }, { 0x10376, 0x1037A }, { 0x10780, 0x10785 }, { 0x10787, 0x107B0 }, { 0x107B2, 0x107BA },
+	{ 0x10A01, 0x10A03 }, { 0x10A05, 0x10A06 }, { 0x10A0C, 0x10A0F }, { 0x10A38, 0x10A3A }, { 0x10A3F, 0x10A3F },
+	{ 0x10AE5, 0x10AE6 }, { 0x10D24, 0x10D27 }, { 0x10D4E, 0x10D4E }, { 0x10D69, 0x10D6D }, { 0x10D6F, 0x10D6F },
+	{ 0x10EAB, 0x10EAC }, { 0x10EC5, 0x10EC5 }, { 0x10EFA, 0x10EFF }, { 0x10F46, 0x10F50 }, { 0x10F82, 0x10F85 },
+	{ 0x11001, 0x11001 }, { 0x11038, 0x11046 }, { 0x11070, 0x11070 }, { 0x11073, 0x11074 }, { 0x1107F, 0x11081 },
+	{ 0x110B3, 0x110B6 }, { 0x110B9, 0x110BA }, { 0x110BD, 0x110BD }, { 0x110C2, 0x110C2 }, { 0x110CD, 0x110CD },
+	{ 0x11100, 0x11102 }, { 0x11127, 0x1112B }, { 0x1112D, 0x11134 }, { 0x11173, 0x11173 }, { 0x11180, 0x11181 },
+	{ 0x111B6, 0x111BE }, { 0x111C9, 0x111CC }, { 0x111CF, 0x111CF }, { 0x1122F, 0x11231 }, { 0x11234, 0x11234 },
+	{ 0x11236, 0x11237 }, { 0x1123E, 0x1123E }, { 0x11241, 0x11241 }, { 0x112DF, 0x112DF }, { 0x112E3, 0x112EA },
+	{ 0x11300, 0x11301 }, { 0x1133B, 0x1133C }, { 0x11340, 0x11340 }, { 0x11366, 0x1136C }, { 0x11370, 0x11374 },
+	{ 0x113BB, 0x113C0 }, { 0x113CE, 0x113CE }, { 0x113D0, 0x113D0 }, { 0x113D2, 0x113D2 }, { 0x113E1, 0x113E2 },
+	{ 0x11438, 0x1143F }, { 0x11442, 0x11444 }, { 0x11446, 0x11446 }, { 0x1145E, 0x1145E }, { 0x114B3, 0x114B8 },
+	{ 0x114BA, 0x114BA }, { 0x114BF, 0x114C0 }, { 0x114C2, 0x114C3 }, { 0x115B2, 0x115B5 }, { 0x115BC, 0x115BD },
+	{ 0x115BF, 0x115C0 }, { 0x115DC, 0x115DD }, { 0x11633, 0x1163A }, { 0x1163D, 0x1163D }, { 0x1163F, 0x11640 },
+	{ 0x116AB, 0x116AB }, { 0x116AD, 0x116AD }, { 0x116B0, 0x116B5 }, { 0x116B7, 0x116B7 }, { 0x1171D, 0x1171D },
+	{ 0x1171F, 0x1171F }, { 0x11722, 0x11725 }, { 0x11727, 0x1172B }, { 0x1182F, 0x11837 }, { 0x11839, 0x1183A },
+	{ 0x1193B, 0x1193C }, { 0x1193E, 0x1193E }, { 0x11943, 0x11943 }, { 0x119D4, 0x119D7 }, { 0x119DA, 0x119DB },
+	{ 0x119E0, 0x119E0 }, { 0x11A01, 0x11A0A }, { 0x11A33, 0x11A38 }, { 0x11A3B, 0x11A3E }, { 0x11A47, 0x11A47 },
+	{ 0x11A51, 0x11A56 }, { 0x11A59, 0x11A5B }, { 0x11A8A, 0x11A96 }, { 0x11A98, 0x11A99 }, { 0x11B60, 0x11B60 },
+	{ 0x11B62, 0x11B64 }, { 0x11B66, 0x11B66 }, { 0x11C30, 0x11C36 }, { 0x11C38, 0x11C3D }, { 0x11C3F, 0x11C3F },
+	{ 0x11C92, 0x11CA7 }, { 0x11CAA, 0x11CB0 }, { 0x11CB2, 0x11CB3 }, { 0x11CB5, 0x11CB6 }, { 0x11D31, 0x11D36 },
+	{ 0x11D3A, 0x11D3A }, { 0x11D3C, 0x11D3D }, { 0x11D3F, 0x11D45 }, { 0x11D47, 0x11D47 }, { 0x11D90, 0x11D91 },
+	{ 0x11D95, 0x11D95 }, { 0x11D97, 0x11D97 }, { 0x11DD9, 0x11DD9 }, { 0x11EF3, 0x11EF4 }, { 0x11F00, 0x11F01 },
+	{ 0x11F36, 0x11F3A }, { 0x11F40, 0x11F40 }, { 0x11F42, 0x11F42 }, { 0x11F5A, 0x11F5A }, { 0x13430, 0x13440 },
+	{ 0x13447, 0x13455 }, { 0x1611E, 0x16129 }, { 0x1612D, 0x1612F }, { 0x16AF0, 0x16AF4 }, { 0x16B30, 0x16B36 },
+	{ 0x16B40, 0x16B43 }, { 0x16D40, 0x16D42 }, { 0x16D6B, 0x16D6C }, { 0x16F4F, 0x16F4F }, { 0x16F8F, 0x16F9F },
+	{ 0x16FE0, 0x16FE1 }, { 0x16FE3, 0x16FE4 }, { 0x16FF2, 0x16FF3 }, { 0x1AFF0, 0x1AFF3 }, { 0x1AFF5, 0x1AFFB },
+	{ 0x1AFFD, 0x1AFFE }, { 0x1BC9D, 0x1BC9E }, { 0x1BCA0, 0x1BCA3 }, { 0x1CF00, 0x1CF2D }, { 0x1CF30, 0x1CF46 },
+	{ 0x1D167, 0x1D169 }, { 0x1D173, 0x1D182 }, { 0x1D185, 0x1D18B }, { 0x1D1AA, 0x1D1AD }, { 0x1D242, 0x1D244 },
+	{ 0x1DA00, 0x1DA36 }, { 0x1DA3B, 0x1DA6C }, { 0x1DA75, 0x1DA75 }, { 0x1DA84, 0x1DA84 }, { 0x1DA9B, 0x1DA9F },
+	{ 0x1DAA1, 0x1DAAF }, { 0x1E000, 0x1E006 }, { 0x1E008, 0x1E018 }, { 0x1E01B, 0x1E021 }, { 0x1E023, 0x1E024 },
+	{ 0x1E026, 0x1E02A }, { 0x1E030, 0x1E06D }, { 0x1E08F, 0x1E08F }, { 0x1E130, 0x1E13D }, { 0x1E2AE, 0x1E2AE },
+	{ 0x1E2EC, 0x1E2EF }, { 0x1E4EB, 0x1E4EF }, { 0x1E5EE, 0x1E5EF }, { 0x1E6E3, 0x1E6E3 }, { 0x1E6E6, 0x1E6E6 },
+	{ 0x1E6EE, 0x1E6EF }, { 0x1E6F5, 0x1E6F5 }, { 0x1E6FF, 0x1E6FF }, { 0x1E8D0, 0x1E8D6 }, { 0x1E944, 0x1E94B },
+	{ 0x1F3FB, 0x1F3FF }, { 0xE0001, 0xE0001 }, { 0xE0020, 0xE007F }, { 0xE0100, 0xE01EF },
+};
+
+#define RANGES(ranges) ranges, sizeof(ranges) / sizeof(*ranges)
+
+static bool in_ranges(const CharRange *ranges, size_t count, uint32_t c) {
+	size_t lo = 0, hi = count;
+	while (lo < hi) {
+		size_t mid = (lo + hi) / 2;
+		if (c < ranges[mid].first) hi = mid;
+		else if (c > ranges[mid].last) lo = mid + 1;
+		else return true;
+	}
+	return false;
+}
+
+/// the lowercase of `c`, which is a single character for every character except U+0130 and sigma
+static uint32_t lowercase_char(uint32_t c) {
+	if (c < 0x80) return c >= 'A' && c <= 'Z' ? c + 32 : c;
+	size_t lo = 0, hi = sizeof(lowercase_ranges) / sizeof(*lowercase_ranges);
+	while (lo < hi) {
+		size_t mid = (lo + hi) / 2;
+		const LowercaseRange *r = &lowercase_ranges[mid];
+		if (c < r->first) hi = mid;
+		else if (c > r->last) lo = mid + 1;
+		else return (c - r->first) % r->step == 0 ? c + r->delta : c;
+	}
+	return c;
+}
+
+/// decodes the UTF-8 character at `*s` and moves past it, invalid bytes are read as themselves
+static uint32_t next_char(const char **s) {
+	const unsigned char *u = (const unsigned char *)*s;
+	int len = u[0] < 0xC0 ? 1 : u[0] < 0xE0 ? 2 : u[0] < 0xF0 ? 3 : 4;
+	uint32_t c = len == 1 ? u[0] : u[0] & (0x3F >> (len - 1));
+	for (int i = 1; i < len; i++) {
+		if ((u[i] & 0xC0) != 0x80) {
+			*s += 1;
+			return u[0];
+		}
+		c = c << 6 | (u[i] & 0x3F);
+	}
+	*s += len;
+	return c;
+}
+
+/// wether `c` decides the case of a sigma next to it: cased characters do, case-ignorable ones are skipped
+static bool skip_case_ignorable(uint32_t c, bool *cased) {
+	if (in_ranges(RANGES(case_ignorable_ranges), c)) return true;
+	*cased = in_ranges(RANGES(cased_ranges), c);
+	return false;
+}
+
+/// wether a sigma between `before` (the start of the string up to `at`) and `after` ends a word,
+/// which lowercases it to ς instead of σ
+static bool is_final_sigma(const char *before, const char *at, const char *after) {
+	bool cased_before = false, cased_after = false;
+	while (at > before) {
+		const char *start = at - 1;
+		while (start > before && (*(const unsigned char *)start & 0xC0) == 0x80) start--;
+		at = start;
+		if (!skip_case_ignorable(next_char(&start), &cased_before)) break;
+	}
+	while (*after) {
+		if (!skip_case_ignorable(next_char(&after), &cased_after)) break;
+	}
+	return cased_before && !cased_after;
+}
+
+/// reads the lowercase of a string one character at a time
+typedef struct {
+	const char *start;
+	const char *s;
+	/// the character left from the lowercase of İ, the only one made of two characters
+	uint32_t pending;
+} LowercaseReader;
+
+/// the next character of the lowercase string, 0 at its end
+static uint32_t next_lowercase(LowercaseReader *r) {
+	if (r->pending) {
+		uint32_t c = r->pending;
+		r->pending = 0;
+		return c;
+	}
+	if (*r->s == '\0') return 0;
+	const char *at = r->s;
+	uint32_t c = next_char(&r->s);
+	if (c == 0x130) {
+		// i followed by a combining dot above
+		r->pending = 0x307;
+		return 'i';
+	}
+	if (c == 0x3A3) return is_final_sigma(r->start, at, r->s) ? 0x3C2 : 0x3C3;
+	return lowercase_char(c);
+}
+
+/// the position of `c` in the order of UTF-16 code units, which javascript compares strings in:
+/// characters outside the basic multilingual plane start with a surrogate, so they come before U+E000
+static uint32_t utf16_order(uint32_t c) {
+	return c >= 0xE000 && c <= 0xFFFF ? c + 0x110000 : c;
+}
+
+int strcmp_lowercase(const char *a, const char *b) {
+	LowercaseReader ra = { a, a, 0 }, rb = { b, b, 0 };
+	for (;;) {
+		uint32_t ca = utf16_order(next_lowercase(&ra));
+		uint32_t cb = utf16_order(next_lowercase(&rb));
+		if (ca != cb || ca == 0) return (ca > cb) - (ca < cb);
+	}
+}
+
+double string_to_number(const char *s) {
+	size_t space;
+	while ((space = whitespace_length(s))) s += space;
+	// the end of the last character that isn't whitespace
+	const char *end = s;
+	for (const char *c = s; *c;) {
+		space = whitespace_length(c);
+		if (space) c += space;
+		else end = ++c;
+	}
+	size_t len = end - s;
+	if (len == 0) return 0;
+
+	if (len > 2 && s[0] == '0') {
+		int radix = 0;
+		if (s[1] == 'x' || s[1] == 'X') radix = 16;
+		else if (s[1] == 'o' || s[1] == 'O') radix = 8;
+		else if (s[1] == 'b' || s[1] == 'B') radix = 2;
+		if (radix != 0) {
+			double n = 0;
+			for (size_t i = 2; i < len; i++) {
+				int digit = -1;
+				if (s[i] >= '0' && s[i] <= '9') digit = s[i] - '0';
+				else if (s[i] >= 'a' && s[i] <= 'f') digit = s[i] - 'a' + 10;
+				else if (s[i] >= 'A' && s[i] <= 'F') digit = s[i] - 'A' + 10;
+				if (digit < 0 || digit >= radix) return NAN;
+				n = n * radix + digit;
+			}
+			return n;
+		}
+	}
+
+	if (len == 8 && strncmp(s, "Infinity", 8) == 0) return INFINITY;
+	if (len == 9 && strncmp(s, "+Infinity", 9) == 0) return INFINITY;
+	if (len == 9 && strncmp(s, "-Infinity", 9) == 0) return -INFINITY;
+
+	// strtod also parses hexadecimal, "inf" and "nan", which javascript doesn't
+	for (size_t i = 0; i < len; i++) {
+		char c = s[i];
+		if (!((c >= '0' && c <= '9') || c == '+' || c == '-' || c == '.' || c == 'e' || c == 'E')) return NAN;
+	}
+	char *parsed;
+	double n = strtod(s, &parsed);
+	if (parsed != end) return NAN;
+	return n;
+}
+
+void number_to_string(double n, char *out) {
+	if (isnan(n)) {
+		strcpy(out, "NaN");
+		return;
+	}
+	if (isinf(n)) {
+		strcpy(out, n > 0 ? "Infinity" : "-Infinity");
+		return;
+	}
+	if (n == 0) {
+		// also for -0
+		strcpy(out, "0");
+		return;
+	}
+
+	// the shortest digits giving back the number, 17 digits always do
+	char scientific[NUMBER_STRING_SIZE];
+	for (int precision = 0; precision <= 16; precision++) {
+		snprintf(scientific, sizeof scientific, "%.*e", precision, fabs(n));
+		if (strtod(scientific, NULL) == fabs(n)) break;
+	}
+	char digits[NUMBER_STRING_SIZE];
+	int len = 0;
+	char *c = scientific;
+	for (; *c != 'e'; c++) {
+		if (*c != '.') digits[len++] = *c;
+	}
+	while (len > 1 && digits[len - 1] == '0') len--;
+	digits[len] = '\0';
+	int exponent = atoi(c + 1);
+	// position of the decimal point, after `point` digits
+	int point = exponent + 1;
+
+	if (n < 0) *out++ = '-';
+	if (len <= point && point <= 21) {
+		// an integer, padded with zeros
+		memcpy(out, digits, len);
+		memset(out + len, '0', point - len);
+		out[point] = '\0';
+	} else if (0 < point && point <= 21) {
+		sprintf(out, "%.*s.%s", point, digits, digits + point);
+	} else if (-6 < point && point <= 0) {
+		// below 1, with zeros after the decimal point
+		memcpy(out, "0.", 2);
+		memset(out + 2, '0', -point);
+		strcpy(out + 2 - point, digits);
+	} else if (len == 1) {
+		sprintf(out, "%se%+d", digits, exponent);
+	} else {
+		sprintf(out, "%c.%se%+d", digits[0], digits + 1, exponent);
 	}
-	return 0.0;
 }
 
-static bool value_as_bool(Value v) {
+double value_as_number(Value v) {
+	double n = 0;
+	if (v.type == VALUE_NUM) n = v.n;
+	else if (v.type == VALUE_BOOL) n = v.b;
+	else if (v.type == VALUE_STRING) n = string_to_number(v.s.ptr);
+	return isnan(n) ? 0 : n;
+}
+
+bool value_as_bool(Value v) {
 	if (v.type == VALUE_BOOL) return v.b;
-	else if (v.type == VALUE_NUM) return v.n != 0;
+	else if (v.type == VALUE_NUM) return v.n != 0 && !isnan(v.n);
 	else if (v.type == VALUE_STRING) {
-		return strcmp(v.s.ptr, "true") == 0;
+		const char *s = v.s.ptr;
+		if (s[0] == '\0' || strcmp(s, "0") == 0) return false;
+		return strcmp_lowercase(s, "false") != 0;
 	}
-	return false;
+	// colors are non-empty strings in scratch
+	return true;
+}
+
+/// the value as a string without allocating: `buf` (of `NUMBER_STRING_SIZE` bytes) holds numbers and colors
+static const char *value_as_cstr(Value v, char *buf) {
+	switch (v.type) {
+		case VALUE_NUM:
+			number_to_string(v.n, buf);
+			return buf;
+		case VALUE_COLOR:
+			snprintf(buf, NUMBER_STRING_SIZE, "#%02x%02x%02x", v.c.r, v.c.g, v.c.b);
+			return buf;
+		case VALUE_STRING:
+			return v.s.ptr;
+		case VALUE_BOOL:
+			return v.b ? "true" : "false";
+	}
+	return "";
+}
+
+/// `Number(v)` in javascript, which is NaN for values that aren't numbers
+static double value_js_number(Value v) {
+	if (v.type == VALUE_NUM) return v.n;
+	else if (v.type == VALUE_BOOL) return v.b;
+	else if (v.type == VALUE_STRING) return string_to_number(v.s.ptr);
+	return NAN;
+}
+
+/// wether the value is a string of whitespace, which scratch doesn't compare as 0
+static bool value_is_blank(Value v) {
+	if (v.type != VALUE_STRING) return false;
+	const char *s = v.s.ptr;
+	size_t space;
+	while ((space = whitespace_length(s))) s += space;
+	return *s == '\0';
+}
+
+int value_compare(Value a, Value b) {
+	double n1 = value_is_blank(a) ? NAN : value_js_number(a);
+	double n2 = value_is_blank(b) ? NAN : value_js_number(b);
+	if (isnan(n1) || isnan(n2)) {
+		char buf1[NUMBER_STRING_SIZE], buf2[NUMBER_STRING_SIZE];
+		return strcmp_lowercase(value_as_cstr(a, buf1), value_as_cstr(b, buf2));
+	}
+	// infinities are equal to themselves
+	return (n1 > n2) - (n1 < n2);
 }
 
 void convert_to_number(Value *v) {
-	float n = value_as_number(*v);
+	double n = value_as_number(*v);
 	if (v->type == VALUE_STRING) free_rcstr(v->s);
 	v->n = n;
 	v->type = VALUE_NUM;
@@ -40,32 +535,275 @@ void convert_to_rcstr(Value *v) {
 	rcstr output;
 	if (v->type == VALUE_STRING) return;
 	else {
-		output = alloc_rcstr(128);
-		if (v->type == VALUE_NUM) snprintf(output.ptr, 128, "%f", v->n);
-		else if (v->type == VALUE_COLOR) snprintf(output.ptr, 128, "#%02X%02X%02X", v->c.r, v->c.g, v->c.b);
+		output = alloc_rcstr(NUMBER_STRING_SIZE);
+		if (v->type == VALUE_NUM) number_to_string(v->n, output.ptr);
+		else if (v->type == VALUE_COLOR) snprintf(output.ptr, NUMBER_STRING_SIZE, "#%02x%02x%02x", v->c.r, v->c.g, v->c.b);
 		else if (v->type == VALUE_BOOL) strcpy(output.ptr, v->b ? "true" : "false");
 		v->s = output;
 		v->type = VALUE_STRING;
 	}
 }
 
-void draw_actor(ActorState *a) {
+#define LIST_INDEX_INVALID 0
+#define LIST_INDEX_ALL -1
+
+/// converts a scratch list index into an index starting at 1,
+/// `LIST_INDEX_INVALID` if it is out of bounds, or `LIST_INDEX_ALL` if `accept_all` is set and the index is "all"
+static int to_list_index(Value index, int length, bool accept_all) {
+	if (index.type == VALUE_STRING) {
+		if (strcmp(index.s.ptr, "all") == 0) return accept_all ? LIST_INDEX_ALL : LIST_INDEX_INVALID;
+		if (strcmp(index.s.ptr, "last") == 0) return length > 0 ? length : LIST_INDEX_INVALID;
+		if (strcmp(index.s.ptr, "random") == 0 || strcmp(index.s.ptr, "any") == 0) {
+			return length > 0 ? GetRandomValue(1, length) : LIST_INDEX_INVALID;
+		}
+	}
+
+	double n = floor(value_as_number(index));
+	if (n < 1 || n > length) return LIST_INDEX_INVALID;
+	return (int)n;
+}
+
+static void list_reserve(ValueList *l, int len) {
+	if (len <= l->cap) return;
+	l->cap = l->cap == 0 ? 8 : l->cap * 2;
+	if (l->cap < len) l->cap = len;
+	l->items = realloc(l->items, l->cap * sizeof(Value));
+}
+
+void list_push(ValueList *l, Value v) {
+	list_reserve(l, l->len + 1);
+	l->items[l->len++] = copy_value(v);
+}
+
+void list_clear(ValueList *l) {
+	for (int i = 0; i < l->len; i++) free_value(l->items[i]);
+	l->len = 0;
+}
+
+void list_delete(ValueList *l, Value index) {
+	int i = to_list_index(index, l->len, true);
+	if (i == LIST_INDEX_ALL) {
+		list_clear(l);
+		return;
+	}
+	if (i == LIST_INDEX_INVALID) return;
+
+	free_value(l->items[i - 1]);
+	memmove(&l->items[i - 1], &l->items[i], (l->len - i) * sizeof(Value));
+	l->len--;
+}
+
+void list_insert(ValueList *l, Value index, Value v) {
+	// inserting right after the last item is allowed
+	int i = to_list_index(index, l->len + 1, false);
+	if (i == LIST_INDEX_INVALID) return;
+
+	list_reserve(l, l->len + 1);
+	memmove(&l->items[i], &l->items[i - 1], (l->len - i + 1) * sizeof(Value));
+	l->items[i - 1] = copy_value(v);
+	l->len++;
+}
+
+void list_replace(ValueList *l, Value index, Value v) {
+	int i = to_list_index(index, l->len, false);
+	if (i == LIST_INDEX_INVALID) return;
+
+	free_value(l->items[i - 1]);
+	l->items[i - 1] = copy_value(v);
+}
+
+Value list_item(ValueList *l, Value index) {
+	int i = to_list_index(index, l->len, false);
+	if (i == LIST_INDEX_INVALID) return (Value){ .type = VALUE_STRING, .s = create_rcstr("") };
+	return copy_value(l->items[i - 1]);
+}
+
+Value list_index_of(ValueList *l, Value v) {
+	for (int i = 0; i < l->len; i++) {
+		if (value_equal(l->items[i], v).b) return (Value){ .type = VALUE_NUM, .n = i + 1 };
+	}
+	return (Value){ .type = VALUE_NUM, .n = 0 };
+}
+
+Value list_length(ValueList *l) {
+	return (Value){ .type = VALUE_NUM, .n = l->len };
+}
+
+Value list_contains(ValueList *l, Value v) {
+	return (Value){ .type = VALUE_BOOL, .b = list_index_of(l, v).n != 0 };
+}
+
+Value list_join(ValueList *l) {
+	bool single_letters = true;
+	for (int i = 0; i < l->len; i++) {
+		if (l->items[i].type != VALUE_STRING || utf16_length(l->items[i].s.ptr) != 1) {
+			single_letters = false;
+			break;
+		}
+	}
+
+	// convert every item once, to know the length of the result
+	rcstr *items = malloc(l->len * sizeof(rcstr));
+	size_t len = 0;
+	for (int i = 0; i < l->len; i++) {
+		Value item = copy_value(l->items[i]);
+		convert_to_rcstr(&item);
+		items[i] = item.s;
+		len += strlen(item.s.ptr) + 1;
+	}
+
+	rcstr out = alloc_rcstr(len);
+	char *end = out.ptr;
+	for (int i = 0; i < l->len; i++) {
+		if (i > 0 && !single_letters) *end++ = ' ';
+		size_t item_len = strlen(items[i].ptr);
+		memcpy(end, items[i].ptr, item_len);
+		end += item_len;
+		free_rcstr(items[i]);
+	}
+	*end = '\0';
+	free(items);
+
+	return (Value){ .type = VALUE_STRING, .s = out };
+}
+
+ValueList list_copy(ValueList *l) {
+	ValueList copy = { 0 };
+	list_reserve(&copy, l->len);
+	for (int i = 0; i < l->len; i++) copy.items[i] = copy_value(l->items[i]);
+	copy.len = l->len;
+	return copy;
+}
+
+void list_free(ValueList *l) {
+	list_clear(l);
+	free(l->items);
+	*l = (ValueList) { 0 };
+}
+
+void *call_stack_push(CallStack *stack, int procedure, size_t state_size) {
+	if (stack->len == stack->cap) {
+		stack->cap = stack->cap == 0 ? 4 : stack->cap * 2;
+		stack->frames = realloc(stack->frames, stack->cap * sizeof(CallFrame));
+	}
+	void *state = calloc(1, state_size);
+	stack->frames[stack->len++] = (CallFrame) { .procedure = procedure, .state = state };
+	return state;
+}
+
+void call_stack_clear(CallStack *stack, void (*free_frame)(CallFrame *frame)) {
+	for (int i = 0; i < stack->len; i++) free_frame(&stack->frames[i]);
+	free(stack->frames);
+	*stack = (CallStack) { 0 };
+}
+
+bool call_stack_is_recursive(CallStack *stack) {
+	int top = stack->len - 1;
+	for (int i = top - 1; i >= 0 && i >= top - 5; i--) {
+		if (stack->frames[i].procedure == stack->frames[top].procedure) return true;
+	}
+	return false;
+}
+
+/// where and how an actor's costume is drawn on screen
+typedef struct {
+	Rectangle source;
+	Rectangle dest;
+	Vector2 origin;
+	float rotation;
+} ActorPlacement;
+
+static ActorPlacement place_actor(ActorState *a) {
     Sprite *sprite = &a->sprites[a->sprite_index];
-    Rectangle source = { .x = 0, .y = 0, .width = sprite->texture.width, .height = sprite->texture.height };
-    Rectangle dest = { .x = a->x + 240, .y = -a->y + 180, .width = source.width * a->size / 100, .height = source.height * a->size / 100 };
-    Vector2 origin = { sprite->rotation_center_x*source.width/100, sprite->rotation_center_y*source.height/100 };
+	ActorPlacement p;
+    p.source = (Rectangle) { .x = 0, .y = 0, .width = sprite->texture.width, .height = sprite->texture.height };
+    p.dest = (Rectangle) { .x = a->x + 240, .y = -a->y + 180, .width = p.source.width * a->size / 100, .height = p.source.height * a->size / 100 };
+    p.origin = (Vector2) { sprite->rotation_center_x*p.source.width/100, sprite->rotation_center_y*p.source.height/100 };
 
 	// scratch direction is in degrees
 	// top is 0
 	// goes clockwise
 
-	// raylib direction is in degrees
+	// raylib rotation is in degrees
 	// right is 0
-	// goes counter-clockwise
+	// goes clockwise (the y axis points down)
+
+	// raylib_rot = scratch_dir - 90
+	switch (a->rotation_style) {
+		case ROTATION_ALL_AROUND:
+			p.rotation = a->direction - 90;
+			break;
+		case ROTATION_LEFT_RIGHT:
+			p.rotation = 0;
+			// a negative width flips the texture
+			if (a->direction < 0) p.source.width = -p.source.width;
+			break;
+		case ROTATION_DONT_ROTATE:
+			p.rotation = 0;
+			break;
+	}
+	return p;
+}
+
+/// bounding box of an actor in stage coordinates (`y` is the bottom of the box)
+static Rectangle actor_bounds(ActorState *a) {
+	ActorPlacement p = place_actor(a);
+	float rotation = p.rotation * PI / 180.0;
+	float c = cosf(rotation), s = sinf(rotation);
 
-	// raylib_dir = -scratch_dir + 90
+	float corners[4][2] = {
+		{ -p.origin.x, -p.origin.y },
+		{ p.dest.width - p.origin.x, -p.origin.y },
+		{ -p.origin.x, p.dest.height - p.origin.y },
+		{ p.dest.width - p.origin.x, p.dest.height - p.origin.y },
+	};
+	float left = INFINITY, right = -INFINITY, top = -INFINITY, bottom = INFINITY;
+	for (int i = 0; i < 4; i++) {
+		// rotate around the origin on screen, then go back to stage coordinates
+		float x = p.dest.x + corners[i][0]*c - corners[i][1]*s - 240;
+		float y = 180 - (p.dest.y + corners[i][0]*s + corners[i][1]*c);
+		left = fminf(left, x);
+		right = fmaxf(right, x);
+		bottom = fminf(bottom, y);
+		top = fmaxf(top, y);
+	}
+	return (Rectangle) { .x = left, .y = bottom, .width = right - left, .height = top - bottom };
+}
+
+void bounce_off_edge(ActorState *a) {
+	Rectangle bounds = actor_bounds(a);
+	float dist_left = fmaxf(0, 240 + bounds.x);
+	float dist_right = fmaxf(0, 240 - (bounds.x + bounds.width));
+	float dist_top = fmaxf(0, 180 - (bounds.y + bounds.height));
+	float dist_bottom = fmaxf(0, 180 + bounds.y);
 
-    DrawTexturePro(sprite->texture, source, dest, origin, -a->direction + 90, WHITE);
+	float nearest = fminf(fminf(dist_left, dist_right), fminf(dist_top, dist_bottom));
+	if (nearest > 0) return; // not touching any edge
+
+	// same as scratch: point away from the nearest edge
+	float radians = (90 - a->direction) * PI / 180.0;
+	float dx = cosf(radians);
+	float dy = -sinf(radians);
+	if (nearest == dist_left) dx = fmaxf(0.2, fabsf(dx));
+	else if (nearest == dist_top) dy = fmaxf(0.2, fabsf(dy));
+	else if (nearest == dist_right) dx = -fmaxf(0.2, fabsf(dx));
+	else dy = -fmaxf(0.2, fabsf(dy));
+	a->direction = wrap_direction(atan2f(dy, dx) * 180.0 / PI + 90);
+
+	// move back inside the stage
+	bounds = actor_bounds(a);
+	if (bounds.x < -240) a->x += -240 - bounds.x;
+	else if (bounds.x + bounds.width > 240) a->x -= bounds.x + bounds.width - 240;
+	if (bounds.y < -180) a->y += -180 - bounds.y;
+	else if (bounds.y + bounds.height > 180) a->y -= bounds.y + bounds.height - 180;
+}
+
+void draw_actor(ActorState *a) {
+    Sprite *sprite = &a->sprites[a->sprite_index];
+	ActorPlacement p = place_actor(a);
+	Rectangle dest = p.dest;
+
+    DrawTexturePro(sprite->texture, p.source, dest, p.origin, p.rotation, WHITE);
 
 	// readjust `dest` based on origin (so it lines up with the texture drawn above)
 	dest.x -= sprite->rotation_center_x*dest.width/100.0;
@@ -88,6 +826,29 @@ void draw_actor(ActorState *a) {
 	}
 }
 
+/// draws the stage, every actor and the start button
+static void draw_frame(GlobalState *g) {
+	BeginDrawing();
+
+	ClearBackground(WHITE);
+	render_global(g);
+
+	DrawCircle(15, 15, 10, GREEN);
+
+	EndDrawing();
+}
+
+void warp_check(GlobalState *g) {
+	if (GetTime() < g->warp_deadline) return;
+
+	draw_frame(g);
+	g->warp_deadline = GetTime() + WARP_TIMEOUT;
+}
+
+bool should_yield(GlobalState *g) {
+	return g->redraw_requested || GetTime() >= g->work_deadline;
+}
+
 int main() {
     InitWindow(480, 360, "Hello, world!");
     SetTargetFPS(60);
@@ -107,14 +868,6 @@ int main() {
 		}
 
 		run_global(&g);
-
-        BeginDrawing();
-
-        ClearBackground(WHITE);
-		render_global(&g);
-
-		DrawCircle(15, 15, 10, GREEN);
-
-        EndDrawing();
+		draw_frame(&g);
     }
 }
@@ -1,9 +1,11 @@
 #pragma once
 
 #include <stdlib.h>
+#include <limits.h>
 #include <inttypes.h>
 #include <stdbool.h>
 #include <string.h>
+#include <math.h>
 
 #include <raylib.h>
 
@@ -22,11 +24,18 @@ typedef struct {
     Texture texture;
 } Sprite;
 
+typedef enum {
+	ROTATION_ALL_AROUND,
+	ROTATION_LEFT_RIGHT,
+	ROTATION_DONT_ROTATE
+} RotationStyle;
+
 typedef struct {
     float x;
     float y;
     float size;
     float direction;
+	RotationStyle rotation_style;
 	bool visible;
     int sprite_index;
 	Sprite *sprites;
@@ -35,8 +44,53 @@ typedef struct {
 	rcstr saying;
 	/// time at which the text should be removed
 	float say_end;
+
+	bool is_clone;
+	/// set by "delete this clone", the actor is freed at the end of the frame
+	bool deleted;
 } ActorState;
 
+/// scratch refuses to create clones past this limit
+#define MAX_CLONES 300
+
+/// a call to a custom block
+typedef struct {
+	/// index of the procedure in its target
+	int procedure;
+	/// state of the procedure (a `{Target}Proc{index}State`)
+	void *state;
+} CallFrame;
+
+/// the custom blocks being run by a script, the last one is the one running
+typedef struct {
+	int len;
+	int cap;
+	CallFrame *frames;
+	/// set when a procedure deletes its clone, or stops all scripts: the whole stack is dropped.
+	/// "stop this script" inside a procedure only returns from it, and leaves this alone
+	bool stopped;
+} CallStack;
+
+/// adds a frame with a zeroed state of `state_size` bytes, and returns the state
+void *call_stack_push(CallStack *stack, int procedure, size_t state_size);
+/// frees every frame with `free_frame`, then the stack itself
+void call_stack_clear(CallStack *stack, void (*free_frame)(CallFrame *frame));
+/// wether the procedure on top of the stack is also in one of the 5 frames below it.
+/// Like scratch, scripts yield on recursive calls, so recursion can be used as a loop.
+bool call_stack_is_recursive(CallStack *stack);
+
+/// like scratch, warp procedures redraw the screen when they run for longer than this (in seconds)
+#define WARP_TIMEOUT 0.5
+/// like scratch, loops stop running for the frame after this time (in seconds), even if nothing needs to be redrawn
+#define WORK_TIME (0.75/60)
+
+/// generated in output.h
+struct GlobalState;
+/// called on every loop iteration of a warp procedure, redraws the screen once the procedure timed out
+void warp_check(struct GlobalState *g);
+/// called at the end of every loop iteration: scripts keep running until something needs to be redrawn
+bool should_yield(struct GlobalState *g);
+
 enum ValueType {
     VALUE_NUM,
     VALUE_COLOR,
@@ -53,7 +107,7 @@ typedef struct {
 typedef struct {
     enum ValueType type;
     union {
-        float n;
+        double n;
         ValueColor c;
         rcstr s;
 		bool b;
@@ -82,24 +136,81 @@ static rcstr create_rcstr(const char *s) {
 	return rcs;
 }
 
+/// reference count of the strings that are never freed, like the literals of the project
+#define RC_IMMORTAL INT_MAX
+/// a reference count of `RC_IMMORTAL`, shared by the immortal strings (which have no `buf`)
+extern int immortal_rc;
+
 static rcstr copy_rcstr(rcstr s) {
-	*s.rc += 1;
+	if (*s.rc != RC_IMMORTAL) *s.rc += 1;
 	return s;
 }
 
 /// removes one from an rcstr's reference count
-/// free's it if it hit zero
+/// free's it if it hit zero, immortal strings are never freed
 static void free_rcstr(rcstr s) {
+	if (*s.rc == RC_IMMORTAL) return;
 	*s.rc -= 1;
 	if (*s.rc <= 0) {
 		free(s.buf);
 	}
 }
 
+/// javascript's `Number(s)`: surrounding whitespace is ignored, the empty string is 0,
+/// "0x", "0o" and "0b" prefix integers in base 16, 8 and 2, and strings that aren't numbers are NaN
+double string_to_number(const char *s);
+/// room needed by `number_to_string`, including the null terminator
+#define NUMBER_STRING_SIZE 32
+/// writes `n` like javascript's `String(n)`, with the shortest digits giving back `n`
+/// and an exponent for numbers below 1e-6 or from 1e21 (like "1e+21")
+void number_to_string(double n, char *out);
+
+/// the value as a number, following scratch's rules: values that aren't numbers are 0
+double value_as_number(Value v);
+/// the value as a boolean, following scratch's rules:
+/// every string is true, except for the empty string, "0" and "false" (in any case)
+bool value_as_bool(Value v);
+/// compares strings like `strcmp`, after lowercasing them like javascript's `toLowerCase`,
+/// in the order javascript compares strings in (by UTF-16 code units)
+int strcmp_lowercase(const char *a, const char *b);
+/// compares values like scratch: as numbers if both are numbers, and as case-insensitive strings otherwise.
+/// Returns a negative number, 0 or a positive number, like `strcmp`.
+/// Strings of whitespace aren't numbers.
+int value_compare(Value a, Value b);
+
 void convert_to_number(Value *v);
 void convert_to_bool(Value *v);
 void convert_to_rcstr(Value *v);
 
+/// a growable array of values, holding a reference to each of its items
+typedef struct {
+	int len;
+	int cap;
+	Value *items;
+} ValueList;
+
+// list operations
+// indices are scratch list indices: numbers starting at 1, "last", "random" (or "any"), and "all" for `list_delete`
+// values given to these functions are borrowed, and copied if the list needs to keep them
+
+void list_push(ValueList *l, Value v);
+void list_clear(ValueList *l);
+void list_delete(ValueList *l, Value index);
+void list_insert(ValueList *l, Value index, Value v);
+void list_replace(ValueList *l, Value index, Value v);
+/// returns a new reference to the item, or an empty string if the index is invalid
+Value list_item(ValueList *l, Value index);
+/// returns the index of the first item equal to `v`, or 0
+Value list_index_of(ValueList *l, Value v);
+Value list_length(ValueList *l);
+Value list_contains(ValueList *l, Value v);
+/// joins the items of the list with spaces, or with nothing if every item is a single character
+Value list_join(ValueList *l);
+/// returns a new list holding the same items
+ValueList list_copy(ValueList *l);
+/// frees the items and the buffer of the list
+void list_free(ValueList *l);
+
 static inline Value copy_value(Value v) {
 	if (v.type == VALUE_STRING) {
 		v.s = copy_rcstr(v.s);
@@ -113,6 +224,13 @@ static inline void free_value(Value v) {
 	}
 }
 
+/// replaces the value in `dst` with a copy of `v`, and frees the value it held
+static inline void set_value(Value *dst, Value v) {
+	Value old = *dst;
+	*dst = copy_value(v);
+	free_value(old);
+}
+
 // define operations on values
 #define M_VALUE_ARITHMETIC_OP(opname, op) \
 	static inline Value value_##opname(Value a, Value b) { \
@@ -150,22 +268,7 @@ static inline Value value_not(Value v) {
 
 #define M_VALUE_COMPARISON_OP(opname, op) \
 	static inline Value value_##opname(Value a, Value b) { \
-		if (a.type == VALUE_STRING || b.type == VALUE_STRING) { \
-			a = copy_value(a); \
-			b = copy_value(b); \
-			convert_to_rcstr(&a); \
-			convert_to_rcstr(&b); \
-			bool res = strcmp(a.s.ptr, b.s.ptr) op 0; \
-			free_value(a); \
-			free_value(b); \
-			a.b = res; \
-		} else { \
-			convert_to_number(&a); \
-			convert_to_number(&b); \
-			a.b = a.n op b.n; \
-		} \
-		a.type = VALUE_BOOL; \
-		return a; \
+		return (Value){ .type = VALUE_BOOL, .b = value_compare(a, b) op 0 }; \
 	}
 
 M_VALUE_COMPARISON_OP(greater_than, >);
@@ -174,8 +277,43 @@ M_VALUE_COMPARISON_OP(equal, ==);
 
 #undef M_VALUE_COMPARISON_OP
 
-static float scratch_degrees_to_radians(int direction) {
+/// `value_compare` for numbers, where NaN is compared as the string "NaN"
+static inline int compare_numbers(double a, double b) {
+	if (isnan(a) || isnan(b)) {
+		return value_compare((Value){ .type = VALUE_NUM, .n = a }, (Value){ .type = VALUE_NUM, .n = b });
+	}
+	return (a > b) - (a < b);
+}
+
+/// javascript's `Math.round`, which rounds halves up (`round` rounds them away from 0)
+static inline double scratch_round(double n) {
+	double floored = floor(n);
+	return n - floored >= 0.5 ? floored + 1 : floored;
+}
+
+static float scratch_degrees_to_radians(float direction) {
 	return (-direction + 90) * PI / 180.0;
 }
 
+/// wraps a direction in degrees to (-180, 180], like scratch does
+static inline float wrap_direction(float direction) {
+	direction = fmodf(direction + 179, 360);
+	if (direction < 0) direction += 360;
+	return direction - 179;
+}
+
+/// scratch direction (in degrees) of the vector (dx, dy)
+static inline float direction_towards(float dx, float dy) {
+	return 90 - atan2f(dy, dx) * 180.0 / PI;
+}
+
+/// mouse position in stage coordinates
+static inline Vector2 stage_mouse_position() {
+	Vector2 mouse = GetMousePosition();
+	return (Vector2) { mouse.x - 240, 180 - mouse.y };
+}
+
+/// turns the actor away from the edge of the stage it touches, and moves it back inside the stage
+void bounce_off_edge(ActorState *a);
+
 void draw_actor(ActorState *a);
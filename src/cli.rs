@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::options::Options;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Compiles scratch projects to C")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Compile a project to C source code
+    Compile {
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        options: OptionArgs,
+        /// Which stage of the compilation to output
+        #[arg(long, value_enum, default_value_t = Emit::C)]
+        emit: Emit,
+    },
+    /// List every block of a project that can't be compiled, without writing anything
+    Check {
+        #[command(flatten)]
+        input: InputArgs,
+        /// How the report is printed
+        #[arg(long, value_enum, default_value_t = Format::Human)]
+        format: Format,
+    },
+    /// Compile a project and build an executable from the generated C
+    Build {
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        options: OptionArgs,
+        /// Path of the built executable
+        #[arg(short, long, default_value = "game")]
+        executable: PathBuf,
+        /// C compiler used to build the executable
+        #[arg(long, default_value = "cc")]
+        cc: String,
+        /// Extra flags given to the C compiler
+        #[arg(long, allow_hyphen_values = true)]
+        cflags: Vec<String>,
+    },
+    /// Print a summary of the targets of a project
+    Info {
+        #[command(flatten)]
+        input: InputArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct InputArgs {
+    /// `.sb3` archive or directory containing an unpacked project
+    pub input: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// Directory where the generated files are written
+    #[arg(short, long, default_value = ".")]
+    pub out_dir: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct OptionArgs {
+    /// Replace blocks that can't be compiled with stubs logging them at runtime, instead of failing
+    #[arg(long)]
+    pub allow_unsupported: bool,
+    /// Report what the optimizations removed from the project
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+impl From<OptionArgs> for Options {
+    fn from(args: OptionArgs) -> Self {
+        Options { allow_unsupported: args.allow_unsupported, verbose: args.verbose }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A summary meant to be read by humans
+    Human,
+    /// A JSON object, for other tools
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Emit {
+    /// The deserialized `project.json`, printed to stdout
+    Project,
+    /// The parsed targets, printed to stdout
+    Parsed,
+    /// The lowered scripts, printed to stdout
+    Ir,
+    /// `output.h` and `output.c`, written to the output directory
+    C,
+}
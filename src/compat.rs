@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::diagnostic::{self, Diagnostics, Kind};
+use crate::options::Options;
+use crate::project::ProjectFiles;
+use crate::{ir, parser};
+
+/// The compilation stage that rejected a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Parser,
+    Generator,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Location {
+    pub sprite: String,
+    /// `None` for the diagnostics that aren't tied to a block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnsupportedOpcode {
+    pub opcode: String,
+    pub stage: Stage,
+    pub count: usize,
+    pub locations: Vec<Location>,
+}
+
+/// Every opcode of a project that can't be compiled
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub unsupported: Vec<UnsupportedOpcode>,
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.unsupported.is_empty()
+    }
+
+    /// number of blocks that can't be compiled
+    pub fn block_count(&self) -> usize {
+        self.unsupported.iter().map(|u| u.count).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "every block is supported");
+        }
+
+        writeln!(f, "{} unsupported opcodes in {} blocks:", self.unsupported.len(), self.block_count())?;
+        for unsupported in &self.unsupported {
+            let stage = match unsupported.stage {
+                Stage::Parser => "parser",
+                Stage::Generator => "generator",
+            };
+            writeln!(f, "    {:<32} {:>4}x  ({stage})", unsupported.opcode, unsupported.count)?;
+            for location in &unsupported.locations {
+                match &location.block_id {
+                    Some(id) => writeln!(f, "        in `{}`, block `{id}`", location.sprite)?,
+                    None => writeln!(f, "        in `{}`", location.sprite)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lists every block of the scripts of the project that the parser or the generator can't handle.
+/// Other errors found along the way are added to `diagnostics`.
+pub fn check(files: ProjectFiles, diagnostics: &mut Diagnostics) -> Report {
+    let mut found: HashMap<(String, Stage), Vec<Location>> = HashMap::new();
+    let mut add = |diagnostic: diagnostic::Diagnostic, stage: Stage, diagnostics: &mut Diagnostics| {
+        match (diagnostic.kind, diagnostic.location.opcode.clone()) {
+            (Kind::Unsupported, Some(opcode)) => {
+                let location = Location {
+                    sprite: diagnostic.location.sprite.unwrap_or_default(),
+                    block_id: diagnostic.location.block_id,
+                };
+                found.entry((opcode, stage)).or_default().push(location);
+            }
+            _ => diagnostics.push(diagnostic),
+        }
+    };
+
+    // unsupported blocks are replaced with stubs, so that parsing goes on and finds all of them
+    let options = Options { allow_unsupported: true, ..Options::default() };
+    let mut parse_diagnostics = Diagnostics::default();
+    let (targets, globals) = parser::parse(files.project.targets, &files.assets, &options, &mut parse_diagnostics);
+    for diagnostic in parse_diagnostics.0 {
+        add(diagnostic, Stage::Parser, diagnostics);
+    }
+
+    // the C generator itself doesn't reject blocks, lowering them does
+    let mut generate_diagnostics = Diagnostics::default();
+    ir::lower(&targets, &globals, &mut generate_diagnostics);
+    for diagnostic in generate_diagnostics.0 {
+        add(diagnostic, Stage::Generator, diagnostics);
+    }
+
+    let mut unsupported: Vec<_> = found
+        .into_iter()
+        .map(|((opcode, stage), mut locations)| {
+            locations.sort_by(|a, b| (&a.sprite, &a.block_id).cmp(&(&b.sprite, &b.block_id)));
+            UnsupportedOpcode { opcode, stage, count: locations.len(), locations }
+        })
+        .collect();
+    // most used opcodes first
+    unsupported.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.opcode.cmp(&b.opcode)));
+
+    Report { unsupported }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::project::Assets;
+
+    /// a block of a script, followed by `next`
+    fn block(opcode: &str, parent: Option<&str>, next: Option<&str>) -> serde_json::Value {
+        json!({
+            "opcode": opcode,
+            "next": next,
+            "parent": parent,
+            "inputs": {},
+            "fields": {},
+            "shadow": false,
+            "topLevel": parent.is_none(),
+        })
+    }
+
+    fn files() -> ProjectFiles {
+        let stage = json!({
+            "isStage": true, "name": "Stage", "variables": {}, "lists": {}, "blocks": {},
+            "currentCostume": 0, "costumes": [], "sounds": [], "tempo": 60,
+        });
+        let blocks = json!({
+            "flag1": block("event_whenflagclicked", None, Some("clear1")),
+            "clear1": block("pen_clear", Some("flag1"), Some("up")),
+            "up": block("pen_penUp", Some("clear1"), Some("turn")),
+            "turn": block("motion_ifonedgebounce", Some("up"), None),
+            "flag2": block("event_whenflagclicked", None, Some("clear2")),
+            "clear2": block("pen_clear", Some("flag2"), None),
+        });
+        let sprite = json!({
+            "isStage": false, "name": "Sprite1", "variables": {}, "lists": {}, "blocks": blocks,
+            "currentCostume": 0, "costumes": [], "sounds": [],
+            "x": 0, "y": 0, "size": 100, "direction": 90,
+        });
+        let project = serde_json::from_value(json!({ "targets": [stage, sprite] })).unwrap();
+        ProjectFiles { project, assets: Assets::new() }
+    }
+
+    #[test]
+    fn reports_every_unsupported_block() {
+        let mut diagnostics = Diagnostics::default();
+        let report = check(files(), &mut diagnostics);
+        assert!(diagnostics.0.is_empty(), "{:?}", diagnostics.0);
+
+        assert_eq!(report.block_count(), 3);
+        let found: Vec<_> = report
+            .unsupported
+            .iter()
+            .map(|u| {
+                let ids: Vec<_> = u.locations.iter().map(|l| l.block_id.as_deref().unwrap()).collect();
+                (u.opcode.as_str(), u.stage, u.count, ids)
+            })
+            .collect();
+        // most used opcodes first, then their blocks in order
+        assert_eq!(
+            found,
+            [("pen_clear", Stage::Parser, 2, vec!["clear1", "clear2"]), ("pen_penUp", Stage::Parser, 1, vec!["up"])]
+        );
+        assert!(report.unsupported.iter().flat_map(|u| &u.locations).all(|l| l.sprite == "Sprite1"));
+    }
+
+    #[test]
+    fn writes_the_report() {
+        let report = check(files(), &mut Diagnostics::default());
+        let text = report.to_string();
+        assert!(text.starts_with("2 unsupported opcodes in 3 blocks:\n"), "{text}");
+        assert!(text.contains("in `Sprite1`, block `clear2`"), "{text}");
+
+        let json = serde_json::to_value(&report).unwrap();
+        let first = &json["unsupported"][0];
+        assert_eq!(first["opcode"], "pen_clear");
+        assert_eq!(first["stage"], "parser");
+        assert_eq!(first["locations"][0], json!({ "sprite": "Sprite1", "block_id": "clear1" }));
+
+        assert_eq!(Report::default().to_string(), "every block is supported\n");
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Level {
+    Error,
+    Warning,
+    /// Information about the compilation, which isn't a problem
+    Note,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// The project itself is malformed
+    Invalid,
+    /// The project is valid, but uses a block the compiler can't handle
+    Unsupported,
+}
+
+/// Where in the project a diagnostic comes from.
+/// Every part is optional, as some errors are not tied to a specific block.
+#[derive(Debug, Default, Clone)]
+pub struct Location {
+    pub sprite: Option<String>,
+    pub block_id: Option<String>,
+    pub opcode: Option<String>,
+    pub input: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub kind: Kind,
+    pub message: String,
+    pub location: Location,
+    /// The opcodes of the blocks containing the one the diagnostic is about,
+    /// from the top of the script, along with their nesting depth.
+    pub context: Vec<(usize, String)>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic {
+            level: Level::Error,
+            kind: Kind::Invalid,
+            message: message.into(),
+            location: Location::default(),
+            context: vec![],
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic { level: Level::Warning, ..Diagnostic::error(message) }
+    }
+
+    pub fn note(message: impl Into<String>) -> Self {
+        Diagnostic { level: Level::Note, ..Diagnostic::error(message) }
+    }
+
+    /// an error for a block that the compiler doesn't know how to handle
+    pub fn unsupported(opcode: &str) -> Self {
+        Diagnostic { kind: Kind::Unsupported, ..Diagnostic::error(format!("unsupported block `{opcode}`")) }
+            .with_opcode(opcode)
+    }
+
+    pub fn with_sprite(mut self, sprite: &str) -> Self {
+        self.location.sprite = Some(sprite.to_owned());
+        self
+    }
+
+    pub fn with_block(mut self, id: &str) -> Self {
+        self.location.block_id = Some(id.to_owned());
+        self
+    }
+
+    pub fn with_opcode(mut self, opcode: &str) -> Self {
+        self.location.opcode = Some(opcode.to_owned());
+        self
+    }
+
+    pub fn with_input(mut self, input: &str) -> Self {
+        self.location.input = Some(input.to_owned());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Level::Error => writeln!(f, "error: {}", self.message)?,
+            Level::Warning => writeln!(f, "warning: {}", self.message)?,
+            Level::Note => writeln!(f, "note: {}", self.message)?,
+        }
+
+        let Location { sprite, block_id, opcode, input } = &self.location;
+        let mut parts = vec![];
+        if let Some(sprite) = sprite {
+            parts.push(format!("in `{sprite}`"));
+        }
+        match (block_id, opcode) {
+            (Some(id), Some(opcode)) => parts.push(format!("block `{id}` ({opcode})")),
+            (Some(id), None) => parts.push(format!("block `{id}`")),
+            (None, Some(opcode)) => parts.push(format!("block {opcode}")),
+            (None, None) => (),
+        }
+        if let Some(input) = input {
+            parts.push(format!("input `{input}`"));
+        }
+        if !parts.is_empty() {
+            writeln!(f, "  --> {}", parts.join(", "))?;
+        }
+
+        if !self.context.is_empty() {
+            writeln!(f, "   |")?;
+            for (i, (depth, opcode)) in self.context.iter().enumerate() {
+                let indent = "  ".repeat(*depth);
+                if i + 1 == self.context.len() {
+                    writeln!(f, "   | {indent}{opcode}  <-- here")?;
+                } else {
+                    writeln!(f, "   | {indent}{opcode}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Diagnostics collected through the whole compilation
+#[derive(Debug, Default)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.0.iter().filter(|d| d.level == Level::Error).count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    /// prints every diagnostic to stderr, followed by a summary
+    pub fn report(&self) {
+        for diagnostic in &self.0 {
+            eprintln!("{diagnostic}");
+        }
+
+        let errors = self.error_count();
+        let warnings = self.0.iter().filter(|d| d.level == Level::Warning).count();
+        if errors > 0 {
+            eprintln!("error: compilation failed with {errors} error(s) and {warnings} warning(s)");
+        } else if warnings > 0 {
+            eprintln!("warning: {warnings} warning(s) emitted");
+        }
+    }
+}
@@ -1,84 +1,145 @@
 use std::io::{self, Write};
-use crate::parser::Control;
-use super::{compute_value, linearize_sequence, Linearize, util::*};
+use crate::ir::{self, Type};
+use crate::parser::{self, Block, Control};
+use super::{event, util::*};
 
-impl Linearize for Control {
-    fn linearize<W: Write>(&self, f: &mut IW<W>, args: &mut GeneratorArgs) -> io::Result<Return> {
-        match self {
-            Control::CreateCloneOf { actor: _ } => {
-                // TODO:
-                writeln!(f, "printf(\"TODO: create clone code\");")?;
-                writeln!(f, "exit(-1);")?;
-            }
-            Control::Wait { duration } => {
-                let duration = compute_value(f, args, duration)?;
-                writeln!(f, "convert_to_number(&{duration});")?;
-                writeln!(f, "s->time = GetTime() + {duration}.n;")?;
-                writeln!(f, "s->state = {};", *args.state + 1)?;
-
-                end_case(f, args.state)?;
-                start_case(f, args.state)?;
-
-                writeln!(f, "if (GetTime() >= s->time) s->state = {};", *args.state + 1)?;
-                return Ok(Return::Hold);
-            }
-            Control::Repeat { times, branch } => {
-                *args.state += 1; // make the branch think we ended this case
-                let repeat_start = *args.state;
-                let mut branch_code = IW::new(Vec::new());
-                // prepare branch to get end state number
-                linearize_sequence(&mut branch_code, args, branch)?;
-
-                // initialize loop value (evaluate condition once)
-                let num = compute_value(f, args, times)?;
-                writeln!(f, "convert_to_number(&{num})")?;
+/// Functions creating clones of every sprite, and freeing the deleted ones
+pub fn generate_clone_functions<W: Write>(
+    header: &mut IW<W>,
+    source: &mut IW<W>,
+    targets: &[parser::Target],
+    program: &ir::Program,
+) -> io::Result<()> {
+    let sprites = targets.iter().zip(&program.targets).filter(|(t, _)| matches!(t.kind, parser::TargetKind::Sprite { .. }));
 
-                // if initial condition is false, skip loop body
-                writeln!(f, "if ((int){num}.n <= 0) s->state = {};", *args.state + 1)?;
+    for (target, lowered) in sprites.clone() {
+        let name = &target.name;
+        writeln!(header, "void clone_{name}(GlobalState *g, Actor{name} *parent);")?;
 
-                // otherwise, initialize loop variable and go to the next state (start of the sequence we linearized above)
-                writeln!(f, "else {{")?;
-                f.indent();
-                writeln!(f, "s->state = {};", repeat_start)?;
-                let loop_var = format!("loop_{}", generate_var_name());
-                writeln!(f, "s->{loop_var} = {num}.n;")?;
-                f.deindent();
-                writeln!(f, "}}")?;
+        writeln!(source, "/// Adds a copy of `parent` to the actors of {name}, unless there are too many clones already.")?;
+        writeln!(source, "void clone_{name}(GlobalState *g, Actor{name} *parent) {{")?;
+        source.indent();
+        writeln!(source, "if (g->num_clones >= MAX_CLONES) return;")?;
+        writeln!(source, "if (g->num_{name} == g->cap_{name}) {{")?;
+        source.indent();
+        writeln!(source, "g->cap_{name} *= 2;")?;
+        writeln!(source, "g->list_{name} = realloc(g->list_{name}, g->cap_{name} * sizeof(Actor{name} *));")?;
+        source.deindent();
+        writeln!(source, "}}")?;
+        writeln!(source)?;
 
-                *args.state -= 1; // reverse previous add
-                                  // end loop start
-                end_case(f, args.state)?;
-
-                f.write_all(&branch_code.writer)?;
+        writeln!(source, "Actor{name} *clone = malloc(sizeof(Actor{name}));")?;
+        writeln!(source, "clone->actor_state = parent->actor_state;")?;
+        writeln!(source, "clone->actor_state.is_clone = true;")?;
+        writeln!(source, "clone->actor_state.saying.ptr = NULL;")?;
+        writeln!(source, "clone->actor_state.say_end = INFINITY;")?;
+        for var in target.vars.values() {
+            match lowered.vars[&var.cident] {
+                Type::Value => writeln!(source, "clone->var_{0} = copy_value(parent->var_{0});", var.cident)?,
+                _ => writeln!(source, "clone->var_{0} = parent->var_{0};", var.cident)?,
+            }
+        }
+        for list in target.lists.values() {
+            writeln!(source, "clone->lst_{0} = list_copy(&parent->lst_{0});", list.cident)?;
+        }
+        for (i, sequence) in target.sequences.iter().enumerate() {
+            // "when I start as a clone" scripts skip their hat, the others wait for theirs
+            let state = match sequence.hat() {
+                Some(Block::Control(Control::StartAsClone)) => 1,
+                _ => 0,
+            };
+            writeln!(source, "clone->sequence{i}_state = ({name}Sequence{i}State) {{ .state = {state} }};")?;
+        }
+        writeln!(source)?;
+        writeln!(source, "g->list_{name}[g->num_{name}++] = clone;")?;
+        writeln!(source, "g->num_clones++;")?;
+        writeln!(source, "if (clone->actor_state.visible) g->redraw_requested = true;")?;
+        source.deindent();
+        writeln!(source, "}}")?;
+        writeln!(source)?;
+    }
 
-                // end loop (loop back condition)
-                start_case(f, args.state)?;
-                writeln!(f, "s->{loop_var}--;")?;
-                writeln!(f, "if (s->{loop_var} > 0) s->state = {};", repeat_start)?;
-                writeln!(f, "else s->state = {};", *args.state + 1)?;
-                args.new_locals.push(loop_var);
-                return Ok(Return::Hold);
+    writeln!(header, "void remove_deleted_clones(GlobalState *g);")?;
+    writeln!(source, "/// Frees the clones deleted during this frame.")?;
+    writeln!(source, "void remove_deleted_clones(GlobalState *g) {{")?;
+    source.indent();
+    for (target, lowered) in sprites {
+        let name = &target.name;
+        writeln!(source, "{{")?;
+        source.indent();
+        writeln!(source, "int kept = 0;")?;
+        writeln!(source, "for (int i = 0; i < g->num_{name}; i++) {{")?;
+        source.indent();
+        writeln!(source, "Actor{name} *a = g->list_{name}[i];")?;
+        writeln!(source, "if (!a->actor_state.deleted) {{")?;
+        source.indent();
+        writeln!(source, "g->list_{name}[kept++] = a;")?;
+        writeln!(source, "continue;")?;
+        source.deindent();
+        writeln!(source, "}}")?;
+        writeln!(source)?;
+        for var in target.vars.values().filter(|v| lowered.vars[&v.cident] == Type::Value) {
+            writeln!(source, "free_value(a->var_{});", var.cident)?;
+        }
+        for list in target.lists.values() {
+            writeln!(source, "list_free(&a->lst_{});", list.cident)?;
+        }
+        if !target.procedures.is_empty() {
+            for i in 0..target.sequences.len() {
+                writeln!(source, "call_stack_clear(&a->sequence{i}_state.stack, free_{name}_frame);")?;
             }
-            Control::IfCondition { condition, branch } => {
-                *args.state += 1; // make the branch think we ended this case
-                let branch_start = *args.state;
-                let mut branch_code = IW::new(Vec::new());
-                // prepare branch to get end state number
-                linearize_sequence(&mut branch_code, args, branch)?;
+        }
+        writeln!(source, "if (a->actor_state.saying.ptr != NULL) free_rcstr(a->actor_state.saying);")?;
+        writeln!(source, "free(a);")?;
+        writeln!(source, "g->num_clones--;")?;
+        source.deindent();
+        writeln!(source, "}}")?;
+        writeln!(source, "g->num_{name} = kept;")?;
+        source.deindent();
+        writeln!(source, "}}")?;
+    }
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
 
-                let condition = compute_value(f, args, condition)?;
-                writeln!(f, "convert_to_bool(&{condition});")?;
-                writeln!(f, "if ({condition}.b) s->state = {branch_start};")?;
-                writeln!(f, "else s->state = {};", *args.state + 1)?;
+    Ok(())
+}
 
-                *args.state -= 1; // reverse previous add
-                                  // end condition start
-                end_case(f, args.state)?;
-                f.write_all(&branch_code.writer)?;
+/// Function stopping every script, for the "stop all" block
+pub fn generate_stop_all<W: Write>(header: &mut IW<W>, source: &mut IW<W>, targets: &[parser::Target]) -> io::Result<()> {
+    writeln!(header, "void stop_all(GlobalState *g);")?;
 
-                return Ok(Return::Ended)
+    writeln!(source, "/// Stops every script, and deletes every clone.")?;
+    writeln!(source, "void stop_all(GlobalState *g) {{")?;
+    source.indent();
+    // broadcasts sent before stopping mustn't start their receivers on the next frame
+    if event::has_broadcasts(targets) {
+        writeln!(source, "memset(g->broadcasts, 0, sizeof(g->broadcasts));")?;
+    }
+    for target in targets {
+        match target.kind {
+            parser::TargetKind::Stage { .. } => {
+                for i in 0..target.sequences.len() {
+                    writeln!(source, "{}", reset_sequence(target, &format!("g->stage.sequence{i}_state"), 0))?;
+                }
+            }
+            parser::TargetKind::Sprite { .. } => {
+                let name = &target.name;
+                writeln!(source, "for (int i = 0; i < g->num_{name}; i++) {{")?;
+                source.indent();
+                writeln!(source, "Actor{name} *a = g->list_{name}[i];")?;
+                writeln!(source, "if (a->actor_state.is_clone) a->actor_state.deleted = true;")?;
+                for i in 0..target.sequences.len() {
+                    writeln!(source, "{}", reset_sequence(target, &format!("a->sequence{i}_state"), 0))?;
+                }
+                source.deindent();
+                writeln!(source, "}}")?;
             }
         }
-        Ok(Return::Empty)
     }
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    Ok(())
 }
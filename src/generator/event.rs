@@ -1,14 +1,157 @@
 use std::io::{self, Write};
-use crate::parser::Event;
-use super::{compute_value, Linearize, util::*};
-
-impl Linearize for Event {
-    fn linearize<W: Write>(&self, f: &mut IW<W>, args: &mut GeneratorArgs) -> io::Result<Return> {
-        match self {
-            Event::WhenFlagClicked => {
-                writeln!(f, "if (g->flag_clicked) s->state = {};", *args.state + 1)?;
-                Ok(Return::Hold)
+use crate::ir;
+use crate::parser::{self, Block, Event};
+use super::{literal, util::*};
+
+/// A broadcast received by at least one script
+struct Receivers<'a> {
+    /// lowercase name, as scratch matches broadcasts case-insensitively (with javascript's `toLowerCase`)
+    name: String,
+    /// every target receiving the broadcast, with the indices of the receiving sequences
+    targets: Vec<(&'a parser::Target, Vec<usize>)>,
+}
+
+fn find_receivers(targets: &[parser::Target]) -> Vec<Receivers<'_>> {
+    let mut receivers: Vec<Receivers> = vec![];
+    for target in targets {
+        for (i, sequence) in target.sequences.iter().enumerate() {
+            let Some(Block::Event(Event::WhenBroadcastReceived { broadcast })) = sequence.hat() else { continue };
+
+            let name = ir::lowercase(&broadcast.name);
+            let index = match receivers.iter().position(|r| r.name == name) {
+                Some(index) => index,
+                None => {
+                    receivers.push(Receivers { name, targets: vec![] });
+                    receivers.len() - 1
+                }
+            };
+            let broadcast = &mut receivers[index];
+            match broadcast.targets.last_mut() {
+                Some((t, sequences)) if std::ptr::eq(*t, target) => sequences.push(i),
+                _ => broadcast.targets.push((target, vec![i])),
             }
         }
     }
+    receivers
+}
+
+/// wether any script receives a broadcast, so that `GlobalState` has a `broadcasts` field
+pub fn has_broadcasts(targets: &[parser::Target]) -> bool {
+    !find_receivers(targets).is_empty()
+}
+
+/// C expression of the state of a sequence of the `i`th actor of `target` (`i` is ignored for the stage)
+fn sequence_state(target: &parser::Target, sequence: usize) -> String {
+    match target.kind {
+        parser::TargetKind::Stage { .. } => format!("g->stage.sequence{sequence}_state"),
+        parser::TargetKind::Sprite { .. } => format!("g->list_{}[i]->sequence{sequence}_state", target.name),
+    }
+}
+
+/// writes `code(target, state)` for the state of every sequence receiving `broadcast`, on every actor
+fn for_each_receiver<W: Write>(
+    f: &mut IW<W>,
+    broadcast: &Receivers,
+    code: impl Fn(&parser::Target, &str) -> String,
+) -> io::Result<()> {
+    for (target, sequences) in &broadcast.targets {
+        let is_sprite = matches!(target.kind, parser::TargetKind::Sprite { .. });
+        if is_sprite {
+            writeln!(f, "for (int i = 0; i < g->num_{}; i++) {{", target.name)?;
+            f.indent();
+        }
+        for &sequence in sequences {
+            writeln!(f, "{}", code(target, &sequence_state(target, sequence)))?;
+        }
+        if is_sprite {
+            f.deindent();
+            writeln!(f, "}}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Fields of `GlobalState` holding the broadcasts sent during the current frame
+pub fn generate_broadcast_fields<W: Write>(header: &mut IW<W>, targets: &[parser::Target]) -> io::Result<()> {
+    let receivers = find_receivers(targets);
+    if !receivers.is_empty() {
+        writeln!(header, "/// broadcasts sent this frame, their receivers start on the next one (scratch starts them in the same frame)")?;
+        writeln!(header, "bool broadcasts[{}];", receivers.len())?;
+    }
+    Ok(())
+}
+
+/// Functions sending broadcasts and starting their receivers.
+/// Broadcasts are looked up by name at runtime, since the message can be any value.
+/// Unlike scratch, which starts the receivers in the same frame, they start on the frame after the broadcast.
+pub fn generate_broadcast_functions<W: Write>(
+    header: &mut IW<W>,
+    source: &mut IW<W>,
+    targets: &[parser::Target],
+) -> io::Result<()> {
+    let receivers = find_receivers(targets);
+
+    writeln!(header, "int send_broadcast(GlobalState *g, Value message);")?;
+    writeln!(header, "bool broadcast_running(GlobalState *g, int broadcast);")?;
+    writeln!(header, "void start_broadcasts(GlobalState *g);")?;
+
+    writeln!(source, "/// Sends the broadcast named `message`, and returns its index (-1 if no script receives it).")?;
+    writeln!(source, "int send_broadcast(GlobalState *g, Value message) {{")?;
+    source.indent();
+    writeln!(source, "Value name = copy_value(message);")?;
+    writeln!(source, "convert_to_rcstr(&name);")?;
+    writeln!(source, "int index = -1;")?;
+    for (i, broadcast) in receivers.iter().enumerate() {
+        if i > 0 {
+            write!(source, "else ")?;
+        }
+        writeln!(source, "if (strcmp_lowercase(name.s.ptr, {}) == 0) index = {i};", literal::string(&broadcast.name))?;
+    }
+    writeln!(source, "free_value(name);")?;
+    if !receivers.is_empty() {
+        writeln!(source, "if (index >= 0) g->broadcasts[index] = true;")?;
+    }
+    writeln!(source, "return index;")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    writeln!(source, "/// Returns wether a script started by the broadcast is still running (or about to start).")?;
+    writeln!(source, "bool broadcast_running(GlobalState *g, int broadcast) {{")?;
+    source.indent();
+    writeln!(source, "switch (broadcast) {{")?;
+    for (i, broadcast) in receivers.iter().enumerate() {
+        writeln!(source, "case {i}:")?;
+        source.indent();
+        writeln!(source, "if (g->broadcasts[{i}]) return true;")?;
+        for_each_receiver(source, broadcast, |_, state| format!("if ({state}.state != 0) return true;"))?;
+        writeln!(source, "return false;")?;
+        source.deindent();
+    }
+    writeln!(source, "default:")?;
+    source.indent();
+    writeln!(source, "return false;")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    writeln!(source, "/// (Re)starts the receivers of the broadcasts sent during the last frame, on every actor.")?;
+    writeln!(source, "void start_broadcasts(GlobalState *g) {{")?;
+    source.indent();
+    for (i, broadcast) in receivers.iter().enumerate() {
+        writeln!(source, "if (g->broadcasts[{i}]) {{")?;
+        source.indent();
+        writeln!(source, "g->broadcasts[{i}] = false;")?;
+        // the hat block is state 0, skip it
+        for_each_receiver(source, broadcast, |target, state| reset_sequence(target, state, 1))?;
+        source.deindent();
+        writeln!(source, "}}")?;
+    }
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    Ok(())
 }
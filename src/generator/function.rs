@@ -0,0 +1,531 @@
+//! C code of the lowered scripts and custom blocks.
+//!
+//! Basic blocks are written in order. The regions that can't suspend the script are structured C (see [`structure`]),
+//! the other blocks jump to each other with `goto`.
+//! Only the blocks a script can be suspended before get a state, which the function jumps to when it is resumed.
+//!
+//! Every `Value` temporary owns a reference, and instructions only borrow their operands.
+//! Temporaries are released whenever the function returns.
+//! String literals are the static constants of the [`StringPool`], which are never freed.
+
+use std::io::{self, Write};
+use crate::ir::{self, Actor, ActorField, BinaryOp, BlockId, Const, Expr, FunctionKind, Inst, Operand, Scope, Terminator, Type, UnaryOp, VarRef};
+use crate::parser;
+use super::{literal, motion, procedures, strings::StringPool, util::*};
+use super::structure::{self, Item, Stmt};
+
+/// C type holding values of type `ty`
+pub fn c_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Number => "double",
+        Type::Bool => "bool",
+        Type::Value => "Value",
+    }
+}
+
+/// writes `release`, the code freeing the temporaries of a function, before it returns
+pub fn write_release<W: Write>(f: &mut IW<W>, release: &str) -> io::Result<()> {
+    if !release.is_empty() {
+        writeln!(f, "{release}")?;
+    }
+    Ok(())
+}
+
+/// writes the fields of the state of `function` holding its locals
+pub fn write_locals<W: Write>(f: &mut IW<W>, function: &ir::Function) -> io::Result<()> {
+    for (i, local) in function.locals.iter().enumerate() {
+        writeln!(f, "{} {}{i};", c_type(local.ty), local.name)?;
+    }
+    Ok(())
+}
+
+struct FunctionWriter<'a> {
+    target: &'a parser::Target,
+    function: &'a ir::Function,
+    /// the state of the blocks the function can be resumed at
+    states: Vec<Option<u32>>,
+    /// wether each block is jumped to, and needs a label
+    labels: Vec<bool>,
+    pool: &'a StringPool,
+    /// the code freeing the temporaries
+    release: String,
+}
+
+impl FunctionWriter<'_> {
+    fn warp(&self) -> bool {
+        matches!(self.function.kind, FunctionKind::WarpProcedure(_))
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Temp(t) => format!("t{}", t.0),
+            Operand::Const(c) => match c {
+                Const::Number(n) => literal::number(*n),
+                Const::Bool(b) => b.to_string(),
+                Const::String(s) => self.pool.value(s),
+                Const::Color(r, g, b) => {
+                    format!("(Value){{ .type = VALUE_COLOR, .c = (ValueColor){{ {r}, {g}, {b} }} }}")
+                }
+            },
+        }
+    }
+
+    fn local(&self, local: ir::Local) -> String {
+        format!("s->{}{}", self.function.locals[local.0].name, local.0)
+    }
+
+    fn var(&self, var: &VarRef) -> String {
+        match var.scope {
+            Scope::Actor => format!("a->var_{}", var.cident),
+            Scope::Global => format!("g->var_{}", var.cident),
+        }
+    }
+
+    /// pointer to a list
+    fn list(&self, list: &VarRef) -> String {
+        match list.scope {
+            Scope::Actor => format!("&a->lst_{}", list.cident),
+            Scope::Global => format!("&g->lst_{}", list.cident),
+        }
+    }
+
+    fn actor_state(&self, actor: &Actor) -> String {
+        match actor {
+            Actor::This => "a->actor_state".to_owned(),
+            Actor::Original(name) => format!("g->list_{name}[0]->actor_state"),
+        }
+    }
+
+    /// converts the operand to `ty` with scratch's rules
+    fn convert(&self, ty: Type, operand: &Operand) -> String {
+        let from = operand.ty(&self.function.temps);
+        let operand = self.operand(operand);
+        match (from, ty) {
+            (Type::Value, Type::Number) => format!("value_as_number({operand})"),
+            (Type::Value, Type::Bool) => format!("value_as_bool({operand})"),
+            (Type::Number, Type::Value) => format!("(Value){{ .type = VALUE_NUM, .n = {operand} }}"),
+            (Type::Bool, Type::Value) => format!("(Value){{ .type = VALUE_BOOL, .b = {operand} }}"),
+            (Type::Number, Type::Bool) => format!("value_as_bool((Value){{ .type = VALUE_NUM, .n = {operand} }})"),
+            (Type::Bool, Type::Number) => format!("({operand} ? 1.0 : 0.0)"),
+            _ => operand,
+        }
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Convert(ty, operand) => self.convert(*ty, operand),
+            Expr::Binary(op, lhs, rhs) => {
+                let values = lhs.ty(&self.function.temps) == Type::Value;
+                let (lhs, rhs) = (self.operand(lhs), self.operand(rhs));
+                match op {
+                    BinaryOp::Add => format!("{lhs} + {rhs}"),
+                    BinaryOp::Sub => format!("{lhs} - {rhs}"),
+                    BinaryOp::Mul => format!("{lhs} * {rhs}"),
+                    BinaryOp::Div => format!("{lhs} / {rhs}"),
+                    BinaryOp::LesserThan if values => format!("value_lesser_than({lhs}, {rhs}).b"),
+                    BinaryOp::GreaterThan if values => format!("value_greater_than({lhs}, {rhs}).b"),
+                    BinaryOp::Equals if values => format!("value_equal({lhs}, {rhs}).b"),
+                    BinaryOp::LesserThan => format!("compare_numbers({lhs}, {rhs}) < 0"),
+                    BinaryOp::GreaterThan => format!("compare_numbers({lhs}, {rhs}) > 0"),
+                    BinaryOp::Equals => format!("compare_numbers({lhs}, {rhs}) == 0"),
+                    BinaryOp::And => format!("{lhs} && {rhs}"),
+                    BinaryOp::Or => format!("{lhs} || {rhs}"),
+                }
+            }
+            Expr::Unary(UnaryOp::Not, operand) => format!("!{}", self.operand(operand)),
+            Expr::Unary(UnaryOp::Round, operand) => format!("scratch_round({})", self.operand(operand)),
+            Expr::Var(var) if var.ty == Type::Value => format!("copy_value({})", self.var(var)),
+            Expr::Var(var) => self.var(var),
+            Expr::Argument(i) => format!("copy_value(s->args[{i}])"),
+            Expr::Local(local) => self.local(*local),
+            Expr::ListItem(l, index) => format!("list_item({}, {})", self.list(l), self.operand(index)),
+            Expr::ListIndexOf(l, item) => format!("list_index_of({}, {}).n", self.list(l), self.operand(item)),
+            Expr::ListLength(l) => format!("list_length({}).n", self.list(l)),
+            Expr::ListContains(l, item) => format!("list_contains({}, {}).b", self.list(l), self.operand(item)),
+            Expr::ListJoin(l) => format!("list_join({})", self.list(l)),
+            Expr::ActorField(actor, field) => {
+                let field = match field {
+                    ActorField::X => "x",
+                    ActorField::Y => "y",
+                    ActorField::Direction => "direction",
+                };
+                format!("{}.{field}", self.actor_state(actor))
+            }
+            Expr::IsClone => "a->actor_state.is_clone".to_owned(),
+            Expr::MouseX => "stage_mouse_position().x".to_owned(),
+            Expr::MouseY => "stage_mouse_position().y".to_owned(),
+            Expr::Random(from, to) => format!("GetRandomValue({from}, {to})"),
+            Expr::Time => "GetTime()".to_owned(),
+            Expr::FlagClicked => "g->flag_clicked".to_owned(),
+            Expr::BroadcastRunning(local) => format!("broadcast_running(g, {})", self.local(*local)),
+        }
+    }
+
+    fn inst<W: Write>(&self, f: &mut IW<W>, inst: &Inst) -> io::Result<()> {
+        let name = &self.target.name;
+        match inst {
+            Inst::Let(t, expr) => {
+                // the temporary still holds its value from the last time the block ran
+                if self.function.temps[t.0] == Type::Value {
+                    writeln!(f, "free_value(t{});", t.0)?;
+                }
+                writeln!(f, "t{} = {};", t.0, self.expr(expr))
+            }
+            Inst::SetLocal(local, value) => writeln!(f, "{} = {};", self.local(*local), self.operand(value)),
+            Inst::SetVar(var, value) if var.ty == Type::Value => {
+                writeln!(f, "set_value(&{}, {});", self.var(var), self.operand(value))
+            }
+            Inst::SetVar(var, value) => writeln!(f, "{} = {};", self.var(var), self.operand(value)),
+            Inst::ListPush(l, item) => writeln!(f, "list_push({}, {});", self.list(l), self.operand(item)),
+            Inst::ListClear(l) => writeln!(f, "list_clear({});", self.list(l)),
+            Inst::ListDelete(l, index) => writeln!(f, "list_delete({}, {});", self.list(l), self.operand(index)),
+            Inst::ListInsert { list, index, item } => writeln!(
+                f,
+                "list_insert({}, {}, {});",
+                self.list(list),
+                self.operand(index),
+                self.operand(item)
+            ),
+            Inst::ListReplace { list, index, item } => writeln!(
+                f,
+                "list_replace({}, {}, {});",
+                self.list(list),
+                self.operand(index),
+                self.operand(item)
+            ),
+            Inst::Broadcast(message, None) => writeln!(f, "send_broadcast(g, {});", self.operand(message)),
+            Inst::Broadcast(message, Some(index)) => {
+                writeln!(f, "{} = send_broadcast(g, {});", self.local(*index), self.operand(message))
+            }
+            Inst::SetActorField(field, value) => {
+                let value = self.operand(value);
+                match field {
+                    ActorField::X => writeln!(f, "a->actor_state.x = {value};"),
+                    ActorField::Y => writeln!(f, "a->actor_state.y = {value};"),
+                    ActorField::Direction => writeln!(f, "a->actor_state.direction = wrap_direction({value});"),
+                }
+            }
+            Inst::MoveSteps(steps) => {
+                let steps = self.operand(steps);
+                writeln!(f, "{{")?;
+                f.indent();
+                writeln!(f, "float direction = scratch_degrees_to_radians(a->actor_state.direction);")?;
+                writeln!(f, "a->actor_state.x += cosf(direction)*{steps};")?;
+                writeln!(f, "a->actor_state.y += sinf(direction)*{steps};")?;
+                f.deindent();
+                writeln!(f, "}}")
+            }
+            Inst::PointTowards(x, y) => writeln!(
+                f,
+                "a->actor_state.direction = direction_towards({} - a->actor_state.x, {} - a->actor_state.y);",
+                self.operand(x),
+                self.operand(y)
+            ),
+            Inst::BounceOffEdge => writeln!(f, "bounce_off_edge(&a->actor_state);"),
+            Inst::SetRotationStyle(style) => {
+                writeln!(f, "a->actor_state.rotation_style = {};", motion::rotation_style(style))
+            }
+            Inst::Say(message, until) => {
+                writeln!(f, "{{")?;
+                f.indent();
+                writeln!(f, "Value output = copy_value({});", self.operand(message))?;
+                writeln!(f, "convert_to_rcstr(&output);")?;
+                writeln!(f, "if (a->actor_state.saying.ptr != NULL) free_rcstr(a->actor_state.saying);")?;
+                writeln!(f, "a->actor_state.saying = output.s;")?;
+                writeln!(f, "a->actor_state.say_end = {};", self.operand(until))?;
+                f.deindent();
+                writeln!(f, "}}")
+            }
+            Inst::RequestRedraw => request_redraw(f),
+            Inst::CreateClone { sprite, parent: Actor::This } => writeln!(f, "clone_{sprite}(g, a);"),
+            Inst::CreateClone { sprite, parent: Actor::Original(original) } => {
+                writeln!(f, "clone_{sprite}(g, g->list_{original}[0]);")
+            }
+            Inst::DeleteClone => writeln!(f, "a->actor_state.deleted = true;"),
+            Inst::StopOtherScripts => {
+                for i in 0..self.target.sequences.len() {
+                    let reset = reset_sequence(self.target, &format!("a->sequence{i}_state"), 0);
+                    match self.function.kind {
+                        FunctionKind::Sequence(sequence) => {
+                            if i != sequence {
+                                writeln!(f, "{reset}")?;
+                            }
+                        }
+                        // any script can call a procedure, it is recognized by its call stack
+                        _ => writeln!(f, "if (stack != &a->sequence{i}_state.stack) {{ {reset} }}")?,
+                    }
+                }
+                Ok(())
+            }
+            Inst::CallWarp { procedure, args, stopped } => {
+                writeln!(f, "{{")?;
+                f.indent();
+                writeln!(f, "{name}Proc{procedure}WarpState call = {{ 0 }};")?;
+                for (i, arg) in args.iter().enumerate() {
+                    writeln!(f, "call.args[{i}] = copy_value({});", self.operand(arg))?;
+                }
+                if !self.warp() {
+                    writeln!(f, "g->warp_deadline = GetTime() + WARP_TIMEOUT;")?;
+                }
+                writeln!(f, "t{} = run_{name}_proc{procedure}_warp(a, &call, g, stack);", stopped.0)?;
+                for i in 0..args.len() {
+                    writeln!(f, "free_value(call.args[{i}]);")?;
+                }
+                f.deindent();
+                writeln!(f, "}}")
+            }
+            Inst::Unsupported { opcode, id } => unsupported_stub(f, name, opcode, Some(id)),
+        }
+    }
+
+    /// writes structured statements, at the place of their blocks
+    fn statements<W: Write>(&self, f: &mut IW<W>, stmts: &[Stmt]) -> io::Result<()> {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Insts(i) => {
+                    for inst in &self.function.blocks[*i].insts {
+                        self.inst(f, inst)?;
+                    }
+                }
+                Stmt::Exit(i) => self.terminator(f, *i, &self.function.blocks[*i].terminator)?,
+                Stmt::WarpCheck => writeln!(f, "warp_check(g);")?,
+                Stmt::Break => writeln!(f, "break;")?,
+                Stmt::Continue => writeln!(f, "continue;")?,
+                Stmt::If { block, then, otherwise } => {
+                    let Terminator::Branch(condition, ..) = &self.function.blocks[*block].terminator else {
+                        unreachable!("structured `if`s end with a branch")
+                    };
+                    let condition = self.operand(condition);
+                    let (condition, then, otherwise) = if then.is_empty() && !otherwise.is_empty() {
+                        (format!("!{condition}"), otherwise, then)
+                    } else {
+                        (condition, then, otherwise)
+                    };
+                    match then.as_slice() {
+                        [Stmt::Break] if otherwise.is_empty() => writeln!(f, "if ({condition}) break;")?,
+                        [Stmt::Continue] if otherwise.is_empty() => writeln!(f, "if ({condition}) continue;")?,
+                        _ => {
+                            writeln!(f, "if ({condition}) {{")?;
+                            f.indent();
+                            self.statements(f, then)?;
+                            f.deindent();
+                            if !otherwise.is_empty() {
+                                writeln!(f, "}} else {{")?;
+                                f.indent();
+                                self.statements(f, otherwise)?;
+                                f.deindent();
+                            }
+                            writeln!(f, "}}")?;
+                        }
+                    }
+                }
+                Stmt::Loop(body) => {
+                    writeln!(f, "while (true) {{")?;
+                    f.indent();
+                    self.statements(f, body)?;
+                    f.deindent();
+                    writeln!(f, "}}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// writes a jump from the block `from` to `to`, nothing if `to` comes right after
+    fn jump<W: Write>(&self, f: &mut IW<W>, from: usize, to: BlockId) -> io::Result<()> {
+        if to.0 != from + 1 {
+            writeln!(f, "goto {to};")?;
+        }
+        Ok(())
+    }
+
+    /// writes the code suspending the script, until it is resumed at `at`
+    fn suspend<W: Write>(&self, f: &mut IW<W>, at: BlockId) -> io::Result<()> {
+        let state = self.states[at.0].expect("blocks the function is resumed at have a state");
+        write_release(f, &self.release)?;
+        writeln!(f, "s->state = {state};")?;
+        writeln!(f, "return false;")
+    }
+
+    fn terminator<W: Write>(&self, f: &mut IW<W>, from: usize, terminator: &Terminator) -> io::Result<()> {
+        let kind = self.function.kind;
+        if matches!(terminator, Terminator::Return | Terminator::Stop | Terminator::StopAll) {
+            write_release(f, &self.release)?;
+        }
+        match terminator {
+            Terminator::Jump(to) => self.jump(f, from, *to),
+            Terminator::Branch(condition, then, otherwise) => {
+                let condition = self.operand(condition);
+                if then.0 == from + 1 {
+                    writeln!(f, "if (!{condition}) goto {otherwise};")
+                } else {
+                    writeln!(f, "if ({condition}) goto {then};")?;
+                    self.jump(f, from, *otherwise)
+                }
+            }
+            // warp procedures don't leave, they only redraw the screen once they timed out
+            Terminator::Yield(to) | Terminator::LoopBack(to) if self.warp() => {
+                writeln!(f, "warp_check(g);")?;
+                self.jump(f, from, *to)
+            }
+            Terminator::Yield(to) => self.suspend(f, *to),
+            Terminator::LoopBack(to) => {
+                writeln!(f, "if (should_yield(g)) {{")?;
+                f.indent();
+                self.suspend(f, *to)?;
+                f.deindent();
+                writeln!(f, "}}")?;
+                self.jump(f, from, *to)
+            }
+            Terminator::WaitForStart(_) => {
+                // the state is set by whatever starts the script
+                write_release(f, &self.release)?;
+                writeln!(f, "return false;")
+            }
+            Terminator::Call { procedure, args, resume } => {
+                let state_type = format!("{}Proc{procedure}State", self.target.name);
+                if args.is_empty() {
+                    writeln!(f, "call_stack_push(stack, {procedure}, sizeof({state_type}));")?;
+                } else {
+                    writeln!(f, "{{")?;
+                    f.indent();
+                    writeln!(f, "{state_type} *call = call_stack_push(stack, {procedure}, sizeof({state_type}));")?;
+                    for (i, arg) in args.iter().enumerate() {
+                        writeln!(f, "call->args[{i}] = copy_value({});", self.operand(arg))?;
+                    }
+                    f.deindent();
+                    writeln!(f, "}}")?;
+                }
+                match kind {
+                    // the procedure runs right away, and the script continues once the stack is empty
+                    FunctionKind::Sequence(_) => {
+                        writeln!(f, "s->state = {};", self.states[resume.0].expect("resumed blocks have a state"))?;
+                        procedures::run_call_stack(f, self.target, &self.release)?;
+                        self.jump(f, from, *resume)
+                    }
+                    // the call stack runs the called procedure
+                    _ => self.suspend(f, *resume),
+                }
+            }
+            Terminator::Return => match kind {
+                // go back to the hat block, so the script can be started again
+                FunctionKind::Sequence(_) => writeln!(f, "s->state = 0;\nreturn true;"),
+                FunctionKind::Procedure(_) => writeln!(f, "return true;"),
+                FunctionKind::WarpProcedure(_) => writeln!(f, "return false;"),
+            },
+            Terminator::Stop => match kind {
+                FunctionKind::Sequence(_) => writeln!(f, "s->state = 0;\nreturn true;"),
+                // the script running the procedure is stopped once the procedure returns, freeing its call stack
+                FunctionKind::Procedure(_) => writeln!(f, "stack->stopped = true;\nreturn true;"),
+                // the caller of the warp procedure stops the script
+                FunctionKind::WarpProcedure(_) => writeln!(f, "return true;"),
+            },
+            Terminator::StopAll => match kind {
+                // `stop_all` empties the call stack, the script running the procedure must see that it stopped
+                FunctionKind::Procedure(_) => writeln!(f, "stop_all(g);\nstack->stopped = true;\nreturn true;"),
+                _ => writeln!(f, "stop_all(g);\nreturn true;"),
+            },
+        }
+    }
+}
+
+/// numbers the blocks a function can be resumed at.
+/// The entry is 0, and scripts started from outside of their hat continue at 1.
+fn resume_states(function: &ir::Function) -> Vec<Option<u32>> {
+    let mut states = vec![None; function.blocks.len()];
+    states[0] = Some(0);
+    let mut next = 1;
+    if let Terminator::WaitForStart(start) = function.blocks[0].terminator {
+        states[start.0] = Some(1);
+        next = 2;
+    }
+    for block in &function.blocks {
+        if let Some(resume) = block.terminator.resume_point() {
+            if states[resume.0].is_none() {
+                states[resume.0] = Some(next);
+                next += 1;
+            }
+        }
+    }
+    states
+}
+
+/// Writes the statements of a lowered function.
+/// The function has an actor `a`, a state `s` and the global state `g`, with a call stack `stack` in procedures.
+pub fn write_function<W: Write>(
+    f: &mut IW<W>,
+    target: &parser::Target,
+    function: &ir::Function,
+    pool: &StringPool,
+) -> io::Result<()> {
+    let warp = matches!(function.kind, FunctionKind::WarpProcedure(_));
+    let states = if warp { vec![None; function.blocks.len()] } else { resume_states(function) };
+
+    let items = structure::structure(function, &states);
+    let mut labels: Vec<bool> = states.iter().map(|s| matches!(s, Some(state) if *state > 0)).collect();
+    for item in &items {
+        // structured statements are only entered from their first block
+        if let Item::Block(i) = *item {
+            for to in structure::flow(function, i) {
+                if to != i + 1 {
+                    labels[to] = true;
+                }
+            }
+        }
+    }
+    let release = function
+        .temps
+        .iter()
+        .enumerate()
+        .filter(|(_, ty)| **ty == Type::Value)
+        .map(|(i, _)| format!("free_value(t{i});"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let writer = FunctionWriter { target, function, states, labels, pool, release };
+
+    if let FunctionKind::Sequence(_) = function.kind {
+        if !target.procedures.is_empty() {
+            writeln!(f, "CallStack *stack = &s->stack;")?;
+            // nothing was allocated yet
+            procedures::run_call_stack(f, target, "")?;
+        }
+    }
+    for (i, ty) in function.temps.iter().enumerate() {
+        let zero = match ty {
+            Type::Number => "0",
+            Type::Bool => "false",
+            Type::Value => "{ 0 }",
+        };
+        writeln!(f, "{} t{i} = {zero};", c_type(*ty))?;
+    }
+    if writer.states.iter().any(|s| matches!(s, Some(state) if *state > 0)) {
+        // continue where the script was suspended, the entry comes right after
+        writeln!(f, "switch (s->state) {{")?;
+        for (i, state) in writer.states.iter().enumerate() {
+            if let Some(state) = state.filter(|&s| s > 0) {
+                writeln!(f, "case {state}: goto {};", BlockId(i))?;
+            }
+        }
+        writeln!(f, "}}")?;
+    }
+
+    for item in &items {
+        let (Item::Block(i) | Item::Structured(i, _)) = *item;
+        let block = &function.blocks[i];
+        if writer.labels[i] {
+            let empty = block.insts.is_empty() && block.terminator == Terminator::Jump(BlockId(i + 1));
+            // a label needs a statement after it
+            writeln!(f, "{}:{}", BlockId(i), if empty { " ;" } else { "" })?;
+        }
+        match item {
+            Item::Block(_) => {
+                for inst in &block.insts {
+                    writer.inst(f, inst)?;
+                }
+                writer.terminator(f, i, &block.terminator)?;
+            }
+            Item::Structured(_, stmts) => writer.statements(f, stmts)?,
+        }
+    }
+    Ok(())
+}
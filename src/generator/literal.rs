@@ -0,0 +1,88 @@
+//! C literals of the project's strings and numbers, which can hold anything scratch allows
+
+use std::fmt::Write;
+
+/// a C string literal of `s`.
+/// Every byte that isn't printable ascii is escaped in octal, which can't swallow the digits after it like hex escapes do.
+/// The runtime works on NUL-terminated strings, so a string containing NUL is cut there.
+pub fn string(s: &str) -> String {
+    let mut literal = String::with_capacity(s.len() + 2);
+    literal.push('"');
+    let mut previous = 0;
+    for &byte in s.as_bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'\n' => literal.push_str("\\n"),
+            b'\t' => literal.push_str("\\t"),
+            // `??` starts a trigraph
+            b'?' if previous == b'?' => literal.push_str("\\?"),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{byte:03o}").expect("writing to a string can't fail"),
+        }
+        previous = byte;
+    }
+    literal.push('"');
+    literal
+}
+
+/// a C literal of the double `n`, using the macros of `math.h` for infinities and NaN
+pub fn number(n: f64) -> String {
+    if n.is_nan() {
+        "NAN".to_owned()
+    } else if n.is_infinite() {
+        if n > 0.0 { "INFINITY" } else { "-INFINITY" }.to_owned()
+    } else {
+        // debug formatting never loses precision, and always has a `.` or an exponent
+        format!("{n:?}")
+    }
+}
+
+/// a `/* */` comment of `s`, on a single line.
+/// A `//` comment ending in `\` (or the trigraph `??/`) would continue on the next line, so block comments are used,
+/// where the only thing to escape is the `*/` ending them.
+pub fn comment(s: &str) -> String {
+    let text = s.replace(|c: char| c.is_control(), " ").replace("*/", "* /").replace("/*", "/ *");
+    format!("/* {text} */")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings() {
+        assert_eq!(string("hello"), r#""hello""#);
+        // `??=` would be the trigraph of `#`
+        assert_eq!(string("??="), r#""?\?=""#);
+        assert_eq!(string("???"), r#""?\?\?""#);
+        assert_eq!(string("\n"), r#""\n""#);
+        assert_eq!(string("\\"), r#""\\""#);
+        assert_eq!(string("\"\t"), r#""\"\t""#);
+        assert_eq!(string("é"), r#""\303\251""#);
+        // octal escapes stop after 3 digits
+        assert_eq!(string("\u{0}1"), r#""\0001""#);
+        // the runtime's strings end at the first NUL
+        assert_eq!(string("a\u{0}b"), r#""a\000b""#);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(comment("costume1"), "/* costume1 */");
+        // neither can continue the comment on the next line
+        assert_eq!(comment("a\\"), r"/* a\ */");
+        assert_eq!(comment("a??/"), "/* a??/ */");
+        assert_eq!(comment("a*/b/*c"), "/* a* /b/ *c */");
+        assert_eq!(comment("/*/"), "/* / * / */");
+        assert_eq!(comment("a\nb"), "/* a b */");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(number(1.0), "1.0");
+        assert_eq!(number(-0.0), "-0.0");
+        assert_eq!(number(1e21), "1e21");
+        assert_eq!(number(f64::NAN), "NAN");
+        assert_eq!(number(f64::NEG_INFINITY), "-INFINITY");
+    }
+}
@@ -1,140 +1,69 @@
 use std::io::{self, Write};
 
-use crate::parser::{self, Block, Value, VarMap};
+use crate::ir;
+use crate::parser::{self, Globals};
 
 mod util;
 use util::*;
 
+mod function;
+mod structure;
+mod literal;
+mod strings;
+use strings::StringPool;
+
 mod motion;
-mod looks;
 mod event;
 mod control;
-mod operator;
-mod data;
-
-/// returns the variable name of the returned value
-fn compute_value<W: Write>(f: &mut IW<W>, args: &mut GeneratorArgs, value: &Value) -> io::Result<String> {
-    let v = generate_var_name();
-    match value {
-        Value::Block(b) => {
-            let v = linearize_block(f, args, &b)?;
-            match v {
-                Return::Value(v) => return Ok(v),
-                _ => unreachable!("expected a block that returns a value:\n{b:#?}")
-            }
-        }
-        Value::Number(n) => {
-            writeln!(f, "Value {v} = (Value){{ .type = VALUE_NUM, .n = {n} }};")?;
-        }
-        Value::Color(c) => {
-            writeln!(
-                f,
-                "Value {v} = (Value){{ .type = VALUE_COLOR, .c = (ValueColor){{ {}, {}, {} }} }};",
-                c.0, c.1, c.2
-            )?;
-        }
-        Value::String(s) => {
-            writeln!(f, "Value {v} = (Value){{ .type = VALUE_STRING, .s = create_rcstr(\"{s}\") }};")?;
-        }
-        Value::Broadcast(_b) => {
-            todo!()
-        }
-        Value::Variable(var) => {
-            writeln!(f, "Value {} = {}; // {}", v, get_var(args, &var.id), var.name)?;
-        }
-        Value::List(_l) => {
-            todo!()
-        }
-    }
-    Ok(v)
-}
-
-trait Linearize {
-    fn linearize<W: Write>(&self, f: &mut IW<W>, args: &mut GeneratorArgs) -> io::Result<Return>;
-}
-
-/// returns the variable name of the returned value (if the block is an expression)
-fn linearize_block<W: Write>(f: &mut IW<W>, args: &mut GeneratorArgs, block: &Block) -> io::Result<Return> {
-    let ret = match block {
-        Block::Motion(v) => v.linearize(f, args),
-        Block::Looks(v) => v.linearize(f, args),
-        Block::Event(v) => v.linearize(f, args),
-        Block::Control(v) => v.linearize(f, args),
-        Block::Operator(v) => v.linearize(f, args),
-        Block::Data(v) => v.linearize(f, args),
-    }?;
-
-    match ret {
-        Return::Empty => writeln!(f, "s->state = {};", *args.state + 1)?,
-        Return::Value(_) => unreachable!("block returning a value used as a statement:\n{block:#?}"),
-        _ => ()
-    }
-    Ok(ret)
-}
-
-fn linearize_sequence<W: Write>(f: &mut IW<W>, args: &mut GeneratorArgs, sequence: &parser::Sequence) -> io::Result<()> {
-    for block in &sequence.0 {
-        start_case(f, args.state)?;
-        let ret = linearize_block(f, args, block)?;
-        match ret {
-            Return::Empty | Return::Hold => end_case(f, args.state)?,
-            Return::Value(_) => unreachable!(),
-            Return::Ended => ()
-        }
-    }
+mod procedures;
 
-    Ok(())
-}
-
-fn linearize<W: Write>(
+fn generate_sequence<W: Write>(
     header: &mut IW<W>,
     source: &mut IW<W>,
     target: &parser::Target,
-    globals: &VarMap,
     sequence_index: usize,
+    function: &ir::Function,
+    pool: &StringPool,
 ) -> io::Result<()> {
-    let mut state = 0;
-    let mut new_locals = Vec::new();
-    let mut args = GeneratorArgs {
-        state: &mut state,
-        new_locals: &mut new_locals,
-        globals,
-        target,
-    };
-    writeln!(source, "/// Returns wether the sequence has finished running.")?;
+    writeln!(header, "typedef struct {{")?;
+    header.indent();
+    writeln!(header, "int state;")?;
+    if !target.procedures.is_empty() {
+        writeln!(header, "CallStack stack;")?;
+    }
+    function::write_locals(header, function)?;
+    header.deindent();
+    writeln!(header, "}} {}Sequence{sequence_index}State;", target.name)?;
+    writeln!(header)?;
+
+    writeln!(source, "/// Runs the sequence until it yields, returns wether it has finished running.")?;
     writeln!(
         source,
         "bool run_{}_sequence{sequence_index}(Actor{} *a, {}Sequence{sequence_index}State *s, GlobalState *g) {{",
         target.name, target.name, target.name
     )?;
     source.indent();
-    writeln!(source, "switch (s->state) {{")?;
-    linearize_sequence(source, &mut args, &target.sequences[sequence_index])?;
-    start_case(source, &mut state)?;
-    writeln!(source, "return true;")?;
-    end_case(source, &mut state)?;
-    writeln!(source, "}}")?;
-    writeln!(source, "return false;")?;
+    function::write_function(source, target, function, pool)?;
     source.deindent();
     writeln!(source, "}}")?;
-
-    writeln!(header, "typedef struct {{")?;
-    source.indent();
-    writeln!(header, "int state;")?;
-    writeln!(header, "float time;")?;
-    for local in new_locals {
-        writeln!(header, "int {local};")?;
-    }
-    source.deindent();
-    writeln!(header, "}} {}Sequence{sequence_index}State;", target.name)?;
-    writeln!(header)?;
+    writeln!(source)?;
 
     Ok(())
 }
 
-fn generate_target<W: Write>(header: &mut IW<W>, source: &mut IW<W>, target: &parser::Target, globals: &VarMap) -> io::Result<()> {
-    for i in 0..target.sequences.len() {
-        linearize(header, source, target, globals, i)?;
+fn generate_target<W: Write>(
+    header: &mut IW<W>,
+    source: &mut IW<W>,
+    target: &parser::Target,
+    lowered: &ir::Target,
+    pool: &StringPool,
+) -> io::Result<()> {
+    // sequences run the procedures on their call stack
+    if !target.procedures.is_empty() {
+        procedures::generate_procedures(header, source, target, lowered, pool)?;
+    }
+    for (i, function) in lowered.sequences.iter().enumerate() {
+        generate_sequence(header, source, target, i, function, pool)?;
     }
 
     match &target.kind {
@@ -154,8 +83,11 @@ fn generate_target<W: Write>(header: &mut IW<W>, source: &mut IW<W>, target: &pa
             writeln!(header, "typedef struct {{")?;
             header.indent();
             writeln!(header, "ActorState actor_state;")?;
-            for (_, v) in &target.vars {
-                writeln!(header, "Value var_{v};")?;
+            for v in target.vars.values() {
+                writeln!(header, "{} var_{};", function::c_type(lowered.vars[&v.cident]), v.cident)?;
+            }
+            for l in target.lists.values() {
+                writeln!(header, "ValueList lst_{};", l.cident)?;
             }
             for i in 0..target.sequences.len() {
                 writeln!(header, "{}Sequence{i}State sequence{i}_state;", target.name)?;
@@ -166,28 +98,34 @@ fn generate_target<W: Write>(header: &mut IW<W>, source: &mut IW<W>, target: &pa
     }
     writeln!(header)?;
 
-    writeln!(source, "/// update every sequence of this actor")?;
-    writeln!(
-        source,
-        "void run_{}(Actor{} *a, GlobalState *g) {{",
-        target.name, target.name
-    )?;
-    source.indent();
-    for i in 0..target.sequences.len() {
-        writeln!(source, "run_{}_sequence{i}(a, &a->sequence{i}_state, g);", target.name)?;
+    for (i, costume) in target.costumes.iter().enumerate() {
+        writeln!(source, "{}", literal::comment(&costume.filename))?;
+        write_byte_array(source, &format!("asset_{}_{i}", target.name), &costume.data)?;
     }
-    source.deindent();
-    writeln!(source, "}}")?;
     writeln!(source)?;
 
     writeln!(source, "Sprite sprites_{}[{}] = {{ 0 }};", target.name, target.costumes.len())?;
     writeln!(source, "void init_sprites_{}() {{", target.name)?;
     source.indent();
     for (i, costume) in target.costumes.iter().enumerate() {
-        writeln!(source, "// Load {}", costume.name)?;
+        // raylib uses the extension to know how to decode the image
+        let extension = costume.filename.rsplit_once('.').map_or("", |(_, ext)| ext);
+        writeln!(source, "{}", literal::comment(&format!("Load {}", costume.name)))?;
         writeln!(source, "sprites_{}[{i}].rotation_center_x = {};", target.name, costume.rotation_center_x)?;
         writeln!(source, "sprites_{}[{i}].rotation_center_y = {};", target.name, costume.rotation_center_y)?;
-        writeln!(source, "sprites_{}[{i}].texture = LoadTexture(\"project/{}\");", target.name, costume.filename)?;
+        writeln!(source, "{{")?;
+        source.indent();
+        writeln!(
+            source,
+            "Image image = LoadImageFromMemory({}, asset_{}_{i}, sizeof(asset_{}_{i}));",
+            literal::string(&format!(".{extension}")),
+            target.name,
+            target.name
+        )?;
+        writeln!(source, "sprites_{}[{i}].texture = LoadTextureFromImage(image);", target.name)?;
+        writeln!(source, "UnloadImage(image);")?;
+        source.deindent();
+        writeln!(source, "}}")?;
     }
     source.deindent();
     writeln!(source, "}}")?;
@@ -195,12 +133,20 @@ fn generate_target<W: Write>(header: &mut IW<W>, source: &mut IW<W>, target: &pa
     Ok(())
 }
 
-pub fn generate<W: Write>(header: &mut W, source: &mut W, targets: &[parser::Target], globals: &VarMap) -> io::Result<()> {
+/// Generates the C code of the project, from its scripts lowered by [`ir::lower`].
+pub fn generate<W: Write>(
+    header: &mut W,
+    source: &mut W,
+    targets: &[parser::Target],
+    program: &ir::Program,
+    globals: &Globals,
+) -> io::Result<()> {
     let header = &mut IW::new(header);
     let source = &mut IW::new(source);
 
     writeln!(header, "#include <stdio.h>")?;
     writeln!(header, "#include <string.h>")?;
+    writeln!(header, "#include <strings.h>")?;
     writeln!(header, "#include <math.h>")?;
     writeln!(header, "#include \"runtime.h\"")?;
     writeln!(header)?;
@@ -208,35 +154,62 @@ pub fn generate<W: Write>(header: &mut W, source: &mut W, targets: &[parser::Tar
     writeln!(source, "#include \"output.h\"")?;
     writeln!(source)?;
 
-    for target in targets {
-        generate_target(header, source, target, &globals)?;
+    let pool = StringPool::collect(targets, program, globals);
+    pool.write(source)?;
+
+    for (target, lowered) in targets.iter().zip(&program.targets) {
+        generate_target(header, source, target, lowered, &pool)?;
     }
 
-    writeln!(header, "typedef struct {{")?;
+    // named, so the runtime can declare functions taking it
+    writeln!(header, "typedef struct GlobalState {{")?;
     header.indent();
 
     writeln!(header, "bool flag_clicked;")?;
-    for global in globals.values() {
-        writeln!(header, "Value var_{global};")?;
+    event::generate_broadcast_fields(header, targets)?;
+    writeln!(header, "int num_clones;")?;
+    writeln!(header, "/// time at which the running warp procedure redraws the screen")?;
+    writeln!(header, "double warp_deadline;")?;
+    writeln!(header, "/// set when an actor changed on screen, loops yield until the next frame")?;
+    writeln!(header, "bool redraw_requested;")?;
+    writeln!(header, "/// time at which loops yield, even if nothing needs to be redrawn")?;
+    writeln!(header, "double work_deadline;")?;
+    for global in globals.vars.values() {
+        writeln!(header, "{} var_{};", function::c_type(program.global_vars[&global.cident]), global.cident)?;
+    }
+    for global in globals.lists.values() {
+        writeln!(header, "ValueList lst_{};", global.cident)?;
     }
     writeln!(header, "ActorStage stage;")?;
     for target in targets {
         if matches!(target.kind, parser::TargetKind::Stage { .. }) { continue }
 
+        // every actor is allocated separately, so pointers to them stay valid when clones are added
         writeln!(header, "int num_{};", target.name)?;
-        writeln!(header, "Actor{} *list_{};", target.name, target.name)?;
+        writeln!(header, "int cap_{};", target.name)?;
+        writeln!(header, "Actor{} **list_{};", target.name, target.name)?;
     }
 
     header.deindent();
     writeln!(header, "}} GlobalState;")?;
     writeln!(header)?;
 
-    generate_global_functions(header, source, targets, globals)?;
+    event::generate_broadcast_functions(header, source, targets)?;
+    control::generate_clone_functions(header, source, targets, program)?;
+    control::generate_stop_all(header, source, targets)?;
+    generate_global_functions(header, source, targets, program, globals, &pool)?;
 
     Ok(())
 }
 
-fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, targets: &[parser::Target], globals: &VarMap) -> io::Result<()> {
+fn generate_global_functions<W: Write>(
+    header: &mut IW<W>,
+    source: &mut IW<W>,
+    targets: &[parser::Target],
+    program: &ir::Program,
+    globals: &Globals,
+    pool: &StringPool,
+) -> io::Result<()> {
     writeln!(header, "GlobalState init_global();")?;
     writeln!(header, "void run_global(GlobalState *g);")?;
     writeln!(header, "void render_global(GlobalState *g);")?;
@@ -252,7 +225,7 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
     writeln!(source)?;
 
     writeln!(source, "// Initialize every actor")?;
-    for target in targets {
+    for (target, lowered) in targets.iter().zip(&program.targets) {
         match &target.kind {
             parser::TargetKind::Stage { tempo } => {
                 writeln!(source, "ActorStage stage = {{")?;
@@ -266,24 +239,31 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
                 source.deindent();
                 writeln!(source, "}};")?;
             },
-            parser::TargetKind::Sprite { visible, x, y, size, direction, draggable: _, rotation_style: _ } => {
+            parser::TargetKind::Sprite { visible, x, y, size, direction, rotation_style } => {
                 let name = &target.name;
-                writeln!(source, "Actor{name} *list_{name} = malloc(sizeof(Actor{name}));")?;
-                // TODO: Initalize default variable value
+                writeln!(source, "Actor{name} *original_{name} = malloc(sizeof(Actor{name}));")?;
+                for var in target.vars.values() {
+                    let value = typed_literal(&var.value, lowered.vars[&var.cident], pool);
+                    writeln!(source, "original_{name}->var_{} = {value};", var.cident)?;
+                }
 
-                writeln!(source, "list_{name}->actor_state = (ActorState) {{")?;
+                writeln!(source, "original_{name}->actor_state = (ActorState) {{")?;
                 source.indent();
                 writeln!(source, ".x = {x},")?;
                 writeln!(source, ".y = {y},")?;
                 writeln!(source, ".size = {size},")?;
                 writeln!(source, ".direction = {direction},")?;
+                writeln!(source, ".rotation_style = {},", motion::rotation_style(rotation_style))?;
                 writeln!(source, ".visible = {visible},")?;
                 writeln!(source, ".sprite_index = {},", target.current_costume)?;
                 writeln!(source, ".sprites = sprites_{name}")?;
                 source.deindent();
                 writeln!(source, "}};")?;
                 for i in 0..target.sequences.len() {
-                    writeln!(source, "list_{name}->sequence{i}_state = ({name}Sequence{i}State) {{ 0 }};")?;
+                    writeln!(source, "original_{name}->sequence{i}_state = ({name}Sequence{i}State) {{ 0 }};")?;
+                }
+                for list in target.lists.values() {
+                    init_list(source, &format!("original_{name}->lst_{}", list.cident), list, pool)?;
                 }
             },
         }
@@ -291,13 +271,12 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
     }
 
     writeln!(source, "// Initialize global state")?;
-    writeln!(source, "return (GlobalState) {{")?;
+    writeln!(source, "GlobalState g = {{")?;
     source.indent();
 
     writeln!(source, ".flag_clicked = false,")?;
-    for var in globals.values() {
-        // TODO: Initialize default variable value
-        writeln!(source, ".var_{var} = (Value) {{ .type = VALUE_NUM, .n = 0 }},")?;
+    for var in globals.vars.values() {
+        writeln!(source, ".var_{} = {},", var.cident, typed_literal(&var.value, program.global_vars[&var.cident], pool))?;
     }
 
     for target in targets {
@@ -306,12 +285,22 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
         } else {
             let name = &target.name;
             writeln!(source, ".num_{name} = 1,")?;
-            writeln!(source, ".list_{name} = list_{name},")?;
+            writeln!(source, ".cap_{name} = 1,")?;
+            writeln!(source, ".list_{name} = malloc(sizeof(Actor{name} *)),")?;
         }
     }
 
     source.deindent();
     writeln!(source, "}};")?;
+    for target in targets {
+        if let parser::TargetKind::Sprite { .. } = target.kind {
+            writeln!(source, "g.list_{0}[0] = original_{0};", target.name)?;
+        }
+    }
+    for list in globals.lists.values() {
+        init_list(source, &format!("g.lst_{}", list.cident), list, pool)?;
+    }
+    writeln!(source, "return g;")?;
 
     source.deindent();
     writeln!(source, "}}")?;
@@ -319,6 +308,10 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
 
     writeln!(source, "void run_global(GlobalState *g) {{")?;
     source.indent();
+    writeln!(source, "g->redraw_requested = false;")?;
+    writeln!(source, "g->work_deadline = GetTime() + WORK_TIME;")?;
+    writeln!(source, "start_broadcasts(g);")?;
+    writeln!(source)?;
     for target in targets {
         match target.kind {
             parser::TargetKind::Stage { .. } => {
@@ -330,8 +323,10 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
                 let name = &target.name;
                 writeln!(source, "for (int i = 0; i < g->num_{name}; i++) {{")?;
                 source.indent();
-                writeln!(source, "Actor{name} *a = &g->list_{name}[i];")?;
+                writeln!(source, "Actor{name} *a = g->list_{name}[i];")?;
                 for i in 0..target.sequences.len() {
+                    // a deleted clone stops running immediately
+                    writeln!(source, "if (a->actor_state.deleted) continue;")?;
                     writeln!(source, "run_{name}_sequence{i}(a, &a->sequence{i}_state, g);")?;
                 }
                 source.deindent();
@@ -340,6 +335,7 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
         }
         writeln!(source)?;
     }
+    writeln!(source, "remove_deleted_clones(g);")?;
     source.deindent();
     writeln!(source, "}}")?;
     writeln!(source)?;
@@ -356,7 +352,7 @@ fn generate_global_functions<W: Write>(header: &mut IW<W>, source: &mut IW<W>, t
         let name = &target.name;
         writeln!(source, "for (int i = 0; i < g->num_{name}; i++) {{")?;
         source.indent();
-        writeln!(source, "draw_actor(&g->list_{name}[i].actor_state);")?;
+        writeln!(source, "draw_actor(&g->list_{name}[i]->actor_state);")?;
         source.deindent();
         writeln!(source, "}}")?;
         writeln!(source)?;
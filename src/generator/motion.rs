@@ -1,20 +1,10 @@
-use std::io::{self, Write};
-use crate::parser::Motion;
-use super::{compute_value, Linearize, util::*};
+use crate::parser::RotationStyle;
 
-impl Linearize for Motion {
-    fn linearize<W: Write>(&self, f: &mut IW<W>, args: &mut GeneratorArgs) -> io::Result<Return> {
-        match self {
-            Motion::MoveSteps { steps } => {
-                let steps = compute_value(f, args, steps)?;
-                writeln!(f, "convert_to_number(&{steps});")?;
-                writeln!(f, "float direction = scratch_degrees_to_radians(a->actor_state.direction);")?;
-                writeln!(f, "a->actor_state.x += cosf(direction)*{steps}.n;")?;
-                writeln!(f, "a->actor_state.y += sinf(direction)*{steps}.n;")?;
-            }
-            _ => todo!()
-        }
-
-        Ok(Return::Empty)
+/// C enum value of a rotation style (see `RotationStyle` in `runtime.h`)
+pub fn rotation_style(style: &RotationStyle) -> &'static str {
+    match style {
+        RotationStyle::AllAround => "ROTATION_ALL_AROUND",
+        RotationStyle::LeftRight => "ROTATION_LEFT_RIGHT",
+        RotationStyle::DontRotate => "ROTATION_DONT_ROTATE",
     }
 }
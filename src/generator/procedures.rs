@@ -0,0 +1,206 @@
+use std::io::{self, Write};
+use crate::ir;
+use crate::parser;
+use super::{function::{self, write_release}, literal, strings::StringPool, util::*};
+
+/// writes the start of every step of a sequence, which runs the procedures on the call stack before the sequence.
+/// The sequence continues once the stack is empty, `release` is written before leaving the sequence.
+pub fn run_call_stack<W: Write>(f: &mut IW<W>, target: &parser::Target, release: &str) -> io::Result<()> {
+    writeln!(f, "if (stack->len > 0) {{")?;
+    f.indent();
+    writeln!(f, "run_{}_call_stack(a, stack, g);", target.name)?;
+    writeln!(f, "if (stack->stopped) {{")?;
+    f.indent();
+    writeln!(f, "call_stack_clear(stack, free_{}_frame);", target.name)?;
+    write_release(f, release)?;
+    writeln!(f, "s->state = 0;")?;
+    writeln!(f, "return true;")?;
+    f.deindent();
+    writeln!(f, "}}")?;
+    // a procedure yielded
+    if release.is_empty() {
+        writeln!(f, "if (stack->len > 0) return false;")?;
+    } else {
+        writeln!(f, "if (stack->len > 0) {{")?;
+        f.indent();
+        write_release(f, release)?;
+        writeln!(f, "return false;")?;
+        f.deindent();
+        writeln!(f, "}}")?;
+    }
+    f.deindent();
+    writeln!(f, "}}")
+}
+
+/// writes a procedure run without screen refresh, as a plain C function
+fn generate_warp_procedure<W: Write>(
+    header: &mut IW<W>,
+    source: &mut IW<W>,
+    target: &parser::Target,
+    index: usize,
+    function: &ir::Function,
+    pool: &StringPool,
+) -> io::Result<()> {
+    let procedure = &target.procedures[index];
+    let name = &target.name;
+
+    // only the arguments and the locals, the procedure has no states
+    writeln!(header, "typedef struct {{")?;
+    header.indent();
+    if !procedure.arguments.is_empty() {
+        writeln!(header, "Value args[{}];", procedure.arguments.len())?;
+    }
+    function::write_locals(header, function)?;
+    if procedure.arguments.is_empty() && function.locals.is_empty() {
+        // C structs can't be empty
+        writeln!(header, "char unused;")?;
+    }
+    header.deindent();
+    writeln!(header, "}} {name}Proc{index}WarpState;")?;
+    writeln!(header)?;
+
+    writeln!(source, "{}", literal::comment(&procedure.proccode))?;
+    writeln!(source, "/// Runs the whole procedure, returns wether the script was stopped.")?;
+    writeln!(
+        source,
+        "bool run_{name}_proc{index}_warp(Actor{name} *a, {name}Proc{index}WarpState *s, GlobalState *g, CallStack *stack) {{"
+    )?;
+    source.indent();
+    function::write_function(source, target, function, pool)?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    Ok(())
+}
+
+fn generate_procedure<W: Write>(
+    header: &mut IW<W>,
+    source: &mut IW<W>,
+    target: &parser::Target,
+    index: usize,
+    function: &ir::Function,
+    pool: &StringPool,
+) -> io::Result<()> {
+    let procedure = &target.procedures[index];
+    let name = &target.name;
+
+    writeln!(header, "typedef struct {{")?;
+    header.indent();
+    writeln!(header, "int state;")?;
+    if !procedure.arguments.is_empty() {
+        writeln!(header, "Value args[{}];", procedure.arguments.len())?;
+    }
+    function::write_locals(header, function)?;
+    header.deindent();
+    writeln!(header, "}} {name}Proc{index}State;")?;
+    writeln!(header)?;
+
+    writeln!(source, "{}", literal::comment(&procedure.proccode))?;
+    writeln!(source, "/// Runs the procedure until it yields or calls another one, returns wether it has finished running.")?;
+    writeln!(
+        source,
+        "bool run_{name}_proc{index}(Actor{name} *a, {name}Proc{index}State *s, GlobalState *g, CallStack *stack) {{"
+    )?;
+    source.indent();
+    function::write_function(source, target, function, pool)?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    Ok(())
+}
+
+/// Generates the custom blocks of a target, with the functions running and freeing the frames of a call stack.
+/// Every call pushes a new frame, so recursive procedures each have their own state.
+pub fn generate_procedures<W: Write>(
+    header: &mut IW<W>,
+    source: &mut IW<W>,
+    target: &parser::Target,
+    lowered: &ir::Target,
+    pool: &StringPool,
+) -> io::Result<()> {
+    let name = &target.name;
+    // procedures can stop other scripts, which frees their frames
+    writeln!(header, "void free_{name}_frame(CallFrame *frame);")?;
+    writeln!(header)?;
+
+    // warp procedures call each other directly
+    for (i, _) in lowered.warp_procedures.iter().enumerate().filter(|(_, f)| f.is_some()) {
+        writeln!(
+            source,
+            "bool run_{name}_proc{i}_warp(Actor{name} *a, {name}Proc{i}WarpState *s, GlobalState *g, CallStack *stack);"
+        )?;
+    }
+    writeln!(source)?;
+
+    for i in 0..target.procedures.len() {
+        if let Some(function) = &lowered.warp_procedures[i] {
+            generate_warp_procedure(header, source, target, i, function, pool)?;
+        }
+        // warp procedures are never pushed on a call stack
+        if let Some(function) = &lowered.procedures[i] {
+            generate_procedure(header, source, target, i, function, pool)?;
+        }
+    }
+
+    writeln!(source, "void free_{name}_frame(CallFrame *frame) {{")?;
+    source.indent();
+    writeln!(source, "switch (frame->procedure) {{")?;
+    for (i, procedure) in target.procedures.iter().enumerate() {
+        if procedure.warp || procedure.arguments.is_empty() { continue }
+
+        writeln!(source, "case {i}: {{")?;
+        source.indent();
+        writeln!(source, "{name}Proc{i}State *s = frame->state;")?;
+        for j in 0..procedure.arguments.len() {
+            writeln!(source, "free_value(s->args[{j}]);")?;
+        }
+        source.deindent();
+        writeln!(source, "}} break;")?;
+    }
+    writeln!(source, "}}")?;
+    writeln!(source, "free(frame->state);")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    writeln!(source, "/// Runs the procedures called by a script, until one of them yields.")?;
+    writeln!(source, "void run_{name}_call_stack(Actor{name} *a, CallStack *stack, GlobalState *g) {{")?;
+    source.indent();
+    writeln!(source, "while (stack->len > 0 && !stack->stopped) {{")?;
+    source.indent();
+    writeln!(source, "int top = stack->len - 1;")?;
+    writeln!(source, "CallFrame frame = stack->frames[top];")?;
+    writeln!(source, "bool finished = false;")?;
+    writeln!(source, "switch (frame.procedure) {{")?;
+    for (i, procedure) in target.procedures.iter().enumerate() {
+        if procedure.warp { continue }
+
+        writeln!(source, "case {i}: finished = run_{name}_proc{i}(a, frame.state, g, stack); break;")?;
+    }
+    writeln!(source, "}}")?;
+    writeln!(source, "if (finished) {{")?;
+    source.indent();
+    // "stop all" clears every stack, including this one
+    writeln!(source, "if (stack->len > top) {{")?;
+    source.indent();
+    writeln!(source, "free_{name}_frame(&stack->frames[top]);")?;
+    writeln!(source, "stack->len = top;")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    source.deindent();
+    // the procedure either yielded, or called another one, which runs right away unless the call is recursive
+    writeln!(source, "}} else if (stack->len == top + 1 || call_stack_is_recursive(stack)) {{")?;
+    source.indent();
+    writeln!(source, "return;")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    source.deindent();
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! The string literals of the project, pooled into static constants.
+//!
+//! Each literal is written once as a `Value` holding an immortal `rcstr`,
+//! which copying and freeing never change, so literals are never allocated at runtime.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::ir::{self, Const, Inst, Operand};
+use crate::parser::{self, Globals, Literal};
+use super::{literal, util::*};
+
+/// Every distinct string literal of the project, the `index`th one is the constant `literal{index}`
+#[derive(Debug, Default)]
+pub struct StringPool {
+    strings: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl StringPool {
+    fn add(&mut self, s: &str) {
+        if !self.indices.contains_key(s) {
+            self.indices.insert(s.to_owned(), self.strings.len());
+            self.strings.push(s.to_owned());
+        }
+    }
+
+    fn add_literal(&mut self, literal: &Literal) {
+        if let Literal::String(s) = literal {
+            self.add(s);
+        }
+    }
+
+    /// collects the string literals of every lowered function, and of the saved values of variables and lists
+    pub fn collect(targets: &[parser::Target], program: &ir::Program, globals: &Globals) -> Self {
+        let mut pool = StringPool::default();
+        for function in program.targets.iter().flat_map(ir::Target::functions) {
+            for block in &function.blocks {
+                let operands = block.insts.iter().flat_map(Inst::operands);
+                for operand in operands.chain(block.terminator.operands()) {
+                    if let Operand::Const(Const::String(s)) = operand {
+                        pool.add(s);
+                    }
+                }
+            }
+        }
+        let vars = targets.iter().flat_map(|t| t.vars.values()).chain(globals.vars.values());
+        for var in vars {
+            pool.add_literal(&var.value);
+        }
+        let lists = targets.iter().flat_map(|t| t.lists.values()).chain(globals.lists.values());
+        for item in lists.flat_map(|list| &list.items) {
+            pool.add_literal(item);
+        }
+        pool
+    }
+
+    /// C expression of the `Value` holding the string `s`, which must have been collected
+    pub fn value(&self, s: &str) -> String {
+        format!("literal{}", self.indices[s])
+    }
+
+    /// writes the constants of every literal
+    pub fn write<W: Write>(&self, source: &mut IW<W>) -> io::Result<()> {
+        for (i, s) in self.strings.iter().enumerate() {
+            writeln!(
+                source,
+                "static const Value literal{i} = {{ .type = VALUE_STRING, .s = {{ .rc = &immortal_rc, .ptr = {} }} }};",
+                literal::string(s)
+            )?;
+        }
+        if !self.strings.is_empty() {
+            writeln!(source)?;
+        }
+        Ok(())
+    }
+}
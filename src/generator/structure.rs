@@ -0,0 +1,323 @@
+//! Structured C for the parts of a lowered function that can't suspend the script.
+//!
+//! Blocks are in the order of the scratch blocks they were lowered from: an `if` branches over the blocks of its body,
+//! and a loop is a run of blocks ending with a jump back to the first one.
+//! Such regions are written as `if` and `while` statements when they are only entered at their first block,
+//! and nothing in them suspends the script or is resumed; the rest of the function jumps between blocks with `goto`.
+
+use crate::ir::{self, FunctionKind, Terminator};
+
+/// a statement of structured C
+#[derive(Debug, PartialEq)]
+pub enum Stmt {
+    /// the instructions of a block
+    Insts(usize),
+    /// the terminator of a block which leaves the function
+    Exit(usize),
+    /// lets a warp procedure redraw the screen where it would have yielded
+    WarpCheck,
+    /// the two ways of the `Branch` ending a block
+    If { block: usize, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    /// loops forever, until it breaks
+    Loop(Vec<Stmt>),
+    Break,
+    Continue,
+}
+
+/// the way a function is written, in the order of its blocks
+#[derive(Debug, PartialEq)]
+pub enum Item {
+    /// a block jumping to the others with `goto`
+    Block(usize),
+    /// structured statements, from the block until the next item
+    Structured(usize, Vec<Stmt>),
+}
+
+/// the blocks a function can continue at after the block `i` in the same run, without being suspended
+pub fn flow(function: &ir::Function, i: usize) -> Vec<usize> {
+    let warp = matches!(function.kind, FunctionKind::WarpProcedure(_));
+    match &function.blocks[i].terminator {
+        // suspended scripts are resumed by their state
+        Terminator::Yield(_) if !warp => vec![],
+        Terminator::WaitForStart(_) => vec![],
+        Terminator::Call { .. } if !matches!(function.kind, FunctionKind::Sequence(_)) => vec![],
+        terminator => terminator.successors().into_iter().map(|b| b.0).collect(),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Loop {
+    header: usize,
+    /// the block after the loop
+    exit: Option<usize>,
+}
+
+/// the blocks being written as a sequence of statements, until `end`
+#[derive(Clone, Copy)]
+struct Region {
+    end: usize,
+    /// the block reached after the last statement
+    follow: Option<usize>,
+    /// the innermost loop the statements are in, which `break` and `continue` refer to
+    in_loop: Option<Loop>,
+}
+
+struct Structurer<'a> {
+    function: &'a ir::Function,
+    preds: Vec<Vec<usize>>,
+    /// wether each block can be written as plain C: it isn't resumed, and doesn't suspend the script
+    plain: Vec<bool>,
+}
+
+impl Structurer<'_> {
+    /// the last block of the loop starting at `i`, jumping back to it
+    fn back_edge(&self, i: usize) -> Option<usize> {
+        self.preds[i].iter().copied().filter(|&p| p >= i).max()
+    }
+
+    /// the block reached after the statements of block `i`
+    fn after(&self, i: usize, region: Region) -> Option<usize> {
+        if i + 1 < region.end { Some(i + 1) } else { region.follow }
+    }
+
+    /// the statements jumping from block `i` to `to`, if it doesn't need a `goto`
+    fn jump(&self, i: usize, to: usize, region: Region) -> Option<Option<Stmt>> {
+        if Some(to) == self.after(i, region) {
+            return Some(None);
+        }
+        match region.in_loop {
+            Some(l) if l.header == to => Some(Some(Stmt::Continue)),
+            Some(l) if l.exit == Some(to) => Some(Some(Stmt::Break)),
+            _ => None,
+        }
+    }
+
+    /// where the statements after block `i` reach `to`: at the block, or at the end of the region
+    fn position(&self, i: usize, to: usize, region: Region) -> Option<usize> {
+        if to > i && to < region.end {
+            Some(to)
+        } else if Some(to) == region.follow {
+            Some(region.end)
+        } else {
+            None
+        }
+    }
+
+    /// the statements of the blocks from `start` until the end of the region
+    fn statements(&self, start: usize, region: Region) -> Option<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        let mut i = start;
+        while i < region.end {
+            if !self.plain[i] {
+                return None;
+            }
+            if region.in_loop.map(|l| l.header) != Some(i) {
+                if let Some(last) = self.back_edge(i) {
+                    if last >= region.end {
+                        return None;
+                    }
+                    let in_loop = Some(Loop { header: i, exit: self.after(last, region) });
+                    stmts.push(Stmt::Loop(self.statements(i, Region { end: last + 1, follow: Some(i), in_loop })?));
+                    i = last + 1;
+                    continue;
+                }
+            }
+            if !self.function.blocks[i].insts.is_empty() {
+                stmts.push(Stmt::Insts(i));
+            }
+            i = match &self.function.blocks[i].terminator {
+                Terminator::Branch(..) => self.branch(i, region, &mut stmts)?,
+                Terminator::Jump(to) => {
+                    stmts.extend(self.jump(i, to.0, region)?);
+                    i + 1
+                }
+                // plain blocks only yield in warp procedures
+                Terminator::Yield(to) | Terminator::LoopBack(to) => {
+                    stmts.push(Stmt::WarpCheck);
+                    stmts.extend(self.jump(i, to.0, region)?);
+                    i + 1
+                }
+                Terminator::Return | Terminator::Stop | Terminator::StopAll => {
+                    stmts.push(Stmt::Exit(i));
+                    i + 1
+                }
+                Terminator::WaitForStart(_) | Terminator::Call { .. } => return None,
+            };
+        }
+        Some(stmts)
+    }
+
+    /// adds the `if` ending block `i`, returns the block after it
+    fn branch(&self, i: usize, region: Region, stmts: &mut Vec<Stmt>) -> Option<usize> {
+        let Terminator::Branch(_, then, otherwise) = self.function.blocks[i].terminator else {
+            unreachable!("only branches are written as an `if`")
+        };
+        let (then, otherwise) = (then.0, otherwise.0);
+        // both ways only continue, or leave a loop
+        if let (Some(a), Some(b)) = (self.jump(i, then, region), self.jump(i, otherwise, region)) {
+            if a.is_some() || b.is_some() {
+                stmts.push(Stmt::If { block: i, then: a.into_iter().collect(), otherwise: b.into_iter().collect() });
+            }
+            return Some(i + 1);
+        }
+
+        // the body comes right after the branch, until the blocks the other way goes to
+        let body = i + 1;
+        let skip = if then == body { otherwise } else if otherwise == body { then } else { return None };
+        let merge = self.position(i, skip, region).filter(|&m| m > body)?;
+        let follow = |end: usize| if end < region.end { Some(end) } else { region.follow };
+        // the body of an `if-else` jumps over the other body when it ends
+        let end = match self.function.blocks[merge - 1].terminator {
+            Terminator::Jump(to) if merge < region.end => {
+                self.position(merge, to.0, region).filter(|&end| end > merge).unwrap_or(merge)
+            }
+            _ => merge,
+        };
+        let in_loop = region.in_loop;
+        let body = self.statements(body, Region { end: merge, follow: follow(end), in_loop })?;
+        let other = if end > merge {
+            self.statements(merge, Region { end, follow: follow(end), in_loop })?
+        } else {
+            Vec::new()
+        };
+        let (then, otherwise) = if then == i + 1 { (body, other) } else { (other, body) };
+        stmts.push(Stmt::If { block: i, then, otherwise });
+        Some(end)
+    }
+
+    /// the structured statements starting at block `i`, until the block returned,
+    /// if they are only entered from their first block
+    fn construct(&self, i: usize) -> Option<(Vec<Stmt>, usize)> {
+        let len = self.function.blocks.len();
+        let looped = self.back_edge(i).and_then(|last| {
+            let in_loop = Some(Loop { header: i, exit: (last + 1 < len).then_some(last + 1) });
+            let body = self.statements(i, Region { end: last + 1, follow: Some(i), in_loop })?;
+            Some((vec![Stmt::Loop(body)], last + 1))
+        });
+        // loops that suspend the script can still start with an `if`
+        let (stmts, end) = looped.or_else(|| {
+            if !matches!(self.function.blocks[i].terminator, Terminator::Branch(..)) {
+                return None;
+            }
+            // the first block can be resumed, its label comes before the statements
+            let mut stmts = Vec::new();
+            if !self.function.blocks[i].insts.is_empty() {
+                stmts.push(Stmt::Insts(i));
+            }
+            let end = self.branch(i, Region { end: len, follow: None, in_loop: None }, &mut stmts)?;
+            Some((stmts, end))
+        })?;
+        let entered_inside = (i + 1..end).any(|b| self.preds[b].iter().any(|p| !(i..end).contains(p)));
+        (end > i + 1 && !stmts.is_empty() && !entered_inside).then_some((stmts, end))
+    }
+}
+
+/// splits `function` into structured statements, and blocks jumping with `goto`.
+/// `states` are the states the function is resumed at.
+pub fn structure(function: &ir::Function, states: &[Option<u32>]) -> Vec<Item> {
+    let warp = matches!(function.kind, FunctionKind::WarpProcedure(_));
+    let mut preds = vec![Vec::new(); function.blocks.len()];
+    for i in 0..function.blocks.len() {
+        for to in flow(function, i) {
+            preds[to].push(i);
+        }
+    }
+    let plain = function
+        .blocks
+        .iter()
+        .zip(states)
+        .map(|(block, state)| {
+            let suspends = match block.terminator {
+                Terminator::Yield(_) | Terminator::LoopBack(_) => !warp,
+                Terminator::WaitForStart(_) | Terminator::Call { .. } => true,
+                _ => false,
+            };
+            !suspends && !matches!(state, Some(s) if *s > 0)
+        })
+        .collect();
+    let structurer = Structurer { function, preds, plain };
+
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < function.blocks.len() {
+        match structurer.construct(i) {
+            Some((stmts, end)) => {
+                items.push(Item::Structured(i, stmts));
+                i = end;
+            }
+            None => {
+                items.push(Item::Block(i));
+                i += 1;
+            }
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{BasicBlock, BlockId, Const, Inst, Operand};
+
+    fn block(insts: usize, terminator: Terminator) -> BasicBlock {
+        BasicBlock { insts: vec![Inst::BounceOffEdge; insts], terminator }
+    }
+
+    fn branch(then: usize, otherwise: usize) -> Terminator {
+        Terminator::Branch(Operand::Const(Const::Bool(true)), BlockId(then), BlockId(otherwise))
+    }
+
+    fn function(kind: FunctionKind, blocks: Vec<BasicBlock>) -> ir::Function {
+        ir::Function { kind, blocks, temps: vec![], locals: vec![] }
+    }
+
+    /// `while (...) { ... }` between two blocks, ending the body with `end`
+    fn looped(kind: FunctionKind, end: Terminator) -> ir::Function {
+        let blocks = vec![
+            block(1, Terminator::Jump(BlockId(1))),
+            block(1, branch(2, 3)),
+            block(1, end),
+            block(0, Terminator::Return),
+        ];
+        function(kind, blocks)
+    }
+
+    #[test]
+    fn writes_an_if() {
+        let blocks = vec![block(1, branch(1, 2)), block(1, Terminator::Jump(BlockId(2))), block(0, Terminator::Return)];
+        let function = function(FunctionKind::Sequence(0), blocks);
+        let items = structure(&function, &[Some(0), None, None]);
+        let then = vec![Stmt::Insts(1)];
+        let stmts = vec![Stmt::Insts(0), Stmt::If { block: 0, then, otherwise: vec![] }];
+        assert_eq!(items, [Item::Structured(0, stmts), Item::Block(2)]);
+    }
+
+    #[test]
+    fn writes_a_loop() {
+        let function = looped(FunctionKind::Sequence(0), Terminator::Jump(BlockId(1)));
+        let items = structure(&function, &[Some(0), None, None, None]);
+        let exit = Stmt::If { block: 1, then: vec![], otherwise: vec![Stmt::Break] };
+        let body = vec![Stmt::Insts(1), exit, Stmt::Insts(2)];
+        assert_eq!(items, [Item::Block(0), Item::Structured(1, vec![Stmt::Loop(body)]), Item::Block(3)]);
+    }
+
+    #[test]
+    fn warp_procedures_check_the_time_in_loops() {
+        let function = looped(FunctionKind::WarpProcedure(0), Terminator::LoopBack(BlockId(1)));
+        let items = structure(&function, &[Some(0), None, None, None]);
+        let exit = Stmt::If { block: 1, then: vec![], otherwise: vec![Stmt::Break] };
+        let body = vec![Stmt::Insts(1), exit, Stmt::Insts(2), Stmt::WarpCheck];
+        assert_eq!(items, [Item::Block(0), Item::Structured(1, vec![Stmt::Loop(body)]), Item::Block(3)]);
+    }
+
+    #[test]
+    fn keeps_suspending_loops_as_blocks() {
+        let blocks = (0..4).map(Item::Block).collect::<Vec<_>>();
+        // the script yields at the end of the loop, and is resumed at its start
+        let function = looped(FunctionKind::Sequence(0), Terminator::LoopBack(BlockId(1)));
+        assert_eq!(structure(&function, &[Some(0), Some(1), None, None]), blocks);
+        // the body is resumed after a wait
+        let function = looped(FunctionKind::Sequence(0), Terminator::Jump(BlockId(1)));
+        assert_eq!(structure(&function, &[Some(0), None, Some(1), None]), blocks);
+    }
+}
@@ -1,7 +1,8 @@
 use std::io::{self, Write};
-use nanoid::nanoid;
 
-use crate::parser::{self, VarMap};
+use crate::ir::{self, Type};
+use crate::parser::{self, ListDecl, Literal};
+use super::{literal, strings::StringPool};
 
 pub struct IndentWriter<W: Write> {
     pub writer: W,
@@ -49,60 +50,81 @@ impl<W: Write> IndentWriter<W> {
 
 pub type IW<W> = IndentWriter<W>;
 
-pub fn generate_var_name() -> String {
-    pub const CIDENT: [char; 53] = [
-        '_', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u',
-        'v', 'w', 'x', 'y', 'z', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q',
-        'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
-    ];
-
-    nanoid!(12, &CIDENT)
+/// writes `data` as a static C byte array named `name`
+pub fn write_byte_array<W: Write>(f: &mut IW<W>, name: &str, data: &[u8]) -> io::Result<()> {
+    writeln!(f, "static const unsigned char {name}[{}] = {{", data.len())?;
+    f.indent();
+    for line in data.chunks(16) {
+        for byte in line {
+            write!(f, "0x{byte:02x},")?;
+        }
+        writeln!(f)?;
+    }
+    f.deindent();
+    writeln!(f, "}};")
 }
 
-pub struct GeneratorArgs<'a> {
-    pub target: &'a parser::Target,
-    pub globals: &'a VarMap,
-    pub state: &'a mut u32,
-    pub new_locals: &'a mut Vec<String>,
+/// writes code logging, the first time it runs, that a block was skipped because it isn't supported
+pub fn unsupported_stub<W: Write>(f: &mut IW<W>, target: &str, opcode: &str, id: Option<&str>) -> io::Result<()> {
+    writeln!(f, "{{")?;
+    f.indent();
+    writeln!(f, "static bool warned = false;")?;
+    writeln!(f, "if (!warned) {{")?;
+    f.indent();
+    writeln!(f, "warned = true;")?;
+    writeln!(
+        f,
+        "fprintf(stderr, \"warning: skipped unsupported block `%s` (sprite `%s`, block `%s`)\\n\", {}, {}, {});",
+        literal::string(opcode),
+        literal::string(target),
+        literal::string(id.unwrap_or("?"))
+    )?;
+    f.deindent();
+    writeln!(f, "}}")?;
+    f.deindent();
+    writeln!(f, "}}")
 }
 
-/// get a variable in a function
-pub fn get_var<'a>(args: &GeneratorArgs, id: &'_ str) -> String {
-    if let Some(var) = args.target.vars.get(id) {
-        format!("a->var_{var}")
-    } else if let Some(var) = args.globals.get(id) {
-        format!("g->var_{var}")
+/// C statements moving the sequence with the state `state` (of `target`) to the case `to`,
+/// dropping the custom blocks it was running
+pub fn reset_sequence(target: &parser::Target, state: &str, to: u32) -> String {
+    if target.procedures.is_empty() {
+        format!("{state}.state = {to};")
     } else {
-        unreachable!(
-            "variable with ID `{id}` does not exists:\n{:#?}\n{:#?}",
-            args.target.vars, args.globals
-        );
+        format!("{state}.state = {to}; call_stack_clear(&{state}.stack, free_{}_frame);", target.name)
     }
 }
 
-pub fn start_case<W: Write>(f: &mut IW<W>, state: &mut u32) -> io::Result<()> {
-    writeln!(f, "case {}: {{", *state)?;
-    f.indent();
-    Ok(())
+/// writes the code asking for the screen to be redrawn if the actor is visible, loops yield until the next frame
+pub fn request_redraw<W: Write>(f: &mut IW<W>) -> io::Result<()> {
+    writeln!(f, "if (a->actor_state.visible) g->redraw_requested = true;")
 }
 
-pub fn end_case<W: Write>(f: &mut IW<W>, state: &mut u32) -> io::Result<()> {
-    f.deindent();
-    writeln!(f, "}}")?;
-    writeln!(f, "break;")?;
-    *state += 1;
-    Ok(())
+/// C expression of the `Value` of a literal
+pub fn literal_value(literal: &Literal, pool: &StringPool) -> String {
+    match literal {
+        Literal::Number(n) => format!("(Value){{ .type = VALUE_NUM, .n = {} }}", literal::number(*n)),
+        Literal::String(s) => pool.value(s),
+        Literal::Bool(b) => format!("(Value){{ .type = VALUE_BOOL, .b = {b} }}"),
+    }
 }
 
-pub enum Return {
-    /// The block returns nothing and ends normally
-    Empty,
-    /// The block returns a value (an identifier to a C variable)
-    Value(String),
-    /// The block handles switching the state by itself. 
-    /// `"s->state = {*state + 1}"` will not be added automatically.
-    Hold,
-    /// The block handles ending the case by itself by calling [`end_case`].
-    /// `"} break;"` will not be added automatically.
-    Ended
+/// C expression of a literal, for a variable of type `ty`
+pub fn typed_literal(literal: &Literal, ty: Type, pool: &StringPool) -> String {
+    match (literal, ty) {
+        (Literal::Number(n), Type::Number) => literal::number(*n),
+        (Literal::String(s), Type::Number) => literal::number(ir::canonical_number(s).unwrap_or(0.0)),
+        (Literal::Bool(b), Type::Bool) => b.to_string(),
+        _ => literal_value(literal, pool),
+    }
+}
+
+/// writes the code initializing `list` (a `ValueList`) with its saved items
+pub fn init_list<W: Write>(f: &mut IW<W>, list: &str, decl: &ListDecl, pool: &StringPool) -> io::Result<()> {
+    writeln!(f, "{list} = (ValueList) {{ 0 }};")?;
+    for item in &decl.items {
+        // the list keeps a copy of the item
+        writeln!(f, "list_push(&{list}, {});", literal_value(item, pool))?;
+    }
+    Ok(())
 }
@@ -0,0 +1,86 @@
+use crate::parser::{self, Block, Control, Event, Looks, Motion, Procedures, Value};
+
+/// the most iterations of a `repeat` that can run without checking if the script should yield
+const SMALL_REPEAT: f64 = 100.0;
+
+/// returns wether `block` can suspend the script running it until a later frame.
+/// Only loops proven short run entirely in the same frame, the others yield once the frame's work time is over.
+pub fn can_yield(block: &Block, target: &parser::Target) -> bool {
+    match block {
+        Block::Event(event) => match event {
+            // hats wait for their event
+            Event::WhenFlagClicked | Event::WhenBroadcastReceived { .. } | Event::BroadcastAndWait { .. } => true,
+            Event::Broadcast { .. } => false,
+        },
+        Block::Control(control) => match control {
+            Control::Wait { .. } | Control::WaitUntil { .. } | Control::StartAsClone => true,
+            // unbounded loops yield at the end of every iteration, so they don't freeze the project
+            Control::RepeatUntil { .. } | Control::Forever { .. } => true,
+            Control::Repeat { times, branch } => {
+                sequence_can_yield(branch, target) || sequence_requests_redraw(branch) || !is_small_repeat(times, branch)
+            }
+            Control::IfCondition { branch, .. } => sequence_can_yield(branch, target),
+            Control::IfElse { if_branch, else_branch, .. } => {
+                sequence_can_yield(if_branch, target) || sequence_can_yield(else_branch, target)
+            }
+            Control::Stop { .. } | Control::CreateCloneOf { .. } | Control::DeleteThisClone => false,
+        },
+        Block::Looks(Looks::SayForSecs { .. }) => true,
+        Block::Motion(Motion::Glide { .. } | Motion::GlideXY { .. }) => true,
+        // warp procedures run entirely when called
+        Block::Procedures(Procedures::Call { proccode, .. }) => {
+            target.procedures.iter().any(|p| &p.proccode == proccode && !p.warp)
+        }
+        Block::Motion(_) | Block::Procedures(_) | Block::Operator(_) | Block::Data(_) | Block::Unsupported { .. } => false,
+    }
+}
+
+pub fn sequence_can_yield(sequence: &parser::Sequence, target: &parser::Target) -> bool {
+    sequence.blocks().any(|b| can_yield(b, target))
+}
+
+/// returns wether a `repeat` is proven short: it repeats a small constant number of times,
+/// and nothing in its body loops or calls a procedure
+pub fn is_small_repeat(times: &Value, branch: &parser::Sequence) -> bool {
+    matches!(times, Value::Number(n) if n.round() <= SMALL_REPEAT) && !can_run_long(branch)
+}
+
+/// returns wether `sequence` contains loops or procedure calls
+fn can_run_long(sequence: &parser::Sequence) -> bool {
+    sequence.blocks().any(|block| match block {
+        Block::Control(Control::Repeat { .. } | Control::RepeatUntil { .. } | Control::Forever { .. }) => true,
+        Block::Control(Control::IfCondition { branch, .. }) => can_run_long(branch),
+        Block::Control(Control::IfElse { if_branch, else_branch, .. }) => {
+            can_run_long(if_branch) || can_run_long(else_branch)
+        }
+        Block::Procedures(Procedures::Call { .. }) => true,
+        _ => false,
+    })
+}
+
+/// returns wether `sequence` can change what is drawn on screen, which makes loops yield
+pub fn sequence_requests_redraw(sequence: &parser::Sequence) -> bool {
+    sequence.blocks().any(requests_redraw)
+}
+
+/// returns wether `block` can change what is drawn on screen
+fn requests_redraw(block: &Block) -> bool {
+    match block {
+        Block::Motion(motion) => !matches!(motion, Motion::XPosition | Motion::YPosition | Motion::Direction),
+        Block::Looks(_) => true,
+        Block::Control(control) => match control {
+            Control::Repeat { branch, .. }
+            | Control::RepeatUntil { branch, .. }
+            | Control::Forever { branch }
+            | Control::IfCondition { branch, .. } => sequence_requests_redraw(branch),
+            Control::IfElse { if_branch, else_branch, .. } => {
+                sequence_requests_redraw(if_branch) || sequence_requests_redraw(else_branch)
+            }
+            Control::CreateCloneOf { .. } | Control::DeleteThisClone => true,
+            _ => false,
+        },
+        // the procedure could draw anything
+        Block::Procedures(Procedures::Call { .. }) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,223 @@
+//! Scratch's conversion rules (`Cast` in scratch-vm), to compute constants at compile time
+//! exactly like the runtime would.
+
+use std::cmp::Ordering;
+
+use super::Const;
+
+/// whitespace as defined by javascript, trimmed when converting strings to numbers
+/// (the runtime's `string_to_number` trims the same characters)
+fn is_js_whitespace(c: char) -> bool {
+    matches!(
+        c,
+        '\t'..='\r'
+            | ' '
+            | '\u{a0}'
+            | '\u{1680}'
+            | '\u{2000}'..='\u{200a}'
+            | '\u{2028}'
+            | '\u{2029}'
+            | '\u{202f}'
+            | '\u{205f}'
+            | '\u{3000}'
+            | '\u{feff}'
+    )
+}
+
+/// javascript's `Number(s)`, which is NaN if the string isn't a number
+pub fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(is_js_whitespace);
+    if s.is_empty() {
+        return 0.0;
+    }
+    for (prefix, radix) in [("0x", 16), ("0X", 16), ("0o", 8), ("0O", 8), ("0b", 2), ("0B", 2)] {
+        if let Some(digits) = s.strip_prefix(prefix) {
+            if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+                return f64::NAN;
+            }
+            return digits.chars().fold(0.0, |n, c| n * radix as f64 + c.to_digit(radix).unwrap() as f64);
+        }
+    }
+    match s {
+        "Infinity" | "+Infinity" => return f64::INFINITY,
+        "-Infinity" => return f64::NEG_INFINITY,
+        _ => (),
+    }
+    // rust also parses "inf" and "nan", which javascript doesn't
+    let decimal = s.chars().all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+    if !decimal {
+        return f64::NAN;
+    }
+    s.parse().unwrap_or(f64::NAN)
+}
+
+/// javascript's `String(n)`
+pub fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        return "NaN".to_owned();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "Infinity" } else { "-Infinity" }.to_owned();
+    }
+    if n == 0.0 {
+        return "0".to_owned();
+    }
+
+    // the shortest digits giving back the number, like javascript
+    let scientific = format!("{:e}", n.abs());
+    let (mantissa, exponent) = scientific.split_once('e').expect("`{:e}` always has an exponent");
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exponent: i32 = exponent.parse().expect("the exponent is an integer");
+    let sign = if n < 0.0 { "-" } else { "" };
+    // position of the decimal point, after `point` digits
+    let point = exponent + 1;
+    let len = digits.len() as i32;
+
+    let body = if len <= point && point <= 21 {
+        format!("{digits}{}", "0".repeat((point - len) as usize))
+    } else if 0 < point && point <= 21 {
+        format!("{}.{}", &digits[..point as usize], &digits[point as usize..])
+    } else if -6 < point && point <= 0 {
+        format!("0.{}{digits}", "0".repeat(-point as usize))
+    } else {
+        let exponent = if exponent < 0 { format!("-{}", -exponent) } else { format!("+{exponent}") };
+        match digits.split_at(1) {
+            (first, "") => format!("{first}e{exponent}"),
+            (first, rest) => format!("{first}.{rest}e{exponent}"),
+        }
+    };
+    format!("{sign}{body}")
+}
+
+/// javascript's `toLowerCase`, which the runtime's `strcmp_lowercase` folds case with
+pub fn lowercase(s: &str) -> String {
+    // rust implements the same unicode mappings, final sigma included
+    s.to_lowercase()
+}
+
+/// compares strings case-insensitively like the runtime's `strcmp_lowercase`,
+/// in the order of their UTF-16 code units like javascript
+fn compare_lowercase(a: &str, b: &str) -> Ordering {
+    lowercase(a).encode_utf16().cmp(lowercase(b).encode_utf16())
+}
+
+fn color_to_string(r: u8, g: u8, b: u8) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// `Number(value)` in javascript, which is NaN for values that aren't numbers
+fn js_number(value: &Const) -> f64 {
+    match value {
+        Const::Number(n) => *n,
+        Const::Bool(b) => *b as u8 as f64,
+        Const::String(s) => string_to_number(s),
+        Const::Color(r, g, b) => string_to_number(&color_to_string(*r, *g, *b)),
+    }
+}
+
+/// `Cast.toNumber`: like javascript, except that NaN is 0
+pub fn to_number(value: &Const) -> f64 {
+    let n = js_number(value);
+    if n.is_nan() { 0.0 } else { n }
+}
+
+/// `Cast.toBoolean`: every string is true, except for the empty string, "0" and "false"
+pub fn to_bool(value: &Const) -> bool {
+    match value {
+        Const::Bool(b) => *b,
+        Const::Number(n) => *n != 0.0 && !n.is_nan(),
+        Const::String(s) => !(s.is_empty() || s == "0" || compare_lowercase(s, "false") == Ordering::Equal),
+        Const::Color(..) => true,
+    }
+}
+
+/// `Cast.toString`
+pub fn to_string(value: &Const) -> String {
+    match value {
+        Const::Number(n) => number_to_string(*n),
+        Const::Bool(b) => b.to_string(),
+        Const::String(s) => s.clone(),
+        Const::Color(r, g, b) => color_to_string(*r, *g, *b),
+    }
+}
+
+/// `Cast.compare`: values are compared as numbers if both are numbers, and as case-insensitive strings otherwise
+pub fn compare(a: &Const, b: &Const) -> Ordering {
+    let number = |value: &Const| {
+        let n = js_number(value);
+        // strings of whitespace would be 0
+        let blank = matches!(value, Const::String(s) if s.trim_matches(is_js_whitespace).is_empty());
+        if blank { f64::NAN } else { n }
+    };
+    let (n1, n2) = (number(a), number(b));
+    if n1.is_nan() || n2.is_nan() {
+        return compare_lowercase(&to_string(a), &to_string(b));
+    }
+    // infinities are equal to themselves, even though their difference is NaN
+    n1.partial_cmp(&n2).unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_to_strings() {
+        assert_eq!(number_to_string(1e21), "1e+21");
+        assert_eq!(number_to_string(1e20), "100000000000000000000");
+        assert_eq!(number_to_string(1e-7), "1e-7");
+        assert_eq!(number_to_string(1e-6), "0.000001");
+        assert_eq!(number_to_string(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(number_to_string(-0.0), "0");
+        assert_eq!(number_to_string(-1.5e-10), "-1.5e-10");
+        assert_eq!(number_to_string(123.456), "123.456");
+        assert_eq!(number_to_string(f64::NAN), "NaN");
+        assert_eq!(number_to_string(f64::INFINITY), "Infinity");
+        assert_eq!(number_to_string(f64::NEG_INFINITY), "-Infinity");
+    }
+
+    #[test]
+    fn strings_to_numbers() {
+        assert_eq!(string_to_number("0x1F"), 31.0);
+        assert_eq!(string_to_number("0b101"), 5.0);
+        assert_eq!(string_to_number(" 12 "), 12.0);
+        assert_eq!(string_to_number("\u{a0}12\n"), 12.0);
+        assert_eq!(string_to_number(""), 0.0);
+        assert_eq!(string_to_number(".5"), 0.5);
+        assert_eq!(string_to_number("+5"), 5.0);
+        assert_eq!(string_to_number("1e3"), 1000.0);
+        assert_eq!(string_to_number("Infinity"), f64::INFINITY);
+        assert_eq!(string_to_number("-Infinity"), f64::NEG_INFINITY);
+        for nan in ["1e", "0x", "-0x10", "abc", "NaN", "nan", "inf", "infinity", "1_000"] {
+            assert!(string_to_number(nan).is_nan(), "{nan:?} should be NaN");
+        }
+    }
+
+    #[test]
+    fn compare_values() {
+        let s = |s: &str| Const::String(s.to_owned());
+        let n = Const::Number;
+        assert_eq!(compare(&n(1.0), &s("1.0")), Ordering::Equal);
+        assert_eq!(compare(&s("10"), &s("9")), Ordering::Greater);
+        assert_eq!(compare(&s("abc"), &s("ABC")), Ordering::Equal);
+        assert_eq!(compare(&s("a"), &s("B")), Ordering::Less);
+        // blank strings aren't 0
+        assert_eq!(compare(&s(""), &n(0.0)), Ordering::Less);
+        assert_eq!(compare(&s(" "), &s("0")), Ordering::Less);
+        assert_eq!(compare(&n(f64::INFINITY), &s("Infinity")), Ordering::Equal);
+        assert_eq!(compare(&s("Infinity"), &s("infinity")), Ordering::Equal);
+        assert_eq!(compare(&n(f64::NAN), &n(f64::NAN)), Ordering::Equal);
+        assert_eq!(compare(&n(f64::NAN), &n(0.0)), Ordering::Greater);
+        assert_eq!(compare(&Const::Bool(true), &n(1.0)), Ordering::Equal);
+    }
+
+    #[test]
+    fn values_to_bools() {
+        for falsy in ["", "0", "false", "FALSE"] {
+            assert!(!to_bool(&Const::String(falsy.to_owned())), "{falsy:?} should be false");
+        }
+        assert!(to_bool(&Const::String("0.0".to_owned())));
+        assert!(!to_bool(&Const::Number(f64::NAN)));
+        assert!(to_bool(&Const::Number(f64::INFINITY)));
+    }
+}
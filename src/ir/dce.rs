@@ -0,0 +1,207 @@
+//! Dead code elimination: removes the blocks no path reaches, and the scripts behind hats that can never fire
+
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{self, Block, Control, Event};
+use super::*;
+
+/// Removes the blocks that can't be reached from the entry of `function`, like the ones after a branch folded into a jump.
+/// Returns the number of instructions removed.
+pub fn remove_unreachable_blocks(function: &mut Function) -> usize {
+    let mut reachable = vec![false; function.blocks.len()];
+    let mut stack = vec![BlockId(0)];
+    while let Some(block) = stack.pop() {
+        if !std::mem::replace(&mut reachable[block.0], true) {
+            stack.extend(function.blocks[block.0].terminator.successors());
+        }
+    }
+
+    let mut ids = vec![None; function.blocks.len()];
+    let mut removed = 0;
+    let mut blocks = vec![];
+    for (i, block) in std::mem::take(&mut function.blocks).into_iter().enumerate() {
+        if reachable[i] {
+            ids[i] = Some(BlockId(blocks.len()));
+            blocks.push(block);
+        } else {
+            removed += block.insts.len();
+        }
+    }
+    for block in &mut blocks {
+        for successor in block.terminator.successors_mut() {
+            *successor = ids[successor.0].expect("successors of reachable blocks are reachable");
+        }
+    }
+    function.blocks = blocks;
+    removed
+}
+
+/// Everything the program does that could start a script
+struct Starts {
+    /// the lowercase names of every broadcast sent, unless some are computed at runtime
+    broadcasts: Option<HashSet<String>>,
+    /// the names of the sprites cloned
+    clones: HashSet<String>,
+}
+
+fn find_starts(program: &Program) -> Starts {
+    let mut broadcasts = Some(HashSet::new());
+    let mut clones = HashSet::new();
+    for function in program.targets.iter().flat_map(Target::functions) {
+        // messages are boxed before being sent
+        let mut consts = HashMap::new();
+        for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+            match inst {
+                Inst::Let(t, Expr::Convert(Type::Value, Operand::Const(c))) => {
+                    consts.insert(*t, c);
+                }
+                Inst::Broadcast(message, _) => {
+                    let name = match message {
+                        Operand::Const(c) => Some(c),
+                        Operand::Temp(t) => consts.get(t).copied(),
+                    };
+                    match (name, &mut broadcasts) {
+                        (Some(name), Some(names)) => {
+                            names.insert(cast::lowercase(&cast::to_string(name)));
+                        }
+                        _ => broadcasts = None,
+                    }
+                }
+                Inst::CreateClone { sprite, .. } => {
+                    clones.insert(sprite.clone());
+                }
+                _ => (),
+            }
+        }
+    }
+    Starts { broadcasts, clones }
+}
+
+/// why the script can never start, if it can't
+fn never_starts(sequence: &parser::Sequence, target: &parser::Target, starts: &Starts) -> Option<String> {
+    match sequence.hat()? {
+        Block::Event(Event::WhenBroadcastReceived { broadcast }) => {
+            let sent = starts.broadcasts.as_ref()?.contains(&cast::lowercase(&broadcast.name));
+            (!sent).then(|| format!("the broadcast `{}` is never sent", broadcast.name))
+        }
+        Block::Control(Control::StartAsClone) => {
+            let cloned = starts.clones.contains(&target.name);
+            (!cloned).then(|| "the sprite is never cloned".to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Empties the scripts that can never start, returns the index of the target and sequence of each,
+/// with the reason it can't start.
+/// Removing a script can stop a broadcast from being sent, so this is repeated until no script is removed.
+pub fn remove_dead_scripts(program: &mut Program, targets: &[parser::Target]) -> Vec<(usize, usize, String)> {
+    let mut removed = vec![];
+    let mut dead = HashSet::new();
+    loop {
+        let starts = find_starts(program);
+        let mut changed = false;
+        for (i, target) in targets.iter().enumerate() {
+            for (j, sequence) in target.sequences.iter().enumerate() {
+                if dead.contains(&(i, j)) { continue }
+                let Some(reason) = never_starts(sequence, target, &starts) else { continue };
+
+                // the script does nothing whenever it runs
+                let function = &mut program.targets[i].sequences[j];
+                function.blocks = vec![BasicBlock { insts: vec![], terminator: Terminator::Return }];
+                function.temps.clear();
+                function.locals.clear();
+                dead.insert((i, j));
+                removed.push((i, j, reason));
+                changed = true;
+            }
+        }
+        if !changed {
+            return removed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Broadcast, LocatedBlock, Sequence, TargetKind};
+
+    fn function(blocks: Vec<BasicBlock>) -> Function {
+        Function { kind: FunctionKind::Sequence(0), blocks, temps: vec![], locals: vec![] }
+    }
+
+    /// a script starting with `hat`
+    fn script(hat: Event) -> Sequence {
+        let block = Block::Event(hat);
+        Sequence(vec![LocatedBlock { id: "hat".to_owned(), opcode: "hat".to_owned(), block }])
+    }
+
+    fn receive(name: &str) -> Sequence {
+        script(Event::WhenBroadcastReceived { broadcast: Broadcast { name: name.to_owned() } })
+    }
+
+    /// a function sending `message`
+    fn broadcast(message: Operand) -> Function {
+        let insts = vec![Inst::Broadcast(message, None)];
+        function(vec![BasicBlock { insts, terminator: Terminator::Return }])
+    }
+
+    fn program(targets: &[parser::Target], sequences: Vec<Function>) -> Program {
+        let target = Target { vars: HashMap::new(), sequences, procedures: vec![], warp_procedures: vec![] };
+        assert_eq!(targets[0].sequences.len(), target.sequences.len());
+        Program { targets: vec![target], global_vars: HashMap::new() }
+    }
+
+    fn stage(sequences: Vec<Sequence>) -> parser::Target {
+        parser::Target {
+            name: "Stage".to_owned(),
+            sequences,
+            procedures: vec![],
+            vars: HashMap::new(),
+            lists: HashMap::new(),
+            current_costume: 0,
+            costumes: vec![],
+            kind: TargetKind::Stage { tempo: 60 },
+        }
+    }
+
+    fn message(name: &str) -> Operand {
+        Operand::Const(Const::String(name.to_owned()))
+    }
+
+    #[test]
+    fn removes_unreachable_blocks() {
+        let mut function = function(vec![
+            BasicBlock { insts: vec![], terminator: Terminator::Jump(BlockId(2)) },
+            BasicBlock { insts: vec![Inst::RequestRedraw, Inst::BounceOffEdge], terminator: Terminator::Return },
+            BasicBlock { insts: vec![], terminator: Terminator::Jump(BlockId(0)) },
+        ]);
+        assert_eq!(remove_unreachable_blocks(&mut function), 2);
+        assert_eq!(function.blocks.len(), 2);
+        assert_eq!(function.blocks[0].terminator, Terminator::Jump(BlockId(1)));
+        assert_eq!(function.blocks[1].terminator, Terminator::Jump(BlockId(0)));
+    }
+
+    #[test]
+    fn removes_scripts_started_by_removed_scripts() {
+        // "A" is never sent, and only its receiver sends "b" (broadcasts match case-insensitively)
+        let targets = [stage(vec![script(Event::WhenFlagClicked), receive("A"), receive("B")])];
+        let sequences = vec![broadcast(message("c")), broadcast(message("b")), broadcast(message("c"))];
+        let mut program = program(&targets, sequences);
+        let removed = remove_dead_scripts(&mut program, &targets);
+        let removed: Vec<_> = removed.into_iter().map(|(i, j, _)| (i, j)).collect();
+        assert_eq!(removed, [(0, 1), (0, 2)]);
+        let empty = BasicBlock { insts: vec![], terminator: Terminator::Return };
+        assert_eq!(program.targets[0].sequences[2].blocks, [empty]);
+        assert_eq!(program.targets[0].sequences[0], broadcast(message("c")));
+    }
+
+    #[test]
+    fn keeps_receivers_of_computed_broadcasts() {
+        let targets = [stage(vec![script(Event::WhenFlagClicked), receive("a")])];
+        let computed = broadcast(Operand::Temp(Temp(0)));
+        let mut program = program(&targets, vec![computed, function(vec![])]);
+        assert!(remove_dead_scripts(&mut program, &targets).is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! Textual form of the IR, printed by `--emit ir`
+
+use std::fmt::{self, Display, Formatter};
+
+use super::*;
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::Bool => write!(f, "bool"),
+            Type::Value => write!(f, "value"),
+        }
+    }
+}
+
+impl Display for Const {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Const::Number(n) => write!(f, "{n}"),
+            Const::Bool(b) => write!(f, "{b}"),
+            Const::String(s) => write!(f, "{s:?}"),
+            Const::Color(r, g, b) => write!(f, "#{r:02x}{g:02x}{b:02x}"),
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Temp(t) => write!(f, "t{}", t.0),
+            Operand::Const(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+impl Display for BlockId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "block{}", self.0)
+    }
+}
+
+impl Display for VarRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.scope {
+            Scope::Actor => write!(f, "{}", self.cident),
+            Scope::Global => write!(f, "global.{}", self.cident),
+        }
+    }
+}
+
+impl Display for Actor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Actor::This => write!(f, "this"),
+            Actor::Original(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl Display for ActorField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ActorField::X => write!(f, "x"),
+            ActorField::Y => write!(f, "y"),
+            ActorField::Direction => write!(f, "direction"),
+        }
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::LesserThan => "<",
+            BinaryOp::GreaterThan => ">",
+            BinaryOp::Equals => "=",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        };
+        write!(f, "{op}")
+    }
+}
+
+/// writes the operands separated by commas
+fn list(f: &mut Formatter<'_>, operands: &[Operand]) -> fmt::Result {
+    for (i, operand) in operands.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{operand}")?;
+    }
+    Ok(())
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Convert(ty, operand) => write!(f, "{ty}({operand})"),
+            Expr::Binary(op, lhs, rhs) => write!(f, "{lhs} {op} {rhs}"),
+            Expr::Unary(UnaryOp::Not, operand) => write!(f, "not {operand}"),
+            Expr::Unary(UnaryOp::Round, operand) => write!(f, "round {operand}"),
+            Expr::Var(var) => write!(f, "var {var}"),
+            Expr::Argument(i) => write!(f, "arg {i}"),
+            Expr::Local(local) => write!(f, "local {}", local.0),
+            Expr::ListItem(l, index) => write!(f, "item {index} of {l}"),
+            Expr::ListIndexOf(l, item) => write!(f, "index of {item} in {l}"),
+            Expr::ListLength(l) => write!(f, "length of {l}"),
+            Expr::ListContains(l, item) => write!(f, "{l} contains {item}"),
+            Expr::ListJoin(l) => write!(f, "join {l}"),
+            Expr::ActorField(actor, field) => write!(f, "{actor}.{field}"),
+            Expr::IsClone => write!(f, "is clone"),
+            Expr::MouseX => write!(f, "mouse x"),
+            Expr::MouseY => write!(f, "mouse y"),
+            Expr::Random(from, to) => write!(f, "random {from} to {to}"),
+            Expr::Time => write!(f, "time"),
+            Expr::FlagClicked => write!(f, "flag clicked"),
+            Expr::BroadcastRunning(local) => write!(f, "broadcast running local {}", local.0),
+        }
+    }
+}
+
+impl Display for Inst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Inst::Let(t, expr) => write!(f, "t{} = {expr}", t.0),
+            Inst::SetLocal(local, value) => write!(f, "local {} = {value}", local.0),
+            Inst::SetVar(var, value) => write!(f, "var {var} = {value}"),
+            Inst::ListPush(l, item) => write!(f, "push {item} to {l}"),
+            Inst::ListClear(l) => write!(f, "clear {l}"),
+            Inst::ListDelete(l, index) => write!(f, "delete {index} of {l}"),
+            Inst::ListInsert { list, index, item } => write!(f, "insert {item} at {index} of {list}"),
+            Inst::ListReplace { list, index, item } => write!(f, "replace {index} of {list} with {item}"),
+            Inst::Broadcast(message, None) => write!(f, "broadcast {message}"),
+            Inst::Broadcast(message, Some(local)) => write!(f, "local {} = broadcast {message}", local.0),
+            Inst::SetActorField(field, value) => write!(f, "this.{field} = {value}"),
+            Inst::MoveSteps(steps) => write!(f, "move {steps}"),
+            Inst::PointTowards(x, y) => write!(f, "point towards {x}, {y}"),
+            Inst::BounceOffEdge => write!(f, "bounce off edge"),
+            Inst::SetRotationStyle(style) => write!(f, "rotation style {style:?}"),
+            Inst::Say(message, until) => write!(f, "say {message} until {until}"),
+            Inst::RequestRedraw => write!(f, "request redraw"),
+            Inst::CreateClone { sprite, parent } => write!(f, "clone {parent} as {sprite}"),
+            Inst::DeleteClone => write!(f, "delete clone"),
+            Inst::StopOtherScripts => write!(f, "stop other scripts"),
+            Inst::CallWarp { procedure, args, stopped } => {
+                write!(f, "t{} = call warp procedure {procedure}(", stopped.0)?;
+                list(f, args)?;
+                write!(f, ")")
+            }
+            Inst::Unsupported { opcode, id } => write!(f, "unsupported {opcode} ({id})"),
+        }
+    }
+}
+
+impl Display for Terminator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(b) => write!(f, "jump {b}"),
+            Terminator::Branch(condition, then, otherwise) => write!(f, "branch {condition} {then} {otherwise}"),
+            Terminator::Yield(b) => write!(f, "yield {b}"),
+            Terminator::LoopBack(b) => write!(f, "loop back {b}"),
+            Terminator::WaitForStart(b) => write!(f, "wait for start {b}"),
+            Terminator::Call { procedure, args, resume } => {
+                write!(f, "call procedure {procedure}(")?;
+                list(f, args)?;
+                write!(f, ") {resume}")
+            }
+            Terminator::Return => write!(f, "return"),
+            Terminator::Stop => write!(f, "stop"),
+            Terminator::StopAll => write!(f, "stop all"),
+        }
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FunctionKind::Sequence(i) => writeln!(f, "sequence {i}")?,
+            FunctionKind::Procedure(i) => writeln!(f, "procedure {i}")?,
+            FunctionKind::WarpProcedure(i) => writeln!(f, "warp procedure {i}")?,
+        }
+        for (i, local) in self.locals.iter().enumerate() {
+            writeln!(f, "    local {i}: {} ({})", local.ty, local.name)?;
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{}:", BlockId(i))?;
+            for inst in &block.insts {
+                match inst {
+                    Inst::Let(t, _) | Inst::CallWarp { stopped: t, .. } => {
+                        writeln!(f, "    {inst} : {}", self.temps[t.0])?
+                    }
+                    _ => writeln!(f, "    {inst}")?,
+                }
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Constant folding: expressions of constants are computed at compile time with scratch's rules,
+//! their temporaries replaced by the constant, and branches on constant conditions become jumps.
+
+use std::cmp::Ordering;
+
+use super::*;
+
+/// javascript's `Math.round`, which rounds halves up
+fn js_round(n: f64) -> f64 {
+    let floor = n.floor();
+    if n - floor >= 0.5 { floor + 1.0 } else { floor }
+}
+
+struct Folder {
+    /// the value of every temporary known at compile time.
+    /// Boxed constants keep their type, so they are only replaced in operands taking that type.
+    consts: Vec<Option<Const>>,
+}
+
+impl Folder {
+    fn value(&self, operand: &Operand) -> Option<Const> {
+        match operand {
+            Operand::Const(c) => Some(c.clone()),
+            Operand::Temp(t) => self.consts[t.0].clone(),
+        }
+    }
+
+    fn eval(&self, expr: &Expr) -> Option<Const> {
+        match expr {
+            Expr::Convert(ty, operand) => {
+                let value = self.value(operand)?;
+                Some(match ty {
+                    Type::Number => Const::Number(cast::to_number(&value)),
+                    Type::Bool => Const::Bool(cast::to_bool(&value)),
+                    Type::Value => value,
+                })
+            }
+            // the other operand has no side effects, so it doesn't need to be computed
+            Expr::Binary(BinaryOp::And, a, b) | Expr::Binary(BinaryOp::And, b, a)
+                if self.value(a) == Some(Const::Bool(false)) => Some(Const::Bool(false)),
+            Expr::Binary(BinaryOp::Or, a, b) | Expr::Binary(BinaryOp::Or, b, a)
+                if self.value(a) == Some(Const::Bool(true)) => Some(Const::Bool(true)),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (self.value(a)?, self.value(b)?);
+                let ordering = || cast::compare(&a, &b);
+                Some(match op {
+                    BinaryOp::Add => Const::Number(cast::to_number(&a) + cast::to_number(&b)),
+                    BinaryOp::Sub => Const::Number(cast::to_number(&a) - cast::to_number(&b)),
+                    BinaryOp::Mul => Const::Number(cast::to_number(&a) * cast::to_number(&b)),
+                    BinaryOp::Div => Const::Number(cast::to_number(&a) / cast::to_number(&b)),
+                    BinaryOp::LesserThan => Const::Bool(ordering() == Ordering::Less),
+                    BinaryOp::GreaterThan => Const::Bool(ordering() == Ordering::Greater),
+                    BinaryOp::Equals => Const::Bool(ordering() == Ordering::Equal),
+                    BinaryOp::And => Const::Bool(cast::to_bool(&a) && cast::to_bool(&b)),
+                    BinaryOp::Or => Const::Bool(cast::to_bool(&a) || cast::to_bool(&b)),
+                })
+            }
+            Expr::Unary(UnaryOp::Not, operand) => Some(Const::Bool(!cast::to_bool(&self.value(operand)?))),
+            Expr::Unary(UnaryOp::Round, operand) => Some(Const::Number(js_round(cast::to_number(&self.value(operand)?)))),
+            _ => None,
+        }
+    }
+
+    /// replaces the temporaries of known constants in `operand`
+    fn substitute(&self, operand: &mut Operand, temps: &[Type]) {
+        if let Operand::Temp(t) = operand {
+            if let Some(c) = &self.consts[t.0] {
+                if c.ty() == temps[t.0] {
+                    *operand = Operand::Const(c.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Folds the constants of `function`.
+/// The computations of folded temporaries are left, for [`Function::remove_unused_temps`] to remove.
+pub fn fold(function: &mut Function) {
+    let mut folder = Folder { consts: vec![None; function.temps.len()] };
+    // temporaries are defined before they are used, in the order of the blocks
+    for block in &mut function.blocks {
+        for inst in &mut block.insts {
+            for operand in inst.operands_mut() {
+                folder.substitute(operand, &function.temps);
+            }
+            if let Inst::Let(t, expr) = inst {
+                folder.consts[t.0] = folder.eval(expr);
+            }
+        }
+        for operand in block.terminator.operands_mut() {
+            folder.substitute(operand, &function.temps);
+        }
+        if let Terminator::Branch(Operand::Const(condition), then, otherwise) = &block.terminator {
+            let to = if cast::to_bool(condition) { *then } else { *otherwise };
+            block.terminator = Terminator::Jump(to);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(n: f64) -> Operand {
+        Operand::Const(Const::Number(n))
+    }
+
+    #[test]
+    fn rounds_like_javascript() {
+        assert_eq!(js_round(2.5), 3.0);
+        assert_eq!(js_round(-2.5), -2.0);
+        assert_eq!(js_round(-2.6), -3.0);
+        assert_eq!(js_round(0.49), 0.0);
+    }
+
+    #[test]
+    fn folds_constants_and_branches() {
+        let x = VarRef { scope: Scope::Global, cident: "x".to_owned(), ty: Type::Number };
+        // x = 1 + "2"; if 1 + "2" > 2
+        let insts = vec![
+            Inst::Let(Temp(0), Expr::Binary(BinaryOp::Add, number(1.0), Operand::Const(Const::String("2".to_owned())))),
+            Inst::SetVar(x.clone(), Operand::Temp(Temp(0))),
+            Inst::Let(Temp(1), Expr::Binary(BinaryOp::GreaterThan, Operand::Temp(Temp(0)), number(2.0))),
+        ];
+        let terminator = Terminator::Branch(Operand::Temp(Temp(1)), BlockId(1), BlockId(2));
+        let blocks = vec![
+            BasicBlock { insts, terminator },
+            BasicBlock { insts: vec![], terminator: Terminator::Return },
+            BasicBlock { insts: vec![], terminator: Terminator::Return },
+        ];
+        let temps = vec![Type::Number, Type::Bool];
+        let mut function = Function { kind: FunctionKind::Sequence(0), blocks, temps, locals: vec![] };
+        fold(&mut function);
+        function.remove_unused_temps();
+
+        assert_eq!(function.blocks[0].insts, [Inst::SetVar(x, number(3.0))]);
+        assert_eq!(function.blocks[0].terminator, Terminator::Jump(BlockId(1)));
+        assert!(function.temps.is_empty());
+    }
+
+    #[test]
+    fn evaluates_what_is_known() {
+        let folder = Folder { consts: vec![None, Some(Const::Number(4.0))] };
+        let unknown = Operand::Temp(Temp(0));
+        let four = Operand::Temp(Temp(1));
+        let and = Expr::Binary(BinaryOp::And, unknown.clone(), Operand::Const(Const::Bool(false)));
+        assert_eq!(folder.eval(&and), Some(Const::Bool(false)));
+        let or = Expr::Binary(BinaryOp::Or, unknown.clone(), Operand::Const(Const::Bool(false)));
+        assert_eq!(folder.eval(&or), None);
+        let divide = Expr::Binary(BinaryOp::Div, four.clone(), number(0.0));
+        assert_eq!(folder.eval(&divide), Some(Const::Number(f64::INFINITY)));
+        assert_eq!(folder.eval(&Expr::Convert(Type::Bool, four)), Some(Const::Bool(true)));
+        assert_eq!(folder.eval(&Expr::Unary(UnaryOp::Round, unknown)), None);
+    }
+
+    #[test]
+    fn keeps_boxed_constants_in_place() {
+        let folder = Folder { consts: vec![Some(Const::Number(1.0))] };
+        // the temporary holds a `Value`, the number can't replace it
+        let mut operand = Operand::Temp(Temp(0));
+        folder.substitute(&mut operand, &[Type::Value]);
+        assert_eq!(operand, Operand::Temp(Temp(0)));
+        folder.substitute(&mut operand, &[Type::Number]);
+        assert_eq!(operand, number(1.0));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::{self, CloneOption, Control, StopOption};
+use super::*;
+
+impl Lower for Control {
+    fn lower(&self, b: &mut Builder) -> Option<Operand> {
+        match self {
+            Control::CreateCloneOf { actor } => match actor {
+                CloneOption::Myself => match b.target.kind {
+                    parser::TargetKind::Stage { .. } => {
+                        b.error(Diagnostic::error("the stage can't be cloned").with_input("CLONE_OPTION"));
+                    }
+                    parser::TargetKind::Sprite { .. } => {
+                        b.push(Inst::CreateClone { sprite: b.target.name.clone(), parent: Actor::This });
+                    }
+                },
+                CloneOption::Actor(name) => {
+                    if let Some(parent) = b.original_actor(name, "CLONE_OPTION") {
+                        b.push(Inst::CreateClone { sprite: name.clone(), parent });
+                    }
+                }
+            },
+            Control::StartAsClone => {
+                // started by `clone_{target}` in new clones
+                let body = b.new_block();
+                b.finish_block(Terminator::WaitForStart(body), body);
+            }
+            Control::DeleteThisClone => {
+                // the stage and original sprites can't be deleted
+                if let parser::TargetKind::Sprite { .. } = b.target.kind {
+                    let delete = b.new_block();
+                    let next = b.new_block();
+                    let is_clone = b.compute(Expr::IsClone);
+                    b.finish_block(Terminator::Branch(is_clone, delete, next), delete);
+                    b.push(Inst::DeleteClone);
+                    b.push(Inst::RequestRedraw);
+                    // the call stack of the script is freed along with the clone
+                    b.finish_block(Terminator::Stop, next);
+                }
+            }
+            Control::Wait { duration } => {
+                let duration = b.number(duration);
+                let now = b.compute(Expr::Time);
+                let end = b.compute(Expr::Binary(BinaryOp::Add, now, duration));
+                let time = b.new_local("time", Type::Number);
+                b.push(Inst::SetLocal(time, end));
+                // like scratch, always wait for at least one frame
+                b.wait_until(true, |b| b.time_reached(time));
+            }
+            Control::WaitUntil { condition } => {
+                b.wait_until(false, |b| b.boolean(condition));
+            }
+            Control::Repeat { times: count, branch } => {
+                let times = b.number(count);
+                let times = b.compute(Expr::Unary(UnaryOp::Round, times));
+                let remaining = b.new_local("loop", Type::Number);
+                b.push(Inst::SetLocal(remaining, times));
+
+                let check = b.new_block();
+                let body = b.new_block();
+                let next = b.new_block();
+                b.finish_block(Terminator::Jump(check), check);
+                let left = b.compute(Expr::Local(remaining));
+                let more = b.compute(Expr::Binary(BinaryOp::GreaterThan, left, Operand::Const(Const::Number(0.0))));
+                b.finish_block(Terminator::Branch(more, body, next), body);
+
+                b.sequence(branch);
+                let left = b.compute(Expr::Local(remaining));
+                let left = b.compute(Expr::Binary(BinaryOp::Sub, left, Operand::Const(Const::Number(1.0))));
+                b.push(Inst::SetLocal(remaining, left));
+                // like scratch's work timer, loops let the other scripts run once the frame's work time is over,
+                // only short loops that don't draw anything run entirely in the same frame
+                let yields = analysis::sequence_can_yield(branch, b.target)
+                    || analysis::sequence_requests_redraw(branch)
+                    || !analysis::is_small_repeat(count, branch);
+                let back = if yields || b.warp() {
+                    Terminator::LoopBack(check)
+                } else {
+                    Terminator::Jump(check)
+                };
+                b.finish_block(back, next);
+            }
+            Control::RepeatUntil { condition, branch } => {
+                let check = b.new_block();
+                let body = b.new_block();
+                let next = b.new_block();
+                b.finish_block(Terminator::Jump(check), check);
+                let done = b.boolean(condition);
+                b.finish_block(Terminator::Branch(done, next, body), body);
+                b.sequence(branch);
+                b.finish_block(Terminator::LoopBack(check), next);
+            }
+            Control::Forever { branch } => {
+                let body = b.new_block();
+                b.finish_block(Terminator::Jump(body), body);
+                b.sequence(branch);
+                // the blocks after the loop are never reached
+                b.finish_script(Terminator::LoopBack(body));
+            }
+            Control::IfCondition { condition, branch } => {
+                let then = b.new_block();
+                let next = b.new_block();
+                let condition = b.boolean(condition);
+                b.finish_block(Terminator::Branch(condition, then, next), then);
+                b.sequence(branch);
+                b.finish_block(Terminator::Jump(next), next);
+            }
+            Control::IfElse { condition, if_branch, else_branch } => {
+                let then = b.new_block();
+                let otherwise = b.new_block();
+                let next = b.new_block();
+                let condition = b.boolean(condition);
+                b.finish_block(Terminator::Branch(condition, then, otherwise), then);
+                b.sequence(if_branch);
+                b.finish_block(Terminator::Jump(next), otherwise);
+                b.sequence(else_branch);
+                b.finish_block(Terminator::Jump(next), next);
+            }
+            Control::Stop { option } => match option {
+                StopOption::All => b.finish_script(Terminator::StopAll),
+                // like scratch, "stop this script" in a custom block only returns from it
+                StopOption::ThisScript if b.procedure.is_some() => b.finish_script(Terminator::Return),
+                StopOption::ThisScript => b.finish_script(Terminator::Stop),
+                StopOption::OtherScripts => b.push(Inst::StopOtherScripts),
+            },
+        }
+        None
+    }
+}
@@ -0,0 +1,163 @@
+use crate::parser::Data;
+use super::*;
+
+impl Lower for Data {
+    fn lower(&self, b: &mut Builder) -> Option<Operand> {
+        match self {
+            Data::SetVariableTo { value, var } => {
+                let value = b.boxed(value);
+                if let Some(var) = b.var(var) {
+                    b.push(Inst::SetVar(var, value));
+                }
+            }
+            Data::AddToList { item, list } => {
+                let item = b.boxed(item);
+                if let Some(list) = b.list(list) {
+                    b.push(Inst::ListPush(list, item));
+                }
+            }
+            Data::DeleteAllOfList { list } => {
+                if let Some(list) = b.list(list) {
+                    b.push(Inst::ListClear(list));
+                }
+            }
+            Data::DeleteOfList { index, list } => {
+                let index = b.boxed(index);
+                if let Some(list) = b.list(list) {
+                    b.push(Inst::ListDelete(list, index));
+                }
+            }
+            Data::InsertAtList { item, index, list } => {
+                let item = b.boxed(item);
+                let index = b.boxed(index);
+                if let Some(list) = b.list(list) {
+                    b.push(Inst::ListInsert { list, index, item });
+                }
+            }
+            Data::ReplaceItemOfList { index, item, list } => {
+                let index = b.boxed(index);
+                let item = b.boxed(item);
+                if let Some(list) = b.list(list) {
+                    b.push(Inst::ListReplace { list, index, item });
+                }
+            }
+            // missing lists are empty
+            Data::ItemOfList { index, list } => {
+                let index = b.boxed(index);
+                return Some(match b.list(list) {
+                    Some(list) => b.compute(Expr::ListItem(list, index)),
+                    None => Operand::Const(Const::String(String::new())),
+                });
+            }
+            Data::ItemNumOfList { item, list } => {
+                let item = b.boxed(item);
+                return Some(match b.list(list) {
+                    Some(list) => b.compute(Expr::ListIndexOf(list, item)),
+                    None => Operand::Const(Const::Number(0.0)),
+                });
+            }
+            Data::LengthOfList { list } => {
+                return Some(match b.list(list) {
+                    Some(list) => b.compute(Expr::ListLength(list)),
+                    None => Operand::Const(Const::Number(0.0)),
+                });
+            }
+            Data::ListContainsItem { list, item } => {
+                let item = b.boxed(item);
+                return Some(match b.list(list) {
+                    Some(list) => b.compute(Expr::ListContains(list, item)),
+                    None => Operand::Const(Const::Bool(false)),
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::parser::{List, ListDecl, TargetKind};
+
+    fn stage() -> parser::Target {
+        parser::Target {
+            name: "Stage".to_owned(),
+            sequences: vec![],
+            procedures: vec![],
+            vars: HashMap::new(),
+            lists: HashMap::new(),
+            current_costume: 0,
+            costumes: vec![],
+            kind: TargetKind::Stage { tempo: 60 },
+        }
+    }
+
+    fn list(id: &str) -> List {
+        List { name: "my list".to_owned(), id: id.to_owned(), input: "LIST".to_owned() }
+    }
+
+    fn string(s: &str) -> Operand {
+        Operand::Const(Const::String(s.to_owned()))
+    }
+
+    /// lowers the blocks in a function, returns the value of the last one
+    fn lower(blocks: &[Data], diagnostics: &mut Diagnostics) -> (Option<Operand>, Function) {
+        let targets = [stage()];
+        let mut globals = Globals::default();
+        globals.lists.insert("l1".to_owned(), ListDecl { cident: "lst".to_owned(), items: vec![] });
+        let mut b = Builder::new(&targets[0], &targets, &globals, diagnostics, FunctionKind::Sequence(0));
+        let mut value = None;
+        for block in blocks {
+            value = block.lower(&mut b);
+        }
+        (value, b.finish())
+    }
+
+    #[test]
+    fn lowers_list_blocks() {
+        let mut diagnostics = Diagnostics::default();
+        let text = |s: &str| Value::String(s.to_owned());
+        let blocks = [
+            Data::AddToList { item: text("x"), list: list("l1") },
+            Data::ReplaceItemOfList { index: text("last"), item: Value::Number(3.0), list: list("l1") },
+            Data::DeleteOfList { index: text("all"), list: list("l1") },
+            Data::ItemOfList { index: text("random"), list: list("l1") },
+        ];
+        let (value, function) = lower(&blocks, &mut diagnostics);
+        assert!(diagnostics.0.is_empty());
+
+        let lst = VarRef { scope: Scope::Global, cident: "lst".to_owned(), ty: Type::Value };
+        // items and indices are values, the runtime handles "last", "random" and "all"
+        assert_eq!(
+            function.blocks[0].insts,
+            [
+                Inst::ListPush(lst.clone(), string("x")),
+                Inst::Let(Temp(0), Expr::Convert(Type::Value, Operand::Const(Const::Number(3.0)))),
+                Inst::ListReplace { list: lst.clone(), index: string("last"), item: Operand::Temp(Temp(0)) },
+                Inst::ListDelete(lst.clone(), string("all")),
+                Inst::Let(Temp(1), Expr::ListItem(lst, string("random"))),
+            ]
+        );
+        assert_eq!(value, Some(Operand::Temp(Temp(1))));
+    }
+
+    #[test]
+    fn missing_lists_are_empty() {
+        let (zero, no) = (Operand::Const(Const::Number(0.0)), Operand::Const(Const::Bool(false)));
+        let reporters = [
+            (Data::ItemOfList { index: Value::Number(1.0), list: list("nope") }, string("")),
+            (Data::ItemNumOfList { item: Value::Number(1.0), list: list("nope") }, zero.clone()),
+            (Data::LengthOfList { list: list("nope") }, zero),
+            (Data::ListContainsItem { item: Value::Number(1.0), list: list("nope") }, no),
+        ];
+        for (block, empty) in reporters {
+            let mut diagnostics = Diagnostics::default();
+            let (value, _) = lower(&[block], &mut diagnostics);
+            assert_eq!(value, Some(empty));
+            assert_eq!(diagnostics.error_count(), 1);
+            assert_eq!(diagnostics.0[0].message, "list `my list` (ID `nope`) does not exist");
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::Event;
+use super::*;
+
+impl Lower for Event {
+    fn lower(&self, b: &mut Builder) -> Option<Operand> {
+        match self {
+            // hats are the first block of their script, so the script waits at its entry
+            Event::WhenFlagClicked => {
+                let entry = b.current;
+                let wait = b.new_block();
+                let body = b.new_block();
+                let clicked = b.compute(Expr::FlagClicked);
+                b.finish_block(Terminator::Branch(clicked, body, wait), wait);
+                b.finish_block(Terminator::Yield(entry), body);
+            }
+            Event::WhenBroadcastReceived { .. } => {
+                // started by `start_broadcasts` when the broadcast is sent
+                let body = b.new_block();
+                b.finish_block(Terminator::WaitForStart(body), body);
+            }
+            Event::Broadcast { message } => {
+                let message = b.boxed(message);
+                b.push(Inst::Broadcast(message, None));
+            }
+            Event::BroadcastAndWait { message } if b.warp() => {
+                // scratch waits for the receivers once the procedure ran out of time,
+                // but they can only start here once the warp procedure returns, so it only broadcasts
+                b.error(Diagnostic::warning(
+                    "\"broadcast and wait\" in a procedure run without screen refresh only broadcasts, without waiting",
+                ));
+                let message = b.boxed(message);
+                b.push(Inst::Broadcast(message, None));
+            }
+            Event::BroadcastAndWait { message } => {
+                let message = b.boxed(message);
+                let index = b.new_local("broadcast", Type::Number);
+                b.push(Inst::Broadcast(message, Some(index)));
+                b.wait_until(false, |b| {
+                    let running = b.compute(Expr::BroadcastRunning(index));
+                    b.compute(Expr::Unary(UnaryOp::Not, running))
+                });
+            }
+        }
+        None
+    }
+}
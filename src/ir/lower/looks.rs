@@ -0,0 +1,23 @@
+use crate::parser::Looks;
+use super::*;
+
+impl Lower for Looks {
+    fn lower(&self, b: &mut Builder) -> Option<Operand> {
+        match self {
+            Looks::SayForSecs { message, secs } => {
+                let message = b.boxed(message);
+                let secs = b.number(secs);
+                let now = b.compute(Expr::Time);
+                let end = b.compute(Expr::Binary(BinaryOp::Add, now, secs));
+                let time = b.new_local("time", Type::Number);
+                b.push(Inst::SetLocal(time, end.clone()));
+                b.push(Inst::Say(message, end));
+                b.push(Inst::RequestRedraw);
+
+                // the message is shown for at least one frame
+                b.wait_until(true, |b| b.time_reached(time));
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Lowering of the parsed blocks into [`Function`]s
+
+use std::collections::HashSet;
+
+use crate::diagnostic::{Diagnostic, Diagnostics, Kind, Level, Location};
+use crate::parser::{self, Block, Globals, LocatedBlock, Value};
+use super::*;
+
+mod motion;
+mod looks;
+mod event;
+mod control;
+mod operator;
+mod data;
+mod procedures;
+
+trait Lower {
+    /// adds the instructions of the block to the function, returns its value if the block is a reporter
+    fn lower(&self, b: &mut Builder) -> Option<Operand>;
+}
+
+/// Builds a function, one basic block at a time
+struct Builder<'a> {
+    target: &'a parser::Target,
+    /// every target of the project, to find the actors blocks refer to
+    targets: &'a [parser::Target],
+    globals: &'a Globals,
+    diagnostics: &'a mut Diagnostics,
+    kind: FunctionKind,
+    /// the custom block being lowered, if not lowering a sequence
+    procedure: Option<&'a parser::Procedure>,
+    /// the ID and opcode of the block being lowered, which diagnostics point to
+    location: Option<(String, String)>,
+    /// the instructions of every block, and their terminator once they are finished
+    blocks: Vec<(Vec<Inst>, Option<Terminator>)>,
+    /// blocks in the order they were started, which is their order in the function
+    order: Vec<BlockId>,
+    /// the block instructions are added to
+    current: BlockId,
+    temps: Vec<Type>,
+    locals: Vec<LocalDecl>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(
+        target: &'a parser::Target,
+        targets: &'a [parser::Target],
+        globals: &'a Globals,
+        diagnostics: &'a mut Diagnostics,
+        kind: FunctionKind,
+    ) -> Self {
+        let procedure = match kind {
+            FunctionKind::Sequence(_) => None,
+            FunctionKind::Procedure(i) | FunctionKind::WarpProcedure(i) => Some(&target.procedures[i]),
+        };
+        Builder {
+            target,
+            targets,
+            globals,
+            diagnostics,
+            kind,
+            procedure,
+            location: None,
+            blocks: vec![(vec![], None)],
+            order: vec![BlockId(0)],
+            current: BlockId(0),
+            temps: vec![],
+            locals: vec![],
+        }
+    }
+
+    /// records a diagnostic about the block being lowered
+    fn error(&mut self, diagnostic: Diagnostic) {
+        let mut diagnostic = diagnostic.with_sprite(&self.target.name);
+        if let Some((id, opcode)) = &self.location {
+            diagnostic = diagnostic.with_block(id).with_opcode(opcode);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// runs `lower` with diagnostics pointing to `block`
+    fn located<T>(&mut self, block: &LocatedBlock, lower: impl FnOnce(&mut Self) -> T) -> T {
+        let parent = self.location.replace((block.id.clone(), block.opcode.clone()));
+        let result = lower(self);
+        self.location = parent;
+        result
+    }
+
+    /// wether the function runs without screen refresh, where waits don't leave the function
+    fn warp(&self) -> bool {
+        matches!(self.kind, FunctionKind::WarpProcedure(_))
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((vec![], None));
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn new_local(&mut self, name: &'static str, ty: Type) -> Local {
+        self.locals.push(LocalDecl { name, ty });
+        Local(self.locals.len() - 1)
+    }
+
+    fn push(&mut self, inst: Inst) {
+        self.blocks[self.current.0].0.push(inst);
+    }
+
+    /// ends the current block with `terminator`, and continues in `next`
+    fn finish_block(&mut self, terminator: Terminator, next: BlockId) {
+        self.blocks[self.current.0].1 = Some(terminator);
+        self.current = next;
+        self.order.push(next);
+    }
+
+    /// ends the current block with `terminator`, the blocks after it are unreachable
+    fn finish_script(&mut self, terminator: Terminator) {
+        let next = self.new_block();
+        self.finish_block(terminator, next);
+    }
+
+    /// computes `expr` into a new temporary
+    fn compute(&mut self, expr: Expr) -> Operand {
+        let temp = Temp(self.temps.len());
+        self.temps.push(expr.ty(&self.locals));
+        self.push(Inst::Let(temp, expr));
+        Operand::Temp(temp)
+    }
+
+    fn new_temp(&mut self, ty: Type) -> Temp {
+        self.temps.push(ty);
+        Temp(self.temps.len() - 1)
+    }
+
+    fn convert(&mut self, operand: Operand, ty: Type) -> Operand {
+        if operand.ty(&self.temps) == ty {
+            operand
+        } else {
+            self.compute(Expr::Convert(ty, operand))
+        }
+    }
+
+    fn value(&mut self, value: &Value) -> Operand {
+        match value {
+            Value::Block { block, input } => {
+                // unsupported reporters evaluate to 0
+                if let Block::Unsupported { opcode, id } = &block.block {
+                    self.push(Inst::Unsupported { opcode: opcode.clone(), id: id.clone() });
+                    return Operand::Const(Const::Number(0.0));
+                }
+
+                match self.located(block, |b| b.block(&block.block)) {
+                    Some(value) => value,
+                    None => {
+                        let message = format!("expected a block that returns a value, found `{}`", block.opcode);
+                        self.error(Diagnostic::error(message).with_input(input));
+                        Operand::Const(Const::Number(0.0))
+                    }
+                }
+            }
+            Value::Number(n) => Operand::Const(Const::Number(*n)),
+            Value::Color(c) => Operand::Const(Const::Color(c.0, c.1, c.2)),
+            Value::String(s) => Operand::Const(Const::String(s.clone())),
+            // broadcasts are looked up by name
+            Value::Broadcast(b) => Operand::Const(Const::String(b.name.clone())),
+            Value::Variable(var) => match self.var(var) {
+                Some(var) => self.compute(Expr::Var(var)),
+                None => Operand::Const(Const::Number(0.0)),
+            },
+            Value::List(l) => match self.list(l) {
+                Some(list) => self.compute(Expr::ListJoin(list)),
+                None => Operand::Const(Const::String(String::new())),
+            },
+        }
+    }
+
+    fn number(&mut self, value: &Value) -> Operand {
+        let value = self.value(value);
+        self.convert(value, Type::Number)
+    }
+
+    fn boolean(&mut self, value: &Value) -> Operand {
+        let value = self.value(value);
+        self.convert(value, Type::Bool)
+    }
+
+    /// computes `value` as a `Value`, whatever its type
+    fn boxed(&mut self, value: &Value) -> Operand {
+        let value = self.value(value);
+        self.convert(value, Type::Value)
+    }
+
+    fn var(&mut self, var: &parser::Variable) -> Option<VarRef> {
+        if let Some(decl) = self.target.vars.get(&var.id) {
+            Some(VarRef { scope: Scope::Actor, cident: decl.cident.clone(), ty: Type::Value })
+        } else if let Some(decl) = self.globals.vars.get(&var.id) {
+            Some(VarRef { scope: Scope::Global, cident: decl.cident.clone(), ty: Type::Value })
+        } else {
+            self.error(Diagnostic::error(format!("variable `{}` (ID `{}`) does not exist", var.name, var.id)).with_input(&var.input));
+            None
+        }
+    }
+
+    fn list(&mut self, list: &parser::List) -> Option<ListRef> {
+        if let Some(decl) = self.target.lists.get(&list.id) {
+            Some(VarRef { scope: Scope::Actor, cident: decl.cident.clone(), ty: Type::Value })
+        } else if let Some(decl) = self.globals.lists.get(&list.id) {
+            Some(VarRef { scope: Scope::Global, cident: decl.cident.clone(), ty: Type::Value })
+        } else {
+            self.error(Diagnostic::error(format!("list `{}` (ID `{}`) does not exist", list.name, list.id)).with_input(&list.input));
+            None
+        }
+    }
+
+    /// the original actor of the sprite named `name`, chosen in the menu of `input` (clones can't be targeted by name)
+    fn original_actor(&mut self, name: &str, input: &str) -> Option<Actor> {
+        let exists = self.targets.iter().any(|t| t.name == name && matches!(t.kind, parser::TargetKind::Sprite { .. }));
+        if exists {
+            Some(Actor::Original(name.to_owned()))
+        } else {
+            self.error(Diagnostic::error(format!("sprite `{name}` does not exist")).with_input(input));
+            None
+        }
+    }
+
+    /// returns the value of the block if it is a reporter
+    fn block(&mut self, block: &Block) -> Option<Operand> {
+        match block {
+            Block::Motion(v) => v.lower(self),
+            Block::Looks(v) => v.lower(self),
+            Block::Event(v) => v.lower(self),
+            Block::Control(v) => v.lower(self),
+            Block::Operator(v) => v.lower(self),
+            Block::Data(v) => v.lower(self),
+            Block::Procedures(v) => v.lower(self),
+            Block::Unsupported { opcode, id } => {
+                self.push(Inst::Unsupported { opcode: opcode.clone(), id: id.clone() });
+                None
+            }
+        }
+    }
+
+    fn sequence(&mut self, sequence: &parser::Sequence) {
+        for block in &sequence.0 {
+            self.located(block, |b| {
+                if b.block(&block.block).is_some() {
+                    b.error(Diagnostic::error("block returning a value used as a statement"));
+                }
+            });
+        }
+    }
+
+    /// suspends the script until `done` is true, it is computed again on every frame.
+    /// If `yield_first` is set, the script always waits for at least one frame.
+    fn wait_until(&mut self, yield_first: bool, done: impl FnOnce(&mut Self) -> Operand) {
+        let check = self.new_block();
+        let wait = self.new_block();
+        let next = self.new_block();
+        let enter = if yield_first { Terminator::Yield(check) } else { Terminator::Jump(check) };
+        self.finish_block(enter, check);
+        let done = done(self);
+        self.finish_block(Terminator::Branch(done, next, wait), wait);
+        self.finish_block(Terminator::Yield(check), next);
+    }
+
+    /// wether the time in `local` (in seconds since the project started) has passed
+    fn time_reached(&mut self, local: Local) -> Operand {
+        let now = self.compute(Expr::Time);
+        let end = self.compute(Expr::Local(local));
+        let waiting = self.compute(Expr::Binary(BinaryOp::LesserThan, now, end));
+        self.compute(Expr::Unary(UnaryOp::Not, waiting))
+    }
+
+    fn finish(mut self) -> Function {
+        // the script ends after its last block
+        if self.blocks[self.current.0].1.is_none() {
+            self.blocks[self.current.0].1 = Some(Terminator::Return);
+        }
+
+        let mut ids = vec![BlockId(0); self.blocks.len()];
+        for (new, old) in self.order.iter().enumerate() {
+            ids[old.0] = BlockId(new);
+        }
+        let mut blocks: Vec<Option<(Vec<Inst>, Option<Terminator>)>> = self.blocks.into_iter().map(Some).collect();
+        let blocks = self.order.iter().map(|old| {
+            let (insts, terminator) = blocks[old.0].take().expect("blocks are only started once");
+            let mut terminator = terminator.unwrap_or(Terminator::Return);
+            for successor in terminator.successors_mut() {
+                *successor = ids[successor.0];
+            }
+            BasicBlock { insts, terminator }
+        }).collect();
+
+        Function { kind: self.kind, blocks, temps: self.temps, locals: self.locals }
+    }
+}
+
+fn lower_function(
+    target: &parser::Target,
+    targets: &[parser::Target],
+    globals: &Globals,
+    diagnostics: &mut Diagnostics,
+    kind: FunctionKind,
+) -> Function {
+    let mut builder = Builder::new(target, targets, globals, diagnostics, kind);
+    match kind {
+        FunctionKind::Sequence(i) => builder.sequence(&target.sequences[i]),
+        FunctionKind::Procedure(i) | FunctionKind::WarpProcedure(i) => builder.sequence(&target.procedures[i].body),
+    }
+    builder.finish()
+}
+
+/// what tells a diagnostic apart from the ones of the same block, reported by another version of its procedure
+fn same_diagnostic(diagnostic: &Diagnostic) -> (Option<&str>, Option<&str>, Option<&str>, Kind, Level, &str) {
+    let Location { block_id, opcode, input, .. } = &diagnostic.location;
+    (block_id.as_deref(), opcode.as_deref(), input.as_deref(), diagnostic.kind, diagnostic.level, &diagnostic.message)
+}
+
+/// Lowers every script and custom block of the project, where every variable holds a `Value`.
+/// Blocks that can't be compiled are reported in `diagnostics`.
+pub fn lower(targets: &[parser::Target], globals: &Globals, diagnostics: &mut Diagnostics) -> Program {
+    let mut lowered = vec![];
+    for target in targets {
+        let mut sequences = vec![];
+        for i in 0..target.sequences.len() {
+            sequences.push(lower_function(target, targets, globals, diagnostics, FunctionKind::Sequence(i)));
+        }
+
+        let warp = procedures::warp_procedures(target);
+        let mut procedures = vec![];
+        let mut warp_procedures = vec![];
+        for (i, procedure) in target.procedures.iter().enumerate() {
+            let reported = diagnostics.0.len();
+            // warp procedures are never pushed on a call stack
+            procedures.push(
+                (!procedure.warp).then(|| lower_function(target, targets, globals, diagnostics, FunctionKind::Procedure(i))),
+            );
+            if !warp[i] {
+                warp_procedures.push(None);
+                continue;
+            }
+
+            // procedures called by warp procedures are lowered twice, their diagnostics are only reported once
+            let mut warp_diagnostics = Diagnostics::default();
+            let function = lower_function(target, targets, globals, &mut warp_diagnostics, FunctionKind::WarpProcedure(i));
+            let reported: HashSet<_> = diagnostics.0[reported..].iter().map(same_diagnostic).collect();
+            let new: Vec<_> =
+                warp_diagnostics.0.into_iter().filter(|d| !reported.contains(&same_diagnostic(d))).collect();
+            diagnostics.0.extend(new);
+            warp_procedures.push(Some(function));
+        }
+
+        let vars = target.vars.values().map(|v| (v.cident.clone(), Type::Value)).collect();
+        lowered.push(super::Target { vars, sequences, procedures, warp_procedures });
+    }
+    let global_vars = globals.vars.values().map(|v| (v.cident.clone(), Type::Value)).collect();
+    Program { targets: lowered, global_vars }
+}
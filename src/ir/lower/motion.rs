@@ -0,0 +1,180 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::{self, GotoDestOption, Motion, PointTowardsOption};
+use super::*;
+
+impl Builder<'_> {
+    fn field(&mut self, actor: Actor, field: ActorField) -> Operand {
+        self.compute(Expr::ActorField(actor, field))
+    }
+
+    /// computes the position of a goto destination
+    fn destination(&mut self, dest: &GotoDestOption) -> (Operand, Operand) {
+        match dest {
+            GotoDestOption::Random => (self.compute(Expr::Random(-240, 240)), self.compute(Expr::Random(-180, 180))),
+            GotoDestOption::MouseCursor => (self.compute(Expr::MouseX), self.compute(Expr::MouseY)),
+            GotoDestOption::Actor(name) => {
+                // the actor stays in place if the sprite doesn't exist
+                let actor = self.original_actor(name, "TO").unwrap_or(Actor::This);
+                (self.field(actor.clone(), ActorField::X), self.field(actor, ActorField::Y))
+            }
+        }
+    }
+
+    /// changes a field of the actor by `by`
+    fn change(&mut self, field: ActorField, op: BinaryOp, by: Operand) {
+        let current = self.field(Actor::This, field);
+        let new = self.compute(Expr::Binary(op, current, by));
+        self.push(Inst::SetActorField(field, new));
+    }
+
+    /// moves the actor to (`x`, `y`) over `secs` seconds, one step every frame
+    fn glide(&mut self, secs: Operand, x: Operand, y: Operand) {
+        let from_x = self.new_local("glide_from_x", Type::Number);
+        let from_y = self.new_local("glide_from_y", Type::Number);
+        let to_x = self.new_local("glide_to_x", Type::Number);
+        let to_y = self.new_local("glide_to_y", Type::Number);
+        let duration = self.new_local("glide_secs", Type::Number);
+        let start = self.new_local("glide_start", Type::Number);
+
+        let current_x = self.field(Actor::This, ActorField::X);
+        let current_y = self.field(Actor::This, ActorField::Y);
+        self.push(Inst::SetLocal(from_x, current_x));
+        self.push(Inst::SetLocal(from_y, current_y));
+        self.push(Inst::SetLocal(to_x, x));
+        self.push(Inst::SetLocal(to_y, y));
+        self.push(Inst::SetLocal(duration, secs));
+        let now = self.compute(Expr::Time);
+        self.push(Inst::SetLocal(start, now));
+
+        let step = self.new_block();
+        let moving = self.new_block();
+        let arrived = self.new_block();
+        // the actor starts moving on the next frame
+        self.finish_block(Terminator::Yield(step), step);
+
+        let now = self.compute(Expr::Time);
+        let start = self.compute(Expr::Local(start));
+        let elapsed = self.compute(Expr::Binary(BinaryOp::Sub, now, start));
+        let duration = self.compute(Expr::Local(duration));
+        let gliding = self.compute(Expr::Binary(BinaryOp::LesserThan, elapsed.clone(), duration.clone()));
+        self.finish_block(Terminator::Branch(gliding, moving, arrived), moving);
+
+        let t = self.compute(Expr::Binary(BinaryOp::Div, elapsed, duration));
+        for (field, from, to) in [(ActorField::X, from_x, to_x), (ActorField::Y, from_y, to_y)] {
+            let from = self.compute(Expr::Local(from));
+            let to = self.compute(Expr::Local(to));
+            let distance = self.compute(Expr::Binary(BinaryOp::Sub, to, from.clone()));
+            let moved = self.compute(Expr::Binary(BinaryOp::Mul, distance, t.clone()));
+            let position = self.compute(Expr::Binary(BinaryOp::Add, from, moved));
+            self.push(Inst::SetActorField(field, position));
+        }
+        self.push(Inst::RequestRedraw);
+        self.finish_block(Terminator::Yield(step), arrived);
+
+        let next = self.new_block();
+        for (field, to) in [(ActorField::X, to_x), (ActorField::Y, to_y)] {
+            let to = self.compute(Expr::Local(to));
+            self.push(Inst::SetActorField(field, to));
+        }
+        self.push(Inst::RequestRedraw);
+        self.finish_block(Terminator::Jump(next), next);
+    }
+}
+
+impl Lower for Motion {
+    fn lower(&self, b: &mut Builder) -> Option<Operand> {
+        // the stage has no position or direction
+        if matches!(b.target.kind, parser::TargetKind::Stage { .. }) {
+            b.error(Diagnostic::error("motion blocks can only be used in sprites"));
+            return match self {
+                Motion::XPosition | Motion::YPosition | Motion::Direction => Some(Operand::Const(Const::Number(0.0))),
+                _ => None,
+            };
+        }
+
+        match self {
+            Motion::MoveSteps { steps } => {
+                let steps = b.number(steps);
+                b.push(Inst::MoveSteps(steps));
+            }
+            Motion::TurnRight { degrees } => {
+                let degrees = b.number(degrees);
+                b.change(ActorField::Direction, BinaryOp::Add, degrees);
+            }
+            Motion::TurnLeft { degrees } => {
+                let degrees = b.number(degrees);
+                b.change(ActorField::Direction, BinaryOp::Sub, degrees);
+            }
+            Motion::Goto { dest } => {
+                let (x, y) = b.destination(dest);
+                b.push(Inst::SetActorField(ActorField::X, x));
+                b.push(Inst::SetActorField(ActorField::Y, y));
+            }
+            Motion::GotoXY { x, y } => {
+                let x = b.number(x);
+                let y = b.number(y);
+                b.push(Inst::SetActorField(ActorField::X, x));
+                b.push(Inst::SetActorField(ActorField::Y, y));
+            }
+            Motion::Glide { secs, dest } => {
+                let secs = b.number(secs);
+                let (x, y) = b.destination(dest);
+                b.glide(secs, x, y);
+                return None;
+            }
+            Motion::GlideXY { secs, x, y } => {
+                let secs = b.number(secs);
+                let x = b.number(x);
+                let y = b.number(y);
+                b.glide(secs, x, y);
+                return None;
+            }
+            Motion::PointInDrection { degrees } => {
+                let degrees = b.number(degrees);
+                b.push(Inst::SetActorField(ActorField::Direction, degrees));
+            }
+            Motion::PointTowards { towards } => match towards {
+                PointTowardsOption::Random => {
+                    let direction = b.compute(Expr::Random(-180, 180));
+                    b.push(Inst::SetActorField(ActorField::Direction, direction));
+                }
+                PointTowardsOption::MouseCursor => {
+                    let x = b.compute(Expr::MouseX);
+                    let y = b.compute(Expr::MouseY);
+                    b.push(Inst::PointTowards(x, y));
+                }
+                PointTowardsOption::Actor(name) => {
+                    if let Some(actor) = b.original_actor(name, "TOWARDS") {
+                        let x = b.field(actor.clone(), ActorField::X);
+                        let y = b.field(actor, ActorField::Y);
+                        b.push(Inst::PointTowards(x, y));
+                    }
+                }
+            },
+            Motion::ChangeX { by } => {
+                let by = b.number(by);
+                b.change(ActorField::X, BinaryOp::Add, by);
+            }
+            Motion::SetX { to } => {
+                let to = b.number(to);
+                b.push(Inst::SetActorField(ActorField::X, to));
+            }
+            Motion::ChangeY { by } => {
+                let by = b.number(by);
+                b.change(ActorField::Y, BinaryOp::Add, by);
+            }
+            Motion::SetY { to } => {
+                let to = b.number(to);
+                b.push(Inst::SetActorField(ActorField::Y, to));
+            }
+            Motion::IfOnEdgeBounce => b.push(Inst::BounceOffEdge),
+            Motion::SetRotationStyle { style } => b.push(Inst::SetRotationStyle(*style)),
+            Motion::XPosition => return Some(b.field(Actor::This, ActorField::X)),
+            Motion::YPosition => return Some(b.field(Actor::This, ActorField::Y)),
+            Motion::Direction => return Some(b.field(Actor::This, ActorField::Direction)),
+        }
+
+        b.push(Inst::RequestRedraw);
+        None
+    }
+}
@@ -0,0 +1,34 @@
+use crate::parser::Operator;
+use super::*;
+
+impl Builder<'_> {
+    /// applies `op` to both operands, once they are converted to `ty`
+    fn binary(&mut self, op: BinaryOp, ty: Type, lhs: &parser::Value, rhs: &parser::Value) -> Option<Operand> {
+        let lhs = self.value(lhs);
+        let lhs = self.convert(lhs, ty);
+        let rhs = self.value(rhs);
+        let rhs = self.convert(rhs, ty);
+        Some(self.compute(Expr::Binary(op, lhs, rhs)))
+    }
+}
+
+impl Lower for Operator {
+    fn lower(&self, b: &mut Builder) -> Option<Operand> {
+        match self {
+            Operator::Add { lhs, rhs } => b.binary(BinaryOp::Add, Type::Number, lhs, rhs),
+            Operator::Sub { lhs, rhs } => b.binary(BinaryOp::Sub, Type::Number, lhs, rhs),
+            Operator::Mul { lhs, rhs } => b.binary(BinaryOp::Mul, Type::Number, lhs, rhs),
+            Operator::Div { lhs, rhs } => b.binary(BinaryOp::Div, Type::Number, lhs, rhs),
+            // comparisons follow scratch's rules for values, which can compare strings
+            Operator::GreaterThan { lhs, rhs } => b.binary(BinaryOp::GreaterThan, Type::Value, lhs, rhs),
+            Operator::LesserThan { lhs, rhs } => b.binary(BinaryOp::LesserThan, Type::Value, lhs, rhs),
+            Operator::Equals { lhs, rhs } => b.binary(BinaryOp::Equals, Type::Value, lhs, rhs),
+            Operator::And { lhs, rhs } => b.binary(BinaryOp::And, Type::Bool, lhs, rhs),
+            Operator::Or { lhs, rhs } => b.binary(BinaryOp::Or, Type::Bool, lhs, rhs),
+            Operator::Not { operand } => {
+                let operand = b.boolean(operand);
+                Some(b.compute(Expr::Unary(UnaryOp::Not, operand)))
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::{self, ArgumentKind, Block, Control, Procedures};
+use super::*;
+
+impl Lower for Procedures {
+    fn lower(&self, b: &mut Builder) -> Option<Operand> {
+        match self {
+            Procedures::Call { proccode, arguments } => {
+                let target = b.target;
+                let Some((index, procedure)) = target.procedures.iter().enumerate().find(|(_, p)| &p.proccode == proccode) else {
+                    // scratch ignores calls to custom blocks that were deleted
+                    b.error(Diagnostic::warning(format!("custom block `{proccode}` is not defined, calls to it do nothing")));
+                    return None;
+                };
+
+                let mut args = vec![];
+                for argument in &procedure.arguments {
+                    let value = match arguments.iter().find(|(id, _)| id == &argument.id) {
+                        Some((_, value)) => b.boxed(value),
+                        // empty slots are empty strings, which are also false
+                        None => Operand::Const(Const::String(String::new())),
+                    };
+                    args.push(value);
+                }
+
+                let next = b.new_block();
+                if b.warp() || procedure.warp {
+                    let stopped = b.new_temp(Type::Bool);
+                    b.push(Inst::CallWarp { procedure: index, args, stopped });
+                    let stop = b.new_block();
+                    b.finish_block(Terminator::Branch(Operand::Temp(stopped), stop, next), stop);
+                    b.finish_block(Terminator::Stop, next);
+                } else {
+                    // the script continues once the procedure returns
+                    b.finish_block(Terminator::Call { procedure: index, args, resume: next }, next);
+                }
+                None
+            }
+            Procedures::Argument { name, kind } => {
+                let index = b.procedure.and_then(|p| p.arguments.iter().position(|a| &a.name == name));
+                Some(match (index, kind) {
+                    (Some(index), _) => b.compute(Expr::Argument(index)),
+                    // arguments of other procedures, or used outside of a procedure
+                    (None, ArgumentKind::StringNumber) => Operand::Const(Const::Number(0.0)),
+                    (None, ArgumentKind::Boolean) => Operand::Const(Const::Bool(false)),
+                })
+            }
+        }
+    }
+}
+
+/// collects the proccodes of the custom blocks called by `sequence`
+fn find_calls<'a>(sequence: &'a parser::Sequence, calls: &mut Vec<&'a str>) {
+    for block in sequence.blocks() {
+        match block {
+            Block::Procedures(Procedures::Call { proccode, .. }) => calls.push(proccode),
+            Block::Control(
+                Control::Repeat { branch, .. }
+                | Control::RepeatUntil { branch, .. }
+                | Control::Forever { branch }
+                | Control::IfCondition { branch, .. },
+            ) => find_calls(branch, calls),
+            Block::Control(Control::IfElse { if_branch, else_branch, .. }) => {
+                find_calls(if_branch, calls);
+                find_calls(else_branch, calls);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// returns wether each procedure of `target` needs a warp version:
+/// warp procedures, and the procedures they call, which also run without screen refresh
+pub fn warp_procedures(target: &parser::Target) -> Vec<bool> {
+    let mut warp: Vec<bool> = target.procedures.iter().map(|p| p.warp).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, procedure) in target.procedures.iter().enumerate() {
+            if !warp[i] { continue }
+
+            let mut calls = vec![];
+            find_calls(&procedure.body, &mut calls);
+            for proccode in calls {
+                let Some(j) = target.procedures.iter().position(|p| p.proccode == proccode) else { continue };
+                if !warp[j] {
+                    warp[j] = true;
+                    changed = true;
+                }
+            }
+        }
+    }
+    warp
+}
@@ -0,0 +1,538 @@
+//! The lowered form of scripts, between the parsed blocks and the generated C.
+//!
+//! Every script and custom block becomes a [`Function`]: a control flow graph of basic blocks,
+//! where the places a script can be suspended until the next frame are explicit terminators.
+//! Blocks compute their values into typed temporaries, and every conversion between types is explicit,
+//! so passes over the IR and the C backend don't need to know about scratch blocks.
+
+use std::collections::HashMap;
+
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::options::Options;
+use crate::parser::{self, RotationStyle};
+
+mod analysis;
+mod cast;
+mod dce;
+mod display;
+mod fold;
+mod lower;
+mod types;
+
+pub use lower::lower;
+pub use cast::lowercase;
+pub use types::canonical_number;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Number,
+    Bool,
+    /// any scratch value (a number, a string, a boolean or a color), boxed in a `Value`
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Number(f64),
+    Bool(bool),
+    String(String),
+    Color(u8, u8, u8),
+}
+
+impl Const {
+    pub fn ty(&self) -> Type {
+        match self {
+            Const::Number(_) => Type::Number,
+            Const::Bool(_) => Type::Bool,
+            Const::String(_) | Const::Color(..) => Type::Value,
+        }
+    }
+}
+
+/// A temporary, computed once by an [`Inst::Let`].
+/// Temporaries don't survive the script being suspended, so they are only used before the next yield.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Temp(pub usize);
+
+/// A variable kept in the state of the function, which keeps its value while the script is suspended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Local(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Temp(Temp),
+    Const(Const),
+}
+
+impl Operand {
+    pub fn ty(&self, temps: &[Type]) -> Type {
+        match self {
+            Operand::Temp(t) => temps[t.0],
+            Operand::Const(c) => c.ty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// owned by the actor running the script
+    Actor,
+    Global,
+}
+
+/// A variable or list, resolved to its C identifier
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VarRef {
+    pub scope: Scope,
+    pub cident: String,
+    /// the type of the values of the variable, always [`Type::Value`] for the items of lists
+    pub ty: Type,
+}
+
+pub type ListRef = VarRef;
+
+/// An actor whose state is read or changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Actor {
+    /// the actor running the script
+    This,
+    /// the original (not a clone) of the sprite with this name
+    Original(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActorField {
+    X,
+    Y,
+    /// set directions are wrapped to (-180, 180]
+    Direction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    // numbers to a number
+    Add,
+    Sub,
+    Mul,
+    Div,
+    // two numbers, or two values compared with scratch's rules, to a boolean
+    LesserThan,
+    GreaterThan,
+    Equals,
+    // booleans to a boolean
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    /// boolean to boolean
+    Not,
+    /// number to the closest integer
+    Round,
+}
+
+/// A computation without side effects, saved in a temporary
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// converts the operand to the type, following scratch's rules
+    Convert(Type, Operand),
+    Binary(BinaryOp, Operand, Operand),
+    Unary(UnaryOp, Operand),
+    Var(VarRef),
+    /// an argument of the procedure being run
+    Argument(usize),
+    Local(Local),
+    ListItem(ListRef, Operand),
+    ListIndexOf(ListRef, Operand),
+    ListLength(ListRef),
+    ListContains(ListRef, Operand),
+    /// the items of the list joined into a string
+    ListJoin(ListRef),
+    ActorField(Actor, ActorField),
+    /// wether the actor running the script is a clone
+    IsClone,
+    MouseX,
+    MouseY,
+    /// a random integer between the two bounds (included)
+    Random(i32, i32),
+    /// seconds since the project started
+    Time,
+    FlagClicked,
+    /// wether the scripts started by the broadcast whose index is in the local are still running
+    BroadcastRunning(Local),
+}
+
+impl Expr {
+    pub fn ty(&self, locals: &[LocalDecl]) -> Type {
+        match self {
+            Expr::Convert(ty, _) => *ty,
+            Expr::Binary(op, ..) => match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => Type::Number,
+                _ => Type::Bool,
+            },
+            Expr::Unary(UnaryOp::Not, _) => Type::Bool,
+            Expr::Unary(UnaryOp::Round, _) => Type::Number,
+            Expr::Local(local) => locals[local.0].ty,
+            Expr::Var(var) => var.ty,
+            Expr::Argument(_) | Expr::ListItem(..) | Expr::ListJoin(_) => Type::Value,
+            Expr::ListIndexOf(..)
+            | Expr::ListLength(_)
+            | Expr::ActorField(..)
+            | Expr::MouseX
+            | Expr::MouseY
+            | Expr::Random(..)
+            | Expr::Time => Type::Number,
+            Expr::ListContains(..) | Expr::IsClone | Expr::FlagClicked | Expr::BroadcastRunning(_) => Type::Bool,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Expr::Convert(_, a) | Expr::Unary(_, a) => vec![a],
+            Expr::Binary(_, a, b) => vec![a, b],
+            Expr::ListItem(_, a) | Expr::ListIndexOf(_, a) | Expr::ListContains(_, a) => vec![a],
+            _ => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Expr::Convert(_, a) | Expr::Unary(_, a) => vec![a],
+            Expr::Binary(_, a, b) => vec![a, b],
+            Expr::ListItem(_, a) | Expr::ListIndexOf(_, a) | Expr::ListContains(_, a) => vec![a],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Let(Temp, Expr),
+    SetLocal(Local, Operand),
+    SetVar(VarRef, Operand),
+    ListPush(ListRef, Operand),
+    ListClear(ListRef),
+    ListDelete(ListRef, Operand),
+    ListInsert { list: ListRef, index: Operand, item: Operand },
+    ListReplace { list: ListRef, index: Operand, item: Operand },
+    /// sends the broadcast named by the operand, saving its index in the local to wait for its receivers
+    Broadcast(Operand, Option<Local>),
+    /// changes the state of the actor running the script
+    SetActorField(ActorField, Operand),
+    MoveSteps(Operand),
+    /// points the actor towards the position
+    PointTowards(Operand, Operand),
+    BounceOffEdge,
+    SetRotationStyle(RotationStyle),
+    /// shows the message (a value) until the time (in seconds since the project started)
+    Say(Operand, Operand),
+    /// asks for the screen to be redrawn if the actor is visible, loops yield until the next frame
+    RequestRedraw,
+    CreateClone { sprite: String, parent: Actor },
+    /// marks the actor as deleted, it is freed at the end of the frame
+    DeleteClone,
+    StopOtherScripts,
+    /// runs the warp version of a procedure entirely, `stopped` is set if it stopped the script
+    CallWarp { procedure: usize, args: Vec<Operand>, stopped: Temp },
+    /// a block skipped at runtime
+    Unsupported { opcode: String, id: String },
+}
+
+impl Inst {
+    /// the operands used by the instruction, not the temporaries it defines
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Inst::Let(_, expr) => expr.operands(),
+            Inst::SetLocal(_, a)
+            | Inst::SetVar(_, a)
+            | Inst::ListPush(_, a)
+            | Inst::ListDelete(_, a)
+            | Inst::Broadcast(a, _)
+            | Inst::SetActorField(_, a)
+            | Inst::MoveSteps(a) => vec![a],
+            Inst::ListInsert { index, item, .. } | Inst::ListReplace { index, item, .. } => vec![index, item],
+            Inst::PointTowards(a, b) | Inst::Say(a, b) => vec![a, b],
+            Inst::CallWarp { args, .. } => args.iter().collect(),
+            Inst::ListClear(_)
+            | Inst::BounceOffEdge
+            | Inst::SetRotationStyle(_)
+            | Inst::RequestRedraw
+            | Inst::CreateClone { .. }
+            | Inst::DeleteClone
+            | Inst::StopOtherScripts
+            | Inst::Unsupported { .. } => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Inst::Let(_, expr) => expr.operands_mut(),
+            Inst::SetLocal(_, a)
+            | Inst::SetVar(_, a)
+            | Inst::ListPush(_, a)
+            | Inst::ListDelete(_, a)
+            | Inst::Broadcast(a, _)
+            | Inst::SetActorField(_, a)
+            | Inst::MoveSteps(a) => vec![a],
+            Inst::ListInsert { index, item, .. } | Inst::ListReplace { index, item, .. } => vec![index, item],
+            Inst::PointTowards(a, b) | Inst::Say(a, b) => vec![a, b],
+            Inst::CallWarp { args, .. } => args.iter_mut().collect(),
+            Inst::ListClear(_)
+            | Inst::BounceOffEdge
+            | Inst::SetRotationStyle(_)
+            | Inst::RequestRedraw
+            | Inst::CreateClone { .. }
+            | Inst::DeleteClone
+            | Inst::StopOtherScripts
+            | Inst::Unsupported { .. } => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// goes to the first block if the boolean is true, to the second otherwise
+    Branch(Operand, BlockId, BlockId),
+    /// suspends the script until the next frame, it then continues at the block.
+    /// Warp procedures never leave, they only redraw the screen once they timed out.
+    Yield(BlockId),
+    /// end of a loop iteration: suspends the script if something needs to be redrawn, or if it ran for too long
+    LoopBack(BlockId),
+    /// stays at the hat until the script is started from outside (by a broadcast or a new clone), at the block
+    WaitForStart(BlockId),
+    /// pushes a call to a procedure on the call stack, the script continues at the block once it returns
+    Call { procedure: usize, args: Vec<Operand>, resume: BlockId },
+    /// the script or procedure ended
+    Return,
+    /// ends the script, along with the procedures it is running, like deleting its clone.
+    /// "stop this script" in a procedure is a `Return`, as it only leaves the procedure.
+    Stop,
+    StopAll,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(b)
+            | Terminator::Yield(b)
+            | Terminator::LoopBack(b)
+            | Terminator::WaitForStart(b)
+            | Terminator::Call { resume: b, .. } => vec![*b],
+            Terminator::Branch(_, a, b) => vec![*a, *b],
+            Terminator::Return | Terminator::Stop | Terminator::StopAll => vec![],
+        }
+    }
+
+    fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(b)
+            | Terminator::Yield(b)
+            | Terminator::LoopBack(b)
+            | Terminator::WaitForStart(b)
+            | Terminator::Call { resume: b, .. } => vec![b],
+            Terminator::Branch(_, a, b) => vec![a, b],
+            Terminator::Return | Terminator::Stop | Terminator::StopAll => vec![],
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Terminator::Branch(condition, ..) => vec![condition],
+            Terminator::Call { args, .. } => args.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Terminator::Branch(condition, ..) => vec![condition],
+            Terminator::Call { args, .. } => args.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// the block the script continues at after being suspended, if it can be
+    pub fn resume_point(&self) -> Option<BlockId> {
+        match self {
+            Terminator::Yield(b)
+            | Terminator::LoopBack(b)
+            | Terminator::WaitForStart(b)
+            | Terminator::Call { resume: b, .. } => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    /// a script of the target, with the index of its sequence
+    Sequence(usize),
+    /// a custom block run on the call stack of a script, which can yield
+    Procedure(usize),
+    /// a custom block run without screen refresh, entirely when called
+    WarpProcedure(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalDecl {
+    /// what the local is used for, to name it in the generated code
+    pub name: &'static str,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub kind: FunctionKind,
+    /// starts at the first block, and the others are in the order they were written
+    pub blocks: Vec<BasicBlock>,
+    pub temps: Vec<Type>,
+    pub locals: Vec<LocalDecl>,
+}
+
+impl Function {
+    /// removes the computations of temporaries that are never used, and numbers the others again
+    pub fn remove_unused_temps(&mut self) {
+        loop {
+            let mut used = vec![false; self.temps.len()];
+            for block in &self.blocks {
+                let operands = block.insts.iter().flat_map(Inst::operands);
+                for operand in operands.chain(block.terminator.operands()) {
+                    if let Operand::Temp(t) = operand {
+                        used[t.0] = true;
+                    }
+                }
+            }
+            // expressions have no side effects, but warp calls always run
+            let mut removed = false;
+            for block in &mut self.blocks {
+                block.insts.retain(|inst| match inst {
+                    Inst::Let(t, _) if !used[t.0] => {
+                        removed = true;
+                        false
+                    }
+                    _ => true,
+                });
+            }
+            if removed { continue }
+
+            let mut numbers = vec![None; self.temps.len()];
+            let mut temps = vec![];
+            for block in &mut self.blocks {
+                for inst in &mut block.insts {
+                    if let Inst::Let(t, _) | Inst::CallWarp { stopped: t, .. } = inst {
+                        numbers[t.0] = Some(Temp(temps.len()));
+                        temps.push(self.temps[t.0]);
+                        *t = numbers[t.0].unwrap();
+                    }
+                }
+            }
+            for block in &mut self.blocks {
+                let operands = block.insts.iter_mut().flat_map(Inst::operands_mut);
+                for operand in operands.chain(block.terminator.operands_mut()) {
+                    if let Operand::Temp(t) = operand {
+                        *t = numbers[t.0].expect("used temporaries are defined");
+                    }
+                }
+            }
+            self.temps = temps;
+            return;
+        }
+    }
+}
+
+/// The lowered scripts and custom blocks of a target, indexed like the parsed ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    /// the type of each variable of the target, by C identifier
+    pub vars: HashMap<String, Type>,
+    pub sequences: Vec<Function>,
+    /// the version of each custom block run on the call stack, except for warp procedures which are never pushed
+    pub procedures: Vec<Option<Function>>,
+    /// the version of each custom block run without screen refresh,
+    /// for warp procedures and the procedures they call
+    pub warp_procedures: Vec<Option<Function>>,
+}
+
+impl Target {
+    pub fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.sequences.iter().chain(self.procedures.iter().flatten()).chain(self.warp_procedures.iter().flatten())
+    }
+}
+
+/// The lowered project, with a [`Target`] for every parsed one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub targets: Vec<Target>,
+    /// the type of each global variable, by C identifier
+    pub global_vars: HashMap<String, Type>,
+}
+
+/// what a function is in the project, to tell the user about it
+fn describe(function: &Function, target: &parser::Target) -> String {
+    match function.kind {
+        FunctionKind::Sequence(i) => format!("script {i}"),
+        FunctionKind::Procedure(i) => format!("custom block `{}`", target.procedures[i].proccode),
+        FunctionKind::WarpProcedure(i) => format!("custom block `{}` (run without screen refresh)", target.procedures[i].proccode),
+    }
+}
+
+/// Runs the passes improving the lowered program, before the C code is generated.
+/// With [`Options::verbose`], what was removed is reported in `diagnostics`.
+pub fn optimize(
+    program: &mut Program,
+    targets: &[parser::Target],
+    globals: &parser::Globals,
+    options: &Options,
+    diagnostics: &mut Diagnostics,
+) {
+    types::infer(program, targets, globals);
+
+    let mut notes = vec![];
+    for (target, lowered) in targets.iter().zip(&mut program.targets) {
+        let functions = lowered
+            .sequences
+            .iter_mut()
+            .chain(lowered.procedures.iter_mut().flatten())
+            .chain(lowered.warp_procedures.iter_mut().flatten());
+        for function in functions {
+            fold::fold(function);
+            let removed = dce::remove_unreachable_blocks(function);
+            if removed > 0 {
+                let message = format!("removed {removed} unreachable instruction(s) from {}", describe(function, target));
+                notes.push(Diagnostic::note(message).with_sprite(&target.name));
+            }
+        }
+    }
+    for (i, j, reason) in dce::remove_dead_scripts(program, targets) {
+        let message = format!("removed script {j}, which can never start: {reason}");
+        notes.push(Diagnostic::note(message).with_sprite(&targets[i].name));
+    }
+    for target in &mut program.targets {
+        let functions = target
+            .sequences
+            .iter_mut()
+            .chain(target.procedures.iter_mut().flatten())
+            .chain(target.warp_procedures.iter_mut().flatten());
+        for function in functions {
+            // the computations of folded constants
+            function.remove_unused_temps();
+        }
+    }
+
+    if options.verbose {
+        for note in notes {
+            diagnostics.push(note);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//! Type inference: finds the variables and temporaries always holding numbers or booleans,
+//! so they are kept as raw `double` and `bool` instead of being boxed in a `Value`.
+//!
+//! Lowering boxes every value it doesn't know the type of, so each function is rewritten
+//! to see through those boxes, and only box values where a `Value` is really needed.
+//! Lists and procedure arguments always hold `Value`s.
+//! Lists aren't inferred even when only numbers are pushed to them: an item out of range reads as an empty string,
+//! so reading a list of numbers still gives a `Value`, and storing raw numbers would only move the boxing to every read.
+
+use std::collections::HashMap;
+
+use crate::parser::{self, Literal};
+use super::*;
+
+/// the number a string holds, if it behaves exactly like that number:
+/// converting the number back to a string gives the same string.
+/// "NaN" isn't one, as arithmetic on it would give NaN where scratch gives a number.
+pub fn canonical_number(s: &str) -> Option<f64> {
+    let n = cast::string_to_number(s);
+    (!n.is_nan() && cast::number_to_string(n) == s).then_some(n)
+}
+
+fn literal_type(literal: &Literal) -> Type {
+    match literal {
+        Literal::Number(_) => Type::Number,
+        Literal::Bool(_) => Type::Bool,
+        Literal::String(s) if canonical_number(s).is_some() => Type::Number,
+        Literal::String(_) => Type::Value,
+    }
+}
+
+/// a variable, with the index of its target if it is owned by an actor
+type VarKey = (Option<usize>, String);
+
+fn var_key(target: usize, var: &VarRef) -> VarKey {
+    (matches!(var.scope, Scope::Actor).then_some(target), var.cident.clone())
+}
+
+/// Rewrites a function with the types of the variables
+struct Rewriter<'a> {
+    function: &'a Function,
+    vars: &'a HashMap<VarKey, Type>,
+    target: usize,
+    /// the temporaries of the rewritten function
+    temps: Vec<Type>,
+    /// the new operand of each temporary of the function
+    map: Vec<Option<Operand>>,
+    /// the number or boolean boxed by each new temporary, to unbox it again without a conversion
+    unboxed: HashMap<Temp, Operand>,
+    insts: Vec<Inst>,
+    /// every variable set by the function, with the type of the value it is set to
+    stores: Vec<(VarKey, Type)>,
+}
+
+impl Rewriter<'_> {
+    fn ty(&self, operand: &Operand) -> Type {
+        operand.ty(&self.temps)
+    }
+
+    fn var(&self, var: &VarRef) -> VarRef {
+        let ty = self.vars.get(&var_key(self.target, var)).copied().unwrap_or(Type::Value);
+        VarRef { ty, ..var.clone() }
+    }
+
+    fn compute(&mut self, expr: Expr) -> Operand {
+        let temp = Temp(self.temps.len());
+        self.temps.push(expr.ty(&self.function.locals));
+        self.insts.push(Inst::Let(temp, expr));
+        Operand::Temp(temp)
+    }
+
+    /// the rewritten operand, of whatever type it has now
+    fn get(&self, operand: &Operand) -> Operand {
+        match operand {
+            Operand::Temp(t) => self.map[t.0].clone().expect("temporaries are defined before they are used"),
+            Operand::Const(c) => Operand::Const(c.clone()),
+        }
+    }
+
+    /// the value inside of a boxed operand, or the operand itself
+    fn unbox(&self, operand: Operand) -> Operand {
+        match &operand {
+            Operand::Temp(t) => self.unboxed.get(t).cloned().unwrap_or(operand),
+            Operand::Const(Const::String(s)) => match canonical_number(s) {
+                Some(n) => Operand::Const(Const::Number(n)),
+                None => operand,
+            },
+            Operand::Const(_) => operand,
+        }
+    }
+
+    /// converts a rewritten operand to `ty`
+    fn coerce(&mut self, operand: Operand, ty: Type) -> Operand {
+        if self.ty(&operand) == ty {
+            return operand;
+        }
+        if ty == Type::Value {
+            let boxed = self.compute(Expr::Convert(Type::Value, operand.clone()));
+            if let Operand::Temp(t) = boxed {
+                self.unboxed.insert(t, operand);
+            }
+            return boxed;
+        }
+        let inner = self.unbox(operand);
+        if self.ty(&inner) == ty {
+            inner
+        } else {
+            self.compute(Expr::Convert(ty, inner))
+        }
+    }
+
+    /// rewrites an operand, converted to the type it had before
+    fn operand(&mut self, operand: &Operand) -> Operand {
+        let ty = operand.ty(&self.function.temps);
+        let new = self.get(operand);
+        self.coerce(new, ty)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Operand {
+        match expr {
+            Expr::Convert(ty, operand) => {
+                let operand = self.get(operand);
+                self.coerce(operand, *ty)
+            }
+            Expr::Binary(op @ (BinaryOp::LesserThan | BinaryOp::GreaterThan | BinaryOp::Equals), lhs, rhs) => {
+                let (lhs, rhs) = (self.get(lhs), self.get(rhs));
+                let (a, b) = (self.unbox(lhs.clone()), self.unbox(rhs.clone()));
+                // scratch compares numbers and booleans as numbers
+                if self.ty(&a) != Type::Value && self.ty(&b) != Type::Value {
+                    let a = self.coerce(a, Type::Number);
+                    let b = self.coerce(b, Type::Number);
+                    self.compute(Expr::Binary(*op, a, b))
+                } else {
+                    let lhs = self.coerce(lhs, Type::Value);
+                    let rhs = self.coerce(rhs, Type::Value);
+                    self.compute(Expr::Binary(*op, lhs, rhs))
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.operand(lhs);
+                let rhs = self.operand(rhs);
+                self.compute(Expr::Binary(*op, lhs, rhs))
+            }
+            Expr::Unary(op, operand) => {
+                let operand = self.operand(operand);
+                self.compute(Expr::Unary(*op, operand))
+            }
+            Expr::Var(var) => {
+                let var = self.var(var);
+                self.compute(Expr::Var(var))
+            }
+            Expr::ListItem(list, index) => {
+                let index = self.operand(index);
+                self.compute(Expr::ListItem(list.clone(), index))
+            }
+            Expr::ListIndexOf(list, item) => {
+                let item = self.operand(item);
+                self.compute(Expr::ListIndexOf(list.clone(), item))
+            }
+            Expr::ListContains(list, item) => {
+                let item = self.operand(item);
+                self.compute(Expr::ListContains(list.clone(), item))
+            }
+            Expr::Argument(_)
+            | Expr::Local(_)
+            | Expr::ListLength(_)
+            | Expr::ListJoin(_)
+            | Expr::ActorField(..)
+            | Expr::IsClone
+            | Expr::MouseX
+            | Expr::MouseY
+            | Expr::Random(..)
+            | Expr::Time
+            | Expr::FlagClicked
+            | Expr::BroadcastRunning(_) => self.compute(expr.clone()),
+        }
+    }
+
+    fn inst(&mut self, inst: &Inst) {
+        let inst = match inst {
+            Inst::Let(temp, expr) => {
+                let value = self.expr(expr);
+                self.map[temp.0] = Some(value);
+                return;
+            }
+            Inst::SetVar(var, value) => {
+                let var = self.var(var);
+                let value = self.get(value);
+                let inner = self.unbox(value.clone());
+                self.stores.push((var_key(self.target, &var), self.ty(&inner)));
+                let value = self.coerce(value, var.ty);
+                Inst::SetVar(var, value)
+            }
+            Inst::SetLocal(local, value) => Inst::SetLocal(*local, self.operand(value)),
+            Inst::ListPush(list, item) => Inst::ListPush(list.clone(), self.operand(item)),
+            Inst::ListDelete(list, index) => Inst::ListDelete(list.clone(), self.operand(index)),
+            Inst::ListInsert { list, index, item } => {
+                let index = self.operand(index);
+                let item = self.operand(item);
+                Inst::ListInsert { list: list.clone(), index, item }
+            }
+            Inst::ListReplace { list, index, item } => {
+                let index = self.operand(index);
+                let item = self.operand(item);
+                Inst::ListReplace { list: list.clone(), index, item }
+            }
+            Inst::Broadcast(message, index) => Inst::Broadcast(self.operand(message), *index),
+            Inst::SetActorField(field, value) => Inst::SetActorField(*field, self.operand(value)),
+            Inst::MoveSteps(steps) => Inst::MoveSteps(self.operand(steps)),
+            Inst::PointTowards(x, y) => {
+                let x = self.operand(x);
+                let y = self.operand(y);
+                Inst::PointTowards(x, y)
+            }
+            Inst::Say(message, until) => {
+                let message = self.operand(message);
+                let until = self.operand(until);
+                Inst::Say(message, until)
+            }
+            Inst::CallWarp { procedure, args, stopped } => {
+                let args = args.iter().map(|arg| self.operand(arg)).collect();
+                let new = Temp(self.temps.len());
+                self.temps.push(Type::Bool);
+                self.map[stopped.0] = Some(Operand::Temp(new));
+                Inst::CallWarp { procedure: *procedure, args, stopped: new }
+            }
+            Inst::ListClear(_)
+            | Inst::BounceOffEdge
+            | Inst::SetRotationStyle(_)
+            | Inst::RequestRedraw
+            | Inst::CreateClone { .. }
+            | Inst::DeleteClone
+            | Inst::StopOtherScripts
+            | Inst::Unsupported { .. } => inst.clone(),
+        };
+        self.insts.push(inst);
+    }
+
+    fn terminator(&mut self, terminator: &Terminator) -> Terminator {
+        match terminator {
+            Terminator::Branch(condition, then, otherwise) => {
+                Terminator::Branch(self.operand(condition), *then, *otherwise)
+            }
+            Terminator::Call { procedure, args, resume } => {
+                let args = args.iter().map(|arg| self.operand(arg)).collect();
+                Terminator::Call { procedure: *procedure, args, resume: *resume }
+            }
+            _ => terminator.clone(),
+        }
+    }
+}
+
+/// rewrites `function` with the types in `vars`, returns it with the variables it sets
+fn rewrite(function: &Function, vars: &HashMap<VarKey, Type>, target: usize) -> (Function, Vec<(VarKey, Type)>) {
+    let mut rewriter = Rewriter {
+        function,
+        vars,
+        target,
+        temps: vec![],
+        map: vec![None; function.temps.len()],
+        unboxed: HashMap::new(),
+        insts: vec![],
+        stores: vec![],
+    };
+    let mut blocks = vec![];
+    for block in &function.blocks {
+        for inst in &block.insts {
+            rewriter.inst(inst);
+        }
+        let terminator = rewriter.terminator(&block.terminator);
+        blocks.push(BasicBlock { insts: std::mem::take(&mut rewriter.insts), terminator });
+    }
+    let function = Function { kind: function.kind, blocks, temps: rewriter.temps, locals: function.locals.clone() };
+    (function, rewriter.stores)
+}
+
+/// Infers the type of every variable, and rewrites the functions to use them.
+/// A variable has a type other than [`Type::Value`] if its initial value and every value it is set to have that type.
+pub fn infer(program: &mut Program, targets: &[parser::Target], globals: &parser::Globals) {
+    let mut vars: HashMap<VarKey, Type> = HashMap::new();
+    for (i, target) in targets.iter().enumerate() {
+        for var in target.vars.values() {
+            vars.insert((Some(i), var.cident.clone()), literal_type(&var.value));
+        }
+    }
+    for var in globals.vars.values() {
+        vars.insert((None, var.cident.clone()), literal_type(&var.value));
+    }
+
+    // a variable set to a value of another type holds values, which can change what other variables are set to
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, target) in program.targets.iter().enumerate() {
+            for function in target.functions() {
+                let (_, stores) = rewrite(function, &vars, i);
+                for (key, ty) in stores {
+                    let current = vars.entry(key).or_insert(Type::Value);
+                    if *current != ty && *current != Type::Value {
+                        *current = Type::Value;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    for (i, target) in program.targets.iter_mut().enumerate() {
+        let functions = target
+            .sequences
+            .iter_mut()
+            .chain(target.procedures.iter_mut().flatten())
+            .chain(target.warp_procedures.iter_mut().flatten());
+        for function in functions {
+            *function = rewrite(function, &vars, i).0;
+            // the boxes around values that turned out to be numbers and booleans
+            function.remove_unused_temps();
+        }
+        for (cident, ty) in &mut target.vars {
+            *ty = vars[&(Some(i), cident.clone())];
+        }
+    }
+    for (cident, ty) in &mut program.global_vars {
+        *ty = vars[&(None, cident.clone())];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::VarDecl;
+
+    fn global(cident: &str, ty: Type) -> VarRef {
+        VarRef { scope: Scope::Global, cident: cident.to_owned(), ty }
+    }
+
+    fn function(insts: Vec<Inst>, temps: Vec<Type>) -> Function {
+        let blocks = vec![BasicBlock { insts, terminator: Terminator::Return }];
+        Function { kind: FunctionKind::Sequence(0), blocks, temps, locals: vec![] }
+    }
+
+    #[test]
+    fn canonical_numbers() {
+        assert_eq!(canonical_number("12"), Some(12.0));
+        assert_eq!(canonical_number("-0.5"), Some(-0.5));
+        assert_eq!(canonical_number("Infinity"), Some(f64::INFINITY));
+        for s in ["012", "1e3", " 1", "0x10", "", "NaN", "abc", "-0"] {
+            assert_eq!(canonical_number(s), None, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn variables_take_the_type_of_their_values() {
+        let mut globals = parser::Globals::default();
+        let initial = [("n", Literal::Number(0.0)), ("s", Literal::Number(0.0)), ("copy", Literal::Number(0.0))];
+        for (cident, value) in initial.into_iter().chain([("flag", Literal::Bool(false))]) {
+            globals.vars.insert(cident.to_owned(), VarDecl { cident: cident.to_owned(), value });
+        }
+
+        // n = 3, s = "abc", copy = s, flag = true
+        let insts = vec![
+            Inst::Let(Temp(0), Expr::Convert(Type::Value, Operand::Const(Const::Number(3.0)))),
+            Inst::SetVar(global("n", Type::Value), Operand::Temp(Temp(0))),
+            Inst::SetVar(global("s", Type::Value), Operand::Const(Const::String("abc".to_owned()))),
+            Inst::Let(Temp(1), Expr::Var(global("s", Type::Value))),
+            Inst::SetVar(global("copy", Type::Value), Operand::Temp(Temp(1))),
+            Inst::Let(Temp(2), Expr::Convert(Type::Value, Operand::Const(Const::Bool(true)))),
+            Inst::SetVar(global("flag", Type::Value), Operand::Temp(Temp(2))),
+        ];
+        let target = Target {
+            vars: HashMap::new(),
+            sequences: vec![function(insts, vec![Type::Value; 3])],
+            procedures: vec![],
+            warp_procedures: vec![],
+        };
+        let global_vars = globals.vars.keys().map(|cident| (cident.clone(), Type::Value)).collect();
+        let mut program = Program { targets: vec![target], global_vars };
+        infer(&mut program, &[], &globals);
+
+        assert_eq!(program.global_vars["n"], Type::Number);
+        assert_eq!(program.global_vars["s"], Type::Value);
+        // only known once `s` holds values
+        assert_eq!(program.global_vars["copy"], Type::Value);
+        assert_eq!(program.global_vars["flag"], Type::Bool);
+        // the number is stored without being boxed
+        let insts = &program.targets[0].sequences[0].blocks[0].insts;
+        assert_eq!(insts[0], Inst::SetVar(global("n", Type::Number), Operand::Const(Const::Number(3.0))));
+    }
+
+    #[test]
+    fn numbers_are_compared_unboxed() {
+        let vars = HashMap::from([((None, "n".to_owned()), Type::Number)]);
+        let compare = |with: &str| {
+            let with = Operand::Const(Const::String(with.to_owned()));
+            let insts = vec![
+                Inst::Let(Temp(0), Expr::Var(global("n", Type::Value))),
+                Inst::Let(Temp(1), Expr::Binary(BinaryOp::Equals, Operand::Temp(Temp(0)), with)),
+            ];
+            let mut function = function(insts, vec![Type::Value, Type::Bool]);
+            function.blocks[0].terminator = Terminator::Branch(Operand::Temp(Temp(1)), BlockId(0), BlockId(0));
+            let (mut function, _) = rewrite(&function, &vars, 0);
+            function.remove_unused_temps();
+            function.blocks.remove(0).insts
+        };
+
+        let five = Operand::Const(Const::Number(5.0));
+        assert_eq!(
+            compare("5"),
+            vec![
+                Inst::Let(Temp(0), Expr::Var(global("n", Type::Number))),
+                Inst::Let(Temp(1), Expr::Binary(BinaryOp::Equals, Operand::Temp(Temp(0)), five)),
+            ]
+        );
+        // "05" isn't written like the number it holds, so it is compared as a value at runtime
+        let (boxed, string) = (Operand::Temp(Temp(1)), Operand::Const(Const::String("05".to_owned())));
+        assert_eq!(
+            compare("05")[1..],
+            [
+                Inst::Let(Temp(1), Expr::Convert(Type::Value, Operand::Temp(Temp(0)))),
+                Inst::Let(Temp(2), Expr::Binary(BinaryOp::Equals, boxed, string)),
+            ]
+        );
+    }
+}
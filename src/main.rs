@@ -1,16 +1,154 @@
+use std::error::Error;
+use std::path::Path;
+use std::process::ExitCode;
+
+use clap::Parser;
+
+mod cli;
+mod compat;
+mod diagnostic;
 mod scratch;
 mod parser;
+mod ir;
 mod generator;
+mod options;
+mod project;
+
+use cli::{Cli, Command, Emit, Format};
+use diagnostic::Diagnostics;
+use options::Options;
+
+/// runtime files the generated code is compiled with
+const RUNTIME_HEADER: &str = include_str!("../runtime.h");
+const RUNTIME_SOURCE: &str = include_str!("../runtime.c");
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let mut diagnostics = Diagnostics::default();
+    let result = run(cli.command, &mut diagnostics);
+    diagnostics.report();
+
+    match result {
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) if diagnostics.has_errors() => ExitCode::FAILURE,
+        Ok(code) => code,
+    }
+}
+
+/// Runs a command.
+/// Errors in the project are added to `diagnostics`, and stop the command before anything is written.
+fn run(command: Command, diagnostics: &mut Diagnostics) -> Result<ExitCode, Box<dyn Error>> {
+    match command {
+        Command::Compile { input, output, options, emit } => {
+            let options = Options::from(options);
+            let files = project::load(&input.input)?;
+            if emit == Emit::Project {
+                println!("{:#?}", files.project);
+                return Ok(ExitCode::SUCCESS);
+            }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let data = std::fs::read_to_string("./project/project.json")?;
+            let (targets, globals) = parser::parse(files.project.targets, &files.assets, &options, diagnostics);
+            if diagnostics.has_errors() { return Ok(ExitCode::FAILURE) }
+            if emit == Emit::Parsed {
+                println!("{targets:#?}");
+                println!("{globals:#?}");
+                return Ok(ExitCode::SUCCESS);
+            }
 
-    let project: scratch::Project = serde_json::from_str(&data)?;
-    let targets = project.targets;
-    let (targets, globals) = parser::parse(targets);
+            let mut program = ir::lower(&targets, &globals, diagnostics);
+            if diagnostics.has_errors() { return Ok(ExitCode::FAILURE) }
+            ir::optimize(&mut program, &targets, &globals, &options, diagnostics);
+            if diagnostics.has_errors() { return Ok(ExitCode::FAILURE) }
+            if emit == Emit::Ir {
+                for (target, lowered) in targets.iter().zip(&program.targets) {
+                    println!("{}:", target.name);
+                    for function in lowered.functions() {
+                        println!("{function}");
+                    }
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
 
-    let mut header = std::fs::File::create("output.h")?;
-    let mut source = std::fs::File::create("output.c")?;
-    generator::generate(&mut header, &mut source, &targets, &globals)?;
+            write_c(&output.out_dir, &targets, &program, &globals)?;
+        }
+        Command::Check { input, format } => {
+            let files = project::load(&input.input)?;
+            let report = compat::check(files, diagnostics);
+            match format {
+                Format::Human => print!("{report}"),
+                Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            }
+            if !report.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Build { input, output, options, executable, cc, cflags } => {
+            let options = Options::from(options);
+            let files = project::load(&input.input)?;
+            let (targets, globals) = parser::parse(files.project.targets, &files.assets, &options, diagnostics);
+            if diagnostics.has_errors() { return Ok(ExitCode::FAILURE) }
+            let mut program = ir::lower(&targets, &globals, diagnostics);
+            if diagnostics.has_errors() { return Ok(ExitCode::FAILURE) }
+            ir::optimize(&mut program, &targets, &globals, &options, diagnostics);
+            if diagnostics.has_errors() { return Ok(ExitCode::FAILURE) }
+            write_c(&output.out_dir, &targets, &program, &globals)?;
+
+            let out_dir = &output.out_dir;
+            std::fs::write(out_dir.join("runtime.h"), RUNTIME_HEADER)?;
+            std::fs::write(out_dir.join("runtime.c"), RUNTIME_SOURCE)?;
+
+            let status = std::process::Command::new(&cc)
+                .arg("-o")
+                .arg(&executable)
+                .arg(out_dir.join("output.c"))
+                .arg(out_dir.join("runtime.c"))
+                .args(&cflags)
+                .args(["-lraylib", "-lm"])
+                .status()?;
+            if !status.success() {
+                return Err(format!("`{cc}` exited with {status}").into());
+            }
+        }
+        Command::Info { input } => {
+            let files = project::load(&input.input)?;
+            print_info(&files.project);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// writes `output.h` and `output.c` in `out_dir`
+fn write_c(
+    out_dir: &Path,
+    targets: &[parser::Target],
+    program: &ir::Program,
+    globals: &parser::Globals,
+) -> Result<(), Box<dyn Error>> {
+    let mut header = Vec::new();
+    let mut source = Vec::new();
+    generator::generate(&mut header, &mut source, targets, program, globals)?;
+
+    std::fs::create_dir_all(out_dir)?;
+    std::fs::write(out_dir.join("output.h"), header)?;
+    std::fs::write(out_dir.join("output.c"), source)?;
     Ok(())
 }
+
+fn print_info(project: &scratch::Project) {
+    for target in &project.targets {
+        let kind = if target.isStage { "stage" } else { "sprite" };
+        let scripts = target.blocks.values().filter(|b| b.topLevel && b.parent.is_none()).count();
+
+        println!("{} ({kind})", target.name);
+        println!("    blocks:    {} in {scripts} scripts", target.blocks.len());
+        println!("    variables: {}", target.variables.len());
+        println!("    lists:     {}", target.lists.len());
+        println!("    costumes:  {}", target.costumes.len());
+        println!("    sounds:    {}", target.sounds.len());
+    }
+}
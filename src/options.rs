@@ -0,0 +1,8 @@
+/// Options affecting how a project is compiled
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Replace blocks that can't be compiled with a stub logging them at runtime, instead of failing
+    pub allow_unsupported: bool,
+    /// Report what the optimizations removed from the project
+    pub verbose: bool,
+}
@@ -1,4 +1,5 @@
-use crate::scratch;
+use crate::diagnostic::{Diagnostic, Diagnostics, Kind, Location};
+use crate::{ir, options::Options, project::Assets, scratch};
 use std::collections::{HashMap, HashSet};
 
 #[derive(Debug)]
@@ -7,24 +8,28 @@ pub struct Color(pub u8, pub u8, pub u8);
 #[derive(Debug)]
 pub struct Broadcast {
     pub name: String,
-    pub id: String,
 }
 
 #[derive(Debug)]
 pub struct Variable {
     pub name: String,
     pub id: String,
+    /// the input or field of the block it is read from
+    pub input: String,
 }
 
 #[derive(Debug)]
 pub struct List {
     pub name: String,
     pub id: String,
+    /// the input or field of the block it is read from
+    pub input: String,
 }
 
 #[derive(Debug)]
 pub enum Value {
-    Block(Box<Block>),
+    /// a reporter, plugged in the input `input`
+    Block { block: Box<LocatedBlock>, input: String },
     Number(f64),
     Color(Color),
     String(String),
@@ -65,14 +70,24 @@ pub enum Looks {
 #[derive(Debug)]
 pub enum Event {
     WhenFlagClicked,
+    WhenBroadcastReceived { broadcast: Broadcast },
+    Broadcast { message: Value },
+    BroadcastAndWait { message: Value },
 }
 
 #[derive(Debug)]
 pub enum Control {
     Wait { duration: Value },
+    WaitUntil { condition: Value },
     Repeat { times: Value, branch: Sequence },
+    RepeatUntil { condition: Value, branch: Sequence },
+    Forever { branch: Sequence },
     IfCondition { condition: Value, branch: Sequence },
-    CreateCloneOf { actor: String },
+    IfElse { condition: Value, if_branch: Sequence, else_branch: Sequence },
+    Stop { option: StopOption },
+    CreateCloneOf { actor: CloneOption },
+    StartAsClone,
+    DeleteThisClone,
 }
 
 #[derive(Debug)]
@@ -92,6 +107,31 @@ pub enum Operator {
 #[derive(Debug)]
 pub enum Data {
     SetVariableTo { value: Value, var: Variable },
+    AddToList { item: Value, list: List },
+    DeleteAllOfList { list: List },
+    DeleteOfList { index: Value, list: List },
+    InsertAtList { item: Value, index: Value, list: List },
+    ReplaceItemOfList { index: Value, item: Value, list: List },
+
+    // Value producing blocks
+    ItemOfList { index: Value, list: List },
+    ItemNumOfList { item: Value, list: List },
+    LengthOfList { list: List },
+    ListContainsItem { list: List, item: Value },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentKind {
+    StringNumber,
+    Boolean,
+}
+
+#[derive(Debug)]
+pub enum Procedures {
+    /// `arguments` maps argument IDs to their values, arguments left empty are not included
+    Call { proccode: String, arguments: Vec<(String, Value)> },
+    /// Arguments are referred to by name, and evaluate to 0 or false outside of the procedure defining them
+    Argument { name: String, kind: ArgumentKind },
 }
 
 #[derive(Debug)]
@@ -101,7 +141,10 @@ pub enum Block {
     Event(Event),
     Control(Control),
     Operator(Operator),
-    Data(Data)
+    Data(Data),
+    Procedures(Procedures),
+    /// A block the compiler can't handle, only parsed when unsupported blocks are allowed
+    Unsupported { opcode: String, id: String },
 }
 
 impl From<Motion> for Block { fn from(value: Motion) -> Self { Block::Motion(value) } }
@@ -110,19 +153,85 @@ impl From<Event> for Block { fn from(value: Event) -> Self { Block::Event(value)
 impl From<Control> for Block { fn from(value: Control) -> Self { Block::Control(value) } }
 impl From<Operator> for Block { fn from(value: Operator) -> Self { Block::Operator(value) } }
 impl From<Data> for Block { fn from(value: Data) -> Self { Block::Data(value) } }
+impl From<Procedures> for Block { fn from(value: Procedures) -> Self { Block::Procedures(value) } }
 
+/// A block, with where it comes from in the project to point diagnostics to it
 #[derive(Debug)]
-pub struct Sequence(pub Vec<Block>);
+pub struct LocatedBlock {
+    pub id: String,
+    pub opcode: String,
+    pub block: Block,
+}
 
 #[derive(Debug)]
+pub struct Sequence(pub Vec<LocatedBlock>);
+
+impl Sequence {
+    /// the hat starting the script, if it has one
+    pub fn hat(&self) -> Option<&Block> {
+        self.0.first().map(|b| &b.block)
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.0.iter().map(|b| &b.block)
+    }
+}
+
+#[derive(Debug)]
+pub struct Argument {
+    pub name: String,
+    pub id: String,
+}
+
+/// A custom block defined in a target
+#[derive(Debug)]
+pub struct Procedure {
+    /// identifies the custom block, like `jump %s times %b`
+    pub proccode: String,
+    pub arguments: Vec<Argument>,
+    /// wether the procedure runs without screen refresh
+    pub warp: bool,
+    pub body: Sequence,
+}
+
 pub struct Costume {
     pub name: String,
     pub filename: String,
+    /// The content of the asset file
+    pub data: Vec<u8>,
     pub bitmap_resolution: i32,
     pub rotation_center_x: i32,
     pub rotation_center_y: i32,
 }
 
+impl std::fmt::Debug for Costume {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // don't print the content of the asset
+        f.debug_struct("Costume")
+            .field("name", &self.name)
+            .field("filename", &self.filename)
+            .field("data", &format_args!("<{} bytes>", self.data.len()))
+            .field("bitmap_resolution", &self.bitmap_resolution)
+            .field("rotation_center_x", &self.rotation_center_x)
+            .field("rotation_center_y", &self.rotation_center_y)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum StopOption {
+    All,
+    ThisScript,
+    /// stops the other scripts of the actor running the block
+    OtherScripts
+}
+
+#[derive(Debug)]
+pub enum CloneOption {
+    Myself,
+    Actor(String)
+}
+
 #[derive(Debug)]
 pub enum GotoDestOption {
     Random,
@@ -132,11 +241,12 @@ pub enum GotoDestOption {
 
 #[derive(Debug)]
 pub enum PointTowardsOption {
+    Random,
     MouseCursor,
     Actor(String)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RotationStyle {
     AllAround,
     LeftRight,
@@ -152,7 +262,6 @@ pub enum TargetKind {
         y: f32,
         size: f32,
         direction: f32,
-        draggable: bool,
         rotation_style: RotationStyle
     }
 }
@@ -161,43 +270,137 @@ pub enum TargetKind {
 pub struct Target {
     pub name: String,
     pub sequences: Vec<Sequence>,
-    /// Map from variable ID to C variable name
-    pub vars: HashMap<String, String>,
+    /// Custom blocks, which can only be called from this target
+    pub procedures: Vec<Procedure>,
+    /// Variables local to this target
+    pub vars: VarMap,
+    /// Lists local to this target
+    pub lists: ListMap,
     pub current_costume: usize,
     pub costumes: Vec<Costume>,
-    pub sounds: Vec<scratch::Sound>,
     pub kind: TargetKind
 }
 
-fn parse_clone_option(blocks: &HashMap<String, scratch::Block>, v: &[serde_json::Value]) -> String {
-    let id = v[1].as_str().expect("expected clone input option to point to a block");
-    let block = &blocks[id];
-    assert!(block.opcode == "control_create_clone_of_menu");
+type Blocks = HashMap<String, scratch::Block>;
+type Field = (serde_json::Value, Option<serde_json::Value>);
+type ParseResult<T> = Result<T, Box<Diagnostic>>;
 
-    block.fields["CLONE_OPTION"].0.as_str().unwrap().to_owned()
+/// State shared by every parsing function of a target
+struct Context<'a> {
+    blocks: &'a Blocks,
+    sprite: &'a str,
+    options: &'a Options,
+    diagnostics: &'a mut Diagnostics,
 }
 
-fn parse_goto_option(blocks: &HashMap<String, scratch::Block>, v: &[serde_json::Value]) -> GotoDestOption {
-    let id = v[1].as_str().expect("expected goto input option to point to a block");
-    let block = &blocks[id];
-    assert!(block.opcode == "motion_goto_menu" || block.opcode == "motion_glideto_menu");
+impl<'a> Context<'a> {
+    fn block(&self, id: &str) -> ParseResult<&'a scratch::Block> {
+        self.blocks.get(id).ok_or_else(|| {
+            Box::new(Diagnostic::error(format!("reference to block `{id}`, which does not exist")).with_sprite(self.sprite))
+        })
+    }
 
-    match block.fields["TO"].0.as_str().unwrap() {
+    /// creates an error about the block `id`, and optionally one of its inputs or fields
+    fn error(&self, id: &str, input: Option<&str>, message: impl Into<String>) -> Box<Diagnostic> {
+        self.locate(Diagnostic::error(message), id, input)
+    }
+
+    /// points a diagnostic to the block `id`, and optionally one of its inputs or fields
+    fn locate(&self, mut diagnostic: Diagnostic, id: &str, input: Option<&str>) -> Box<Diagnostic> {
+        diagnostic.location = Location {
+            sprite: Some(self.sprite.to_owned()),
+            block_id: Some(id.to_owned()),
+            opcode: self.blocks.get(id).map(|b| b.opcode.clone()),
+            input: input.map(str::to_owned),
+        };
+        diagnostic.context = self.script_context(id);
+        Box::new(diagnostic)
+    }
+
+    /// lists the blocks containing the block `id`, from the top of its script
+    fn script_context(&self, id: &str) -> Vec<(usize, String)> {
+        let Some(mut block) = self.blocks.get(id) else { return vec![] };
+        let mut current = id;
+        let mut chain = vec![block.opcode.clone()];
+
+        while let Some(parent_id) = &block.parent {
+            let Some(parent) = self.blocks.get(parent_id) else { break };
+            // blocks coming before in the same stack are not interesting, only the ones containing it and the top one
+            if parent.next.as_deref() != Some(current) || parent.parent.is_none() {
+                chain.push(parent.opcode.clone());
+            }
+            current = parent_id;
+            block = parent;
+        }
+
+        chain.into_iter().rev().enumerate().collect()
+    }
+
+    fn input(&self, id: &str, name: &str) -> ParseResult<&'a [serde_json::Value]> {
+        let block = self.block(id)?;
+        match block.inputs.get(name) {
+            Some(v) if v.len() >= 2 => Ok(v),
+            Some(_) => Err(self.error(id, Some(name), "malformed input")),
+            None => Err(self.error(id, Some(name), format!("missing input `{name}`"))),
+        }
+    }
+
+    fn field(&self, id: &str, name: &str) -> ParseResult<&'a Field> {
+        let block = self.block(id)?;
+        block.fields.get(name).ok_or_else(|| self.error(id, Some(name), format!("missing field `{name}`")))
+    }
+
+    fn field_str(&self, id: &str, name: &str) -> ParseResult<&'a str> {
+        self.field(id, name)?.0.as_str().ok_or_else(|| self.error(id, Some(name), "expected field to be a string"))
+    }
+
+    /// returns the field `field` of the menu block pointed to by the input `input`
+    fn menu(&self, id: &str, input: &str, menu_opcodes: &[&str], field: &str) -> ParseResult<&'a str> {
+        let v = self.input(id, input)?;
+        let menu_id = v[1].as_str().ok_or_else(|| self.error(id, Some(input), "expected input to point to a menu"))?;
+        let menu = self.block(menu_id)?;
+        if !menu_opcodes.contains(&menu.opcode.as_str()) {
+            return Err(self.error(
+                id,
+                Some(input),
+                format!("expected input to be one of {menu_opcodes:?}, found `{}`", menu.opcode),
+            ));
+        }
+
+        self.field_str(menu_id, field)
+    }
+}
+
+fn parse_stop_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<StopOption> {
+    match ctx.field_str(id, name)? {
+        "all" => Ok(StopOption::All),
+        "this script" => Ok(StopOption::ThisScript),
+        "other scripts in sprite" | "other scripts in stage" => Ok(StopOption::OtherScripts),
+        s => Err(ctx.error(id, Some(name), format!("unknown stop option `{s}`"))),
+    }
+}
+
+fn parse_clone_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<CloneOption> {
+    Ok(match ctx.menu(id, name, &["control_create_clone_of_menu"], "CLONE_OPTION")? {
+        "_myself_" => CloneOption::Myself,
+        s => CloneOption::Actor(s.to_owned())
+    })
+}
+
+fn parse_goto_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<GotoDestOption> {
+    Ok(match ctx.menu(id, name, &["motion_goto_menu", "motion_glideto_menu"], "TO")? {
         "_random_" => GotoDestOption::Random,
         "_mouse_" => GotoDestOption::MouseCursor,
         s => GotoDestOption::Actor(s.to_owned())
-    }
+    })
 }
 
-fn parse_towards_option(blocks: &HashMap<String, scratch::Block>, v: &[serde_json::Value]) -> PointTowardsOption {
-    let id = v[1].as_str().expect("expected towards input option to point to a block");
-    let block = &blocks[id];
-    assert!(block.opcode == "motion_pointtowards_menu");
-
-    match block.fields["TOWARDS"].0.as_str().unwrap() {
+fn parse_towards_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<PointTowardsOption> {
+    Ok(match ctx.menu(id, name, &["motion_pointtowards_menu"], "TOWARDS")? {
+        "_random_" => PointTowardsOption::Random,
         "_mouse_" => PointTowardsOption::MouseCursor,
         s => PointTowardsOption::Actor(s.to_owned())
-    }
+    })
 }
 
 impl std::str::FromStr for RotationStyle {
@@ -208,76 +411,171 @@ impl std::str::FromStr for RotationStyle {
             "all around" => Ok(RotationStyle::AllAround),
             "left-right" => Ok(RotationStyle::LeftRight),
             "don't rotate" => Ok(RotationStyle::DontRotate),
-            _ => return Err(())
+            _ => Err(())
         }
     }
 }
 
-fn parse_rotation_style_option(_: &HashMap<String, scratch::Block>, v: &(serde_json::Value, Option<serde_json::Value>)) -> RotationStyle {
-    v.0.as_str().unwrap().parse().expect("rotation style field attribute to be well formed")
+fn parse_rotation_style_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<RotationStyle> {
+    let style = ctx.field_str(id, name)?;
+    style.parse().map_err(|_| ctx.error(id, Some(name), format!("invalid rotation style `{style}`")))
+}
+
+fn parse_variable_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<Variable> {
+    let field = ctx.field(id, name)?;
+    match (field.0.as_str(), field.1.as_ref().and_then(|id| id.as_str())) {
+        (Some(var), Some(var_id)) => Ok(Variable { name: var.to_owned(), id: var_id.to_owned(), input: name.to_owned() }),
+        _ => Err(ctx.error(id, Some(name), "expected a variable name and ID")),
+    }
+}
+
+fn parse_broadcast_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<Broadcast> {
+    let field = ctx.field(id, name)?;
+    match (field.0.as_str(), field.1.as_ref().and_then(|id| id.as_str())) {
+        (Some(name), Some(_)) => Ok(Broadcast { name: name.to_owned() }),
+        _ => Err(ctx.error(id, Some(name), "expected a broadcast name and ID")),
+    }
 }
 
-fn parse_variable_option(_: &HashMap<String, scratch::Block>, v: &(serde_json::Value, Option<serde_json::Value>)) -> Variable {
-    Variable {
-        name: v.0.as_str().unwrap().to_owned(),
-        id: v.1.as_ref().unwrap().as_str().unwrap().to_owned(),
+fn parse_list_option(ctx: &mut Context, id: &str, name: &str) -> ParseResult<List> {
+    let field = ctx.field(id, name)?;
+    match (field.0.as_str(), field.1.as_ref().and_then(|id| id.as_str())) {
+        (Some(list), Some(list_id)) => Ok(List { name: list.to_owned(), id: list_id.to_owned(), input: name.to_owned() }),
+        _ => Err(ctx.error(id, Some(name), "expected a list name and ID")),
     }
 }
 
 /// Parses an RGB color code formatted like `#RRGGBB` into the `Color` struct.
-fn parse_color(hexadecimal_code: &str) -> Color {
-    let r = u8::from_str_radix(&hexadecimal_code[1..3], 16).unwrap();
-    let g = u8::from_str_radix(&hexadecimal_code[3..5], 16).unwrap();
-    let b = u8::from_str_radix(&hexadecimal_code[5..7], 16).unwrap();
-    Color(r, g, b)
+fn parse_color(hexadecimal_code: &str) -> Option<Color> {
+    let component = |range| hexadecimal_code.get(range).and_then(|c| u8::from_str_radix(c, 16).ok());
+    if hexadecimal_code.len() != 7 || !hexadecimal_code.starts_with('#') {
+        return None;
+    }
+    Some(Color(component(1..3)?, component(3..5)?, component(5..7)?))
 }
 
-fn parse_value(blocks: &HashMap<String, scratch::Block>, v: &[serde_json::Value]) -> Value {
-    // The first element of `v` describes wether the input is shadowed or not
+fn parse_value(ctx: &mut Context, id: &str, name: &str) -> ParseResult<Value> {
+    // The first element of the input describes wether the input is shadowed or not
     // But, whether is is the case, the actual value will always be the second element in the array
     // So we don't care about the shadowed state
+    let input = ctx.input(id, name)?;
+    let malformed = |ctx: &Context, what: &str| ctx.error(id, Some(name), format!("malformed input: {what}"));
 
-    if v[1].is_string() {
-        let id = v[1].as_str().unwrap();
-        Value::Block(Box::new(parse_block(blocks, &blocks[id])))
-    } else if v[1].is_array() {
-        let v = v[1].as_array().unwrap();
-
+    if let Some(block_id) = input[1].as_str() {
+        let block = Box::new(parse_located_block(ctx, block_id)?);
+        Ok(Value::Block { block, input: name.to_owned() })
+    } else if let Some(v) = input[1].as_array() {
         // The first value tells us what type of element it is
-        let kind = v[0].as_u64().unwrap();
+        let kind = v.first().and_then(|k| k.as_u64()).ok_or_else(|| malformed(ctx, "missing value type"))?;
+        let text = v.get(1).and_then(|t| t.as_str()).ok_or_else(|| malformed(ctx, "missing value"))?;
         match kind {
-            4..=8 => Value::Number(v[1].as_str().unwrap().parse().unwrap()),
-            9 => Value::Color(parse_color(v[1].as_str().unwrap())),
-            10 => {
-                let v = v[1].as_str().unwrap();
-                match v.parse() {
-                    Ok(n) => Value::Number(n),
-                    Err(_) => Value::String(v.to_owned()),
-                }
-            }
-            11 | 12 | 13 => {
-                let name = v[1].as_str().unwrap().to_owned();
-                let id = v[2].as_str().unwrap().to_owned();
-                match kind {
-                    11 => Value::Broadcast(Broadcast { name, id }),
-                    12 => Value::Variable(Variable { name, id }),
-                    13 => Value::List(List { name, id }),
-                    _ => unreachable!(),
-                }
+            // number inputs can still contain arbitrary text, which stays a string unless it is written like scratch writes
+            // the number, as `1.0` or `inf` don't behave like the number they convert to
+            4..=8 | 10 => Ok(match ir::canonical_number(text) {
+                Some(n) => Value::Number(n),
+                None => Value::String(text.to_owned()),
+            }),
+            9 => parse_color(text)
+                .map(Value::Color)
+                .ok_or_else(|| ctx.error(id, Some(name), format!("invalid color `{text}`"))),
+            11..=13 => {
+                let (input, name) = (name.to_owned(), text.to_owned());
+                let id = v.get(2).and_then(|i| i.as_str()).ok_or_else(|| malformed(ctx, "missing ID"))?.to_owned();
+                Ok(match kind {
+                    11 => Value::Broadcast(Broadcast { name }),
+                    12 => Value::Variable(Variable { name, id, input }),
+                    _ => Value::List(List { name, id, input }),
+                })
             }
-            _ => unreachable!("invalid value: {kind}"),
+            _ => Err(ctx.error(id, Some(name), format!("invalid value type `{kind}`"))),
         }
     } else {
-        unreachable!("invalid block")
+        Err(malformed(ctx, "expected a block or a value"))
     }
 }
 
-fn parse_block(blocks: &HashMap<String, scratch::Block>, block: &scratch::Block) -> Block {
+/// returns the mutation of a custom block, with its proccode and argument IDs
+fn parse_mutation<'a>(ctx: &Context<'a>, id: &str) -> ParseResult<(&'a scratch::Mutation, &'a str, Vec<String>)> {
+    let mutation = ctx.block(id)?.mutation.as_ref().ok_or_else(|| ctx.error(id, None, "missing mutation"))?;
+    let proccode = mutation.proccode.as_deref().ok_or_else(|| ctx.error(id, None, "missing `proccode` in mutation"))?;
+    let argument_ids = match &mutation.argumentids {
+        Some(ids) => serde_json::from_str(ids)
+            .map_err(|e| ctx.error(id, None, format!("invalid `argumentids` in mutation: {e}")))?,
+        None => vec![],
+    };
+    Ok((mutation, proccode, argument_ids))
+}
+
+fn parse_call(ctx: &mut Context, id: &str) -> ParseResult<Procedures> {
+    let (_, proccode, argument_ids) = parse_mutation(ctx, id)?;
+    let mut arguments = vec![];
+    for argument_id in argument_ids {
+        if ctx.block(id)?.inputs.contains_key(&argument_id) {
+            let value = parse_value(ctx, id, &argument_id)?;
+            arguments.push((argument_id, value));
+        }
+    }
+    Ok(Procedures::Call { proccode: proccode.to_owned(), arguments })
+}
+
+/// parses a `procedures_definition` hat, and the body of the procedure
+fn parse_procedure(ctx: &mut Context, id: &str) -> ParseResult<Procedure> {
+    let prototype = ctx.input(id, "custom_block")?[1]
+        .as_str()
+        .ok_or_else(|| ctx.error(id, Some("custom_block"), "expected input to point to a prototype"))?;
+    let (mutation, proccode, argument_ids) = parse_mutation(ctx, prototype)?;
+    let names: Vec<String> = match &mutation.argumentnames {
+        Some(names) => serde_json::from_str(names)
+            .map_err(|e| ctx.error(prototype, None, format!("invalid `argumentnames` in mutation: {e}")))?,
+        None => vec![],
+    };
+    if names.len() != argument_ids.len() {
+        return Err(ctx.error(prototype, None, "custom block has a different number of argument names and IDs"));
+    }
+    let warp = match &mutation.warp {
+        Some(serde_json::Value::Bool(b)) => *b,
+        Some(serde_json::Value::String(s)) => s == "true",
+        _ => false,
+    };
+
+    let mut arguments = vec![];
+    for (name, argument_id) in names.into_iter().zip(argument_ids) {
+        // every argument is shown by a reporter in the prototype
+        let reporter = ctx.input(prototype, &argument_id)?[1]
+            .as_str()
+            .ok_or_else(|| ctx.error(prototype, Some(&argument_id), "expected input to point to an argument reporter"))?;
+        match ctx.block(reporter)?.opcode.as_str() {
+            "argument_reporter_string_number" | "argument_reporter_boolean" => (),
+            opcode => return Err(ctx.error(reporter, None, format!("expected an argument reporter, found `{opcode}`"))),
+        }
+        arguments.push(Argument { name, id: argument_id });
+    }
+
+    let body = match ctx.block(id)?.next.as_deref() {
+        Some(next) => parse_sequence(ctx, next),
+        None => Sequence(vec![]),
+    };
+    Ok(Procedure { proccode: proccode.to_owned(), arguments, warp, body })
+}
+
+/// parses a boolean input, which is left out of the project when the slot is empty
+fn parse_condition(ctx: &mut Context, id: &str, name: &str) -> ParseResult<Value> {
+    if ctx.block(id)?.inputs.contains_key(name) {
+        parse_value(ctx, id, name)
+    } else {
+        // an empty string is false
+        Ok(Value::String(String::new()))
+    }
+}
+
+fn parse_block(ctx: &mut Context, id: &str) -> ParseResult<Block> {
+    let block = ctx.block(id)?;
+
     macro_rules! binop {
         ($outblock:ident, $inputname:literal) => {
             {
-                let lhs = parse_value(blocks, &block.inputs[concat!($inputname, "1")]);
-                let rhs = parse_value(blocks, &block.inputs[concat!($inputname, "2")]);
+                let lhs = parse_value(ctx, id, concat!($inputname, "1"))?;
+                let rhs = parse_value(ctx, id, concat!($inputname, "2"))?;
                 Operator::$outblock { lhs, rhs }.into()
             }
         };
@@ -285,11 +583,11 @@ fn parse_block(blocks: &HashMap<String, scratch::Block>, block: &scratch::Block)
 
     // pretty cursed macro to automate `parse_value` and `parse_*` calls for every input and field of a block. works pretty well though.
     // here is an example:
-    //   normal_block!(Control::Repeat; times => "TIMES"; branch => parse_sequence_from_id_or_empty(inputs "SUBSTACK")),
+    //   normal_block!(Control::Repeat; times => "TIMES"; branch => parse_sequence_from_id_or_empty("SUBSTACK")),
     // was:
     // {
-    //     let times = parse_value(blocks, &block.inputs["TIMES"]);
-    //     let branch = parse_sequence_from_id_or_empty(blocks, &block.inputs["SUBSTACK"]);
+    //     let times = parse_value(ctx, id, "TIMES")?;
+    //     let branch = parse_sequence_from_id_or_empty(ctx, id, "SUBSTACK")?;
     //     Control::Repeat { times, branch }.into()
     // }
 
@@ -297,63 +595,96 @@ fn parse_block(blocks: &HashMap<String, scratch::Block>, block: &scratch::Block)
     // second is for no inputs and fields
     // third is for macros with no fields (avoids ending semicolon)
     macro_rules! normal_block {
-        ($kind:path; $($value:ident => $valuename:literal),+; $($option:ident => $func:ident($valueoroption:ident $optionname:literal)),*) => {
+        ($kind:path; $($value:ident => $valuename:literal),+; $($option:ident => $func:ident($optionname:literal)),*) => {
             {
                 $kind {
-                    $($value: parse_value(blocks, &block.inputs[$valuename])),+,
-                    $($option: $func(blocks, &block.$valueoroption[$optionname])),*
+                    $($value: parse_value(ctx, id, $valuename)?),+,
+                    $($option: $func(ctx, id, $optionname)?),*
                 }.into()
             }
         };
-        ($kind:path; ; $($option:ident => $func:ident($valueoroption:ident $optionname:literal)),+) => {
+        ($kind:path; ; $($option:ident => $func:ident($optionname:literal)),+) => {
             {
                 $kind {
-                    $($option: $func(blocks, &block.$valueoroption[$optionname])),+
+                    $($option: $func(ctx, id, $optionname)?),+
                 }.into()
             }
         };
         ($kind:path; $($value:ident => $valuename:literal),+) => {
             {
                 $kind {
-                    $($value: parse_value(blocks, &block.inputs[$valuename])),+
+                    $($value: parse_value(ctx, id, $valuename)?),+
                 }.into()
             }
         };
     }
 
-    match block.opcode.as_str() {
+    Ok(match block.opcode.as_str() {
         "event_whenflagclicked" => Event::WhenFlagClicked.into(),
+        "event_whenbroadcastreceived" => normal_block!(Event::WhenBroadcastReceived;; broadcast => parse_broadcast_option("BROADCAST_OPTION")),
+        "event_broadcast" => normal_block!(Event::Broadcast; message => "BROADCAST_INPUT"),
+        "event_broadcastandwait" => normal_block!(Event::BroadcastAndWait; message => "BROADCAST_INPUT"),
         "motion_movesteps" => normal_block!(Motion::MoveSteps; steps => "STEPS"),
         "motion_turnright" => normal_block!(Motion::TurnRight; degrees => "DEGREES"),
         "motion_turnleft" => normal_block!(Motion::TurnLeft; degrees => "DEGREES"),
-        "motion_goto" => normal_block!(Motion::Goto;; dest => parse_goto_option(inputs "TO")),
+        "motion_goto" => normal_block!(Motion::Goto;; dest => parse_goto_option("TO")),
         "motion_gotoxy" => normal_block!(Motion::GotoXY; x => "X", y => "Y"),
         // glide has the same parameters as goto
-        "motion_glideto" => normal_block!(Motion::Glide; secs => "SECS"; dest => parse_goto_option(inputs "TO")),
+        "motion_glideto" => normal_block!(Motion::Glide; secs => "SECS"; dest => parse_goto_option("TO")),
         "motion_glidesecstoxy" => normal_block!(Motion::GlideXY; secs => "SECS", x => "X", y => "Y"),
         "motion_pointindirection" => normal_block!(Motion::PointInDrection; degrees => "DIRECTION"),
-        "motion_pointtowards" => normal_block!(Motion::PointTowards;; towards => parse_towards_option(inputs "TOWARDS")),
+        "motion_pointtowards" => normal_block!(Motion::PointTowards;; towards => parse_towards_option("TOWARDS")),
         "motion_changexby" => normal_block!(Motion::ChangeX; by => "DX"),
         "motion_setx" => normal_block!(Motion::SetX; to => "X"),
-        "motion_changeyby" => normal_block!(Motion::ChangeX; by => "DY"),
-        "motion_sety" => normal_block!(Motion::SetX; to => "Y"),
+        "motion_changeyby" => normal_block!(Motion::ChangeY; by => "DY"),
+        "motion_sety" => normal_block!(Motion::SetY; to => "Y"),
         "motion_ifonedgebounce" => Motion::IfOnEdgeBounce.into(),
-        "motion_setrotationstyle" => normal_block!(Motion::SetRotationStyle;; style => parse_rotation_style_option(fields "STYLE")),
+        "motion_setrotationstyle" => normal_block!(Motion::SetRotationStyle;; style => parse_rotation_style_option("STYLE")),
         "motion_xposition" => Motion::XPosition.into(),
-        "motion_yposition" => Motion::XPosition.into(),
+        "motion_yposition" => Motion::YPosition.into(),
         "motion_direction" => Motion::Direction.into(),
         "looks_sayforsecs" => normal_block!(Looks::SayForSecs; message => "MESSAGE", secs => "SECS"),
         "control_wait" => normal_block!(Control::Wait; duration => "DURATION"),
+        "control_wait_until" => normal_block!(Control::WaitUntil;; condition => parse_condition("CONDITION")),
         "control_repeat" => normal_block! { Control::Repeat;
             times => "TIMES";
-            branch => parse_sequence_from_id_or_empty(inputs "SUBSTACK")
+            branch => parse_sequence_from_id_or_empty("SUBSTACK")
+        },
+        "control_repeat_until" => normal_block! { Control::RepeatUntil;;
+            condition => parse_condition("CONDITION"),
+            branch => parse_sequence_from_id_or_empty("SUBSTACK")
         },
-        "control_if" => normal_block! { Control::IfCondition;
-            condition => "CONDITION";
-            branch => parse_sequence_from_id_or_empty(inputs "SUBSTACK")
+        "control_forever" => normal_block!(Control::Forever;; branch => parse_sequence_from_id_or_empty("SUBSTACK")),
+        "control_if" => normal_block! { Control::IfCondition;;
+            condition => parse_condition("CONDITION"),
+            branch => parse_sequence_from_id_or_empty("SUBSTACK")
         },
-        "control_create_clone_of" => normal_block!(Control::CreateCloneOf;; actor => parse_clone_option(inputs "CLONE_OPTION")),
-        "data_setvariableto" => normal_block!(Data::SetVariableTo; value => "VALUE"; var => parse_variable_option(fields "VARIABLE")),
+        "control_if_else" => normal_block! { Control::IfElse;;
+            condition => parse_condition("CONDITION"),
+            if_branch => parse_sequence_from_id_or_empty("SUBSTACK"),
+            else_branch => parse_sequence_from_id_or_empty("SUBSTACK2")
+        },
+        "control_stop" => normal_block!(Control::Stop;; option => parse_stop_option("STOP_OPTION")),
+        "procedures_call" => parse_call(ctx, id)?.into(),
+        "argument_reporter_string_number" => {
+            Procedures::Argument { name: ctx.field_str(id, "VALUE")?.to_owned(), kind: ArgumentKind::StringNumber }.into()
+        }
+        "argument_reporter_boolean" => {
+            Procedures::Argument { name: ctx.field_str(id, "VALUE")?.to_owned(), kind: ArgumentKind::Boolean }.into()
+        }
+        "control_create_clone_of" => normal_block!(Control::CreateCloneOf;; actor => parse_clone_option("CLONE_OPTION")),
+        "control_start_as_clone" => Control::StartAsClone.into(),
+        "control_delete_this_clone" => Control::DeleteThisClone.into(),
+        "data_setvariableto" => normal_block!(Data::SetVariableTo; value => "VALUE"; var => parse_variable_option("VARIABLE")),
+        "data_addtolist" => normal_block!(Data::AddToList; item => "ITEM"; list => parse_list_option("LIST")),
+        "data_deletealloflist" => normal_block!(Data::DeleteAllOfList;; list => parse_list_option("LIST")),
+        "data_deleteoflist" => normal_block!(Data::DeleteOfList; index => "INDEX"; list => parse_list_option("LIST")),
+        "data_insertatlist" => normal_block!(Data::InsertAtList; item => "ITEM", index => "INDEX"; list => parse_list_option("LIST")),
+        "data_replaceitemoflist" => normal_block!(Data::ReplaceItemOfList; index => "INDEX", item => "ITEM"; list => parse_list_option("LIST")),
+        "data_itemoflist" => normal_block!(Data::ItemOfList; index => "INDEX"; list => parse_list_option("LIST")),
+        "data_itemnumoflist" => normal_block!(Data::ItemNumOfList; item => "ITEM"; list => parse_list_option("LIST")),
+        "data_lengthoflist" => normal_block!(Data::LengthOfList;; list => parse_list_option("LIST")),
+        "data_listcontainsitem" => normal_block!(Data::ListContainsItem; item => "ITEM"; list => parse_list_option("LIST")),
         "operator_add" => binop!(Add, "NUM"),
         "operator_subtract" => binop!(Sub, "NUM"),
         "operator_multiply" => binop!(Mul, "NUM"),
@@ -364,65 +695,155 @@ fn parse_block(blocks: &HashMap<String, scratch::Block>, block: &scratch::Block)
         "operator_and" => binop!(And, "OPERAND"),
         "operator_or" => binop!(Or, "OPERAND"),
         "operator_not" => normal_block!(Operator::Not; operand => "OPERAND"),
-        opcode => todo!("unimplemented block: {opcode}"),
-    }
+        opcode if ctx.options.allow_unsupported => {
+            let warning = Diagnostic::warning(format!("unsupported block `{opcode}` replaced with a stub"));
+            ctx.diagnostics.push(*ctx.locate(Diagnostic { kind: Kind::Unsupported, ..warning }, id, None));
+            Block::Unsupported { opcode: opcode.to_owned(), id: id.to_owned() }
+        }
+        opcode => return Err(ctx.locate(Diagnostic::unsupported(opcode), id, None)),
+    })
 }
 
-/// parse a sequence if the given value has an ID, otherwise returns an empty sequence
-fn parse_sequence_from_id_or_empty(blocks: &HashMap<String, scratch::Block>, input: &[serde_json::Value]) -> Sequence {
-    if let Some(id) = input[1].as_str() {
-        parse_sequence(blocks, &blocks[id])
-    } else {
-        Sequence(vec![])
+fn parse_located_block(ctx: &mut Context, id: &str) -> ParseResult<LocatedBlock> {
+    let block = parse_block(ctx, id)?;
+    Ok(LocatedBlock { id: id.to_owned(), opcode: ctx.block(id)?.opcode.clone(), block })
+}
+
+/// parse a sequence if the given input has an ID, otherwise returns an empty sequence
+/// scratch omits the input entirely when the branch is empty
+fn parse_sequence_from_id_or_empty(ctx: &mut Context, id: &str, name: &str) -> ParseResult<Sequence> {
+    let block = ctx.block(id)?;
+    match block.inputs.get(name).and_then(|input| input.get(1)).and_then(|v| v.as_str()) {
+        Some(start) => Ok(parse_sequence(ctx, start)),
+        None => Ok(Sequence(vec![])),
     }
 }
 
-fn parse_sequence(blocks: &HashMap<String, scratch::Block>, start: &scratch::Block) -> Sequence {
+/// parses every block of a sequence
+/// blocks that fail to parse are skipped, and their error is recorded so that every error can be reported at once
+fn parse_sequence(ctx: &mut Context, start: &str) -> Sequence {
     let mut sequence = vec![];
-    sequence.push(parse_block(blocks, start));
 
-    let mut block = start;
-    while let Some(next_id) = &block.next {
-        block = &blocks[next_id];
+    let mut next = Some(start);
+    while let Some(id) = next {
+        match parse_located_block(ctx, id) {
+            Ok(block) => sequence.push(block),
+            Err(diagnostic) => ctx.diagnostics.push(*diagnostic),
+        }
 
-        sequence.push(parse_block(blocks, block));
+        next = ctx.blocks.get(id).and_then(|b| b.next.as_deref());
     }
 
     Sequence(sequence)
 }
 
-pub type VarMap = HashMap<String, String>;
+/// A value saved in a project, like the items of a list
+#[derive(Debug, Clone)]
+pub enum Literal {
+    Number(f64),
+    String(String),
+    Bool(bool),
+}
 
-/// transforms scratch variable names into valid C identifiers
+impl From<&serde_json::Value> for Literal {
+    fn from(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Number(n) => Literal::Number(n.as_f64().unwrap_or(0.0)),
+            serde_json::Value::String(s) => Literal::String(s.clone()),
+            serde_json::Value::Bool(b) => Literal::Bool(*b),
+            v => Literal::String(v.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VarDecl {
+    /// C identifier of the variable
+    pub cident: String,
+    /// Value of the variable when the project was saved
+    pub value: Literal,
+}
+
+/// Map from variable ID to its declaration
+pub type VarMap = HashMap<String, VarDecl>;
+
+#[derive(Debug)]
+pub struct ListDecl {
+    /// C identifier of the list
+    pub cident: String,
+    /// Items of the list when the project was saved
+    pub items: Vec<Literal>,
+}
+
+/// Map from list ID to its declaration
+pub type ListMap = HashMap<String, ListDecl>;
+
+/// Variables and lists shared by every target, which are the ones declared on the stage
+#[derive(Debug, Default)]
+pub struct Globals {
+    pub vars: VarMap,
+    pub lists: ListMap,
+}
+
+/// C identifiers already used by global variables and lists
+#[derive(Default)]
+struct GlobalNames {
+    vars: HashSet<String>,
+    lists: HashSet<String>,
+}
+
+/// transforms a scratch name into a valid C identifier that is not in `taken`
 /// ascii alphanumeric characters are passed through,
 /// other characters are transformed into underscores,
 /// conflicts are resolved by adding underscores at the end of the word
+fn sanitize_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let mut cident = name.replace(|x: char| !x.is_ascii_alphanumeric(), "_");
+    while taken.contains(&cident) {
+        cident.push('_');
+    }
+    taken.insert(cident.clone());
+    cident
+}
+
+/// transforms scratch variable names into valid C identifiers
 ///
 /// only local variables are returned
-/// `globals` is another hash map created to gather global variables
+/// global variables are added to `globals`
 /// 
 /// variables on stage targets are always global
-fn sanitize_varnames(is_stage: bool, vars: HashMap<String, scratch::Variable>, globals: &mut VarMap, global_hashset: &mut HashSet<String>) -> VarMap {
+fn sanitize_varnames(is_stage: bool, vars: HashMap<String, scratch::Variable>, globals: &mut Globals, global_names: &mut GlobalNames) -> VarMap {
     let mut out = HashMap::new();
     let mut cidents = HashSet::new();
 
     for (id, var) in vars {
-        let mut cident = var.0.replace(|x: char| !x.is_ascii_alphanumeric(), "_");
-
+        let value = Literal::from(&var.1);
         if is_stage || var.2.is_some_and(|global| global) {
-            while global_hashset.contains(&cident) {
-                cident.push('_');
-            }
-            global_hashset.insert(cident.clone());
-            globals.insert(id, cident);
-            continue
+            let cident = sanitize_name(&var.0, &mut global_names.vars);
+            globals.vars.insert(id, VarDecl { cident, value });
+        } else {
+            let cident = sanitize_name(&var.0, &mut cidents);
+            out.insert(id, VarDecl { cident, value });
         }
+    }
+
+    out
+}
 
-        while cidents.contains(&cident) {
-            cident.push('_');
+/// same as [`sanitize_varnames`], for lists
+/// lists are global when they are declared on the stage
+fn sanitize_lists(is_stage: bool, lists: HashMap<String, scratch::List>, globals: &mut Globals, global_names: &mut GlobalNames) -> ListMap {
+    let mut out = HashMap::new();
+    let mut cidents = HashSet::new();
+
+    for (id, list) in lists {
+        let items = list.1.iter().map(Literal::from).collect();
+        if is_stage {
+            let cident = sanitize_name(&list.0, &mut global_names.lists);
+            globals.lists.insert(id, ListDecl { cident, items });
+        } else {
+            let cident = sanitize_name(&list.0, &mut cidents);
+            out.insert(id, ListDecl { cident, items });
         }
-        cidents.insert(cident.clone());
-        out.insert(id, cident);
     }
 
     out
@@ -444,65 +865,114 @@ fn is_block_real_toplevel(opcode: &str) -> bool {
     )
 }
 
-fn parse_target(mut target: scratch::Target, globals: &mut VarMap, global_hashset: &mut HashSet<String>) -> Target {
+fn parse_target(
+    mut target: scratch::Target,
+    assets: &Assets,
+    options: &Options,
+    globals: &mut Globals,
+    global_names: &mut GlobalNames,
+    diagnostics: &mut Diagnostics,
+) -> Target {
     let mut sequences = vec![];
+    let mut procedures = vec![];
 
-    for (_, block) in &target.blocks {
+    let mut ctx = Context { blocks: &target.blocks, sprite: &target.name, options, diagnostics };
+    for (id, block) in &target.blocks {
         if block.topLevel {
+            if block.opcode == "procedures_definition" {
+                match parse_procedure(&mut ctx, id) {
+                    Ok(procedure) => procedures.push(procedure),
+                    Err(diagnostic) => ctx.diagnostics.push(*diagnostic),
+                }
+                continue
+            }
             if !is_block_real_toplevel(&block.opcode) { continue }
 
-            sequences.push(parse_sequence(&target.blocks, &block));
+            let diagnostic_count = ctx.diagnostics.0.len();
+            let sequence = parse_sequence(&mut ctx, id);
+            // a script starting with an unsupported hat would run immediately instead of never,
+            // so it is removed, along with the warnings about its blocks
+            if let Some(Block::Unsupported { opcode, .. }) = sequence.hat() {
+                ctx.diagnostics.0.truncate(diagnostic_count);
+                let warning = Diagnostic::warning(format!("script starting with unsupported hat `{opcode}` will never run"));
+                ctx.diagnostics.push(*ctx.locate(Diagnostic { kind: Kind::Unsupported, ..warning }, id, None));
+                continue
+            }
+            sequences.push(sequence);
         }
     }
 
-    let files: Vec<_> = std::fs::read_dir("project").unwrap().collect::<Result<_, _>>().unwrap();
     for costume in &mut target.costumes {
         if costume.md5ext.is_none() {
-            let file = files.iter().find(|f| f.file_name().to_str().unwrap().starts_with(&costume.assetId)).expect("expected asset to be linked to a valid file");
-            costume.md5ext = Some(file.file_name().to_str().unwrap().to_owned());
+            costume.md5ext = assets.keys().find(|f| f.starts_with(&costume.assetId)).cloned();
         }
     }
 
     let costumes = target.costumes.into_iter().map(|costume| {
         let filename = costume.md5ext.unwrap_or_else(|| format!("{}.{}", costume.assetId, costume.dataFormat));
+        let data = assets.get(&filename).cloned().unwrap_or_else(|| {
+            diagnostics.push(
+                Diagnostic::error(format!("costume `{}` refers to `{filename}`, which is not in the project", costume.name))
+                    .with_sprite(&target.name)
+            );
+            vec![]
+        });
         Costume {
             name: costume.name,
             filename,
+            data,
             bitmap_resolution: costume.bitmapResolution,
             rotation_center_x: costume.rotationCenterX,
             rotation_center_y: costume.rotationCenterY
         }
     }).collect();
 
+    let mut missing = |attribute: &str| {
+        diagnostics.push(Diagnostic::error(format!("missing attribute `{attribute}`")).with_sprite(&target.name));
+    };
     let kind = if target.isStage {
-        TargetKind::Stage { tempo: target.tempo.unwrap() }
+        TargetKind::Stage { tempo: target.tempo.unwrap_or_else(|| { missing("tempo"); 60 }) }
     } else {
+        let rotation_style = target.rotationStyle.as_deref().unwrap_or("all around");
         TargetKind::Sprite {
-            x: target.x.unwrap(),
-            y: target.y.unwrap(),
-            size: target.size.unwrap(),
-            direction: target.direction.unwrap(),
-            visible: target.visible.unwrap(),
-            draggable: target.draggable.unwrap(),
-            rotation_style: target.rotationStyle.unwrap().as_str().parse().expect("expected rotation style attribute to be well formed")
+            x: target.x.unwrap_or_else(|| { missing("x"); 0.0 }),
+            y: target.y.unwrap_or_else(|| { missing("y"); 0.0 }),
+            size: target.size.unwrap_or_else(|| { missing("size"); 100.0 }),
+            direction: target.direction.unwrap_or_else(|| { missing("direction"); 90.0 }),
+            visible: target.visible.unwrap_or(true),
+            rotation_style: rotation_style.parse().unwrap_or_else(|_| {
+                missing("rotationStyle");
+                RotationStyle::AllAround
+            })
         }
     };
 
     Target {
         name: target.name,
         sequences,
-        vars: sanitize_varnames(target.isStage, target.variables, globals, global_hashset),
+        procedures,
+        vars: sanitize_varnames(target.isStage, target.variables, globals, global_names),
+        lists: sanitize_lists(target.isStage, target.lists, globals, global_names),
         current_costume: target.currentCostume,
         costumes,
-        sounds: target.sounds,
         kind
     }
 }
 
-pub fn parse(targets: Vec<scratch::Target>) -> (Vec<Target>, VarMap) {
-    let mut globals = VarMap::new();
-    let mut global_hashset = HashSet::new();
-
-    let targets = targets.into_iter().map(|t| parse_target(t, &mut globals, &mut global_hashset)).collect();
+/// Parses every target of a project.
+/// Errors are added to `diagnostics`, the returned targets should not be compiled if there are any.
+pub fn parse(
+    targets: Vec<scratch::Target>,
+    assets: &Assets,
+    options: &Options,
+    diagnostics: &mut Diagnostics,
+) -> (Vec<Target>, Globals) {
+    let mut globals = Globals::default();
+    let mut global_names = GlobalNames::default();
+
+    let targets = targets
+        .into_iter()
+        .map(|t| parse_target(t, assets, options, &mut globals, &mut global_names, diagnostics))
+        .collect();
     (targets, globals)
 }
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::scratch;
+
+/// Map from asset file name (`md5ext`) to the content of the file
+pub type Assets = HashMap<String, Vec<u8>>;
+
+/// A scratch project with every file it references loaded in memory
+pub struct ProjectFiles {
+    pub project: scratch::Project,
+    pub assets: Assets,
+}
+
+/// Loads a project from either an `.sb3` archive or a directory containing an unpacked one.
+pub fn load(path: &Path) -> Result<ProjectFiles, Box<dyn Error>> {
+    if path.is_dir() {
+        load_directory(path)
+    } else {
+        load_archive(path)
+    }
+}
+
+fn load_directory(path: &Path) -> Result<ProjectFiles, Box<dyn Error>> {
+    let mut project = None;
+    let mut assets = Assets::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() { continue }
+
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else { continue };
+        let data = std::fs::read(entry.path())?;
+        if name == "project.json" {
+            project = Some(data);
+        } else {
+            assets.insert(name, data);
+        }
+    }
+
+    let project = project.ok_or_else(|| format!("no `project.json` in directory `{}`", path.display()))?;
+    Ok(ProjectFiles { project: serde_json::from_slice(&project)?, assets })
+}
+
+fn load_archive(path: &Path) -> Result<ProjectFiles, Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let mut project = None;
+    let mut assets = Assets::new();
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if !file.is_file() { continue }
+
+        // scratch puts every file at the root of the archive, but other tools may not
+        let name = file.name().rsplit('/').next().unwrap_or_default().to_owned();
+        // the size in the header isn't trusted, the vector grows with what is actually read
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        if name == "project.json" {
+            project = Some(data);
+        } else {
+            assets.insert(name, data);
+        }
+    }
+
+    let project = project.ok_or_else(|| format!("no `project.json` in archive `{}`", path.display()))?;
+    Ok(ProjectFiles { project: serde_json::from_slice(&project)?, assets })
+}
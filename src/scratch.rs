@@ -1,4 +1,6 @@
-use serde::Deserialize;
+// mirrors the layout of `project.json`, fields the compiler doesn't use are left out
+
+use serde::{de::Error, Deserialize, Deserializer};
 use serde_json::Value;
 use std::collections::HashMap;
 
@@ -10,13 +12,20 @@ pub struct Block {
     pub parent: Option<String>,
     pub inputs: HashMap<String, Vec<Value>>,
     pub fields: HashMap<String, (Value, Option<Value>)>,
-    pub shadow: bool,
     pub topLevel: bool,
-    pub x: Option<f32>,
-    pub y: Option<f32>,
-    pub comment: Option<String>,
     #[serde(default)]
-    pub mutation: Value,
+    pub mutation: Option<Mutation>,
+}
+
+/// Extra data of custom blocks and a few others.
+/// Scratch stores the arrays as JSON strings.
+#[derive(Debug, Deserialize)]
+pub struct Mutation {
+    pub proccode: Option<String>,
+    pub argumentids: Option<String>,
+    pub argumentnames: Option<String>,
+    /// either a boolean or a string ("true" or "false"), depending on the version of scratch
+    pub warp: Option<Value>,
 }
 
 #[allow(non_snake_case)]
@@ -35,23 +44,12 @@ pub struct Costume {
     pub rotationCenterY: i32,
 }
 
-#[allow(non_snake_case)]
-#[derive(Debug, Deserialize)]
-pub struct Sound {
-    pub assetId: String,
-    pub name: String,
-    /// The name of the asset file
-    pub md5ext: String,
-    pub dataFormat: String,
-    pub rate: u64,
-    pub sampleCount: u64,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct Variable(pub String, pub Value, #[serde(default)] pub Option<bool>);
 
+/// A list's name and its saved items, which can be numbers or strings
 #[derive(Debug, Deserialize)]
-pub struct List(String, Vec<i32>);
+pub struct List(pub String, pub Vec<Value>);
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
@@ -60,26 +58,15 @@ pub struct Target {
     pub name: String,
     pub variables: HashMap<String, Variable>,
     pub lists: HashMap<String, List>,
-    pub broadcasts: HashMap<String, Value>,
+    #[serde(deserialize_with = "deserialize_blocks")]
     pub blocks: HashMap<String, Block>,
-    pub comments: HashMap<String, Value>,
     pub currentCostume: usize,
     pub costumes: Vec<Costume>,
-    pub sounds: Vec<Sound>,
-    pub layerOrder: i32,
-    pub volume: u32,
+    /// only counted, sounds can't be played
+    pub sounds: Vec<Value>,
     /// Stage specific
     #[serde(default)]
     pub tempo: Option<u32>,
-    /// Stage specific
-    #[serde(default)]
-    pub videoState: Option<String>,
-    /// Stage specific
-    #[serde(default)]
-    pub videoTransparency: Option<u32>,
-    /// Stage specific
-    #[serde(default)]
-    pub textToSpeechLanguage: Option<String>,
     /// Sprite specific
     #[serde(default)]
     pub visible: Option<bool>,
@@ -97,16 +84,61 @@ pub struct Target {
     pub direction: Option<f32>,
     /// Sprite specific
     #[serde(default)]
-    pub draggable: Option<bool>,
-    /// Sprite specific
-    #[serde(default)]
     pub rotationStyle: Option<String>,
 }
 
+/// Deserializes the blocks of a target, dropping the loose variable and list reporters.
+/// Scratch stores those in a compact array form (`[12, "name", "id", x, y]`) instead of a block object,
+/// and since they are never part of a script, the compiler doesn't need them.
+fn deserialize_blocks<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, Block>, D::Error> {
+    let blocks = HashMap::<String, Value>::deserialize(deserializer)?;
+
+    blocks
+        .into_iter()
+        .filter(|(_, block)| !block.is_array())
+        .map(|(id, block)| match Block::deserialize(block) {
+            Ok(block) => Ok((id, block)),
+            Err(e) => Err(D::Error::custom(format!("invalid block `{id}`: {e}"))),
+        })
+        .collect()
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Project {
     pub targets: Vec<Target>,
-    pub monitors: Value,
-    pub extensions: Value,
-    pub meta: Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn target(blocks: Value) -> Value {
+        json!({
+            "isStage": true, "name": "Stage", "variables": {}, "lists": {}, "blocks": blocks,
+            "currentCostume": 0, "costumes": [], "sounds": [],
+        })
+    }
+
+    #[test]
+    fn drops_compact_reporters() {
+        let blocks = json!({
+            "var": [12, "my variable", "v1", 10, 20],
+            "list": [13, "my list", "l1", 30, 40],
+            "flag": {
+                "opcode": "event_whenflagclicked", "next": null, "parent": null,
+                "inputs": {}, "fields": {}, "shadow": false, "topLevel": true,
+            },
+        });
+        let target = Target::deserialize(target(blocks)).unwrap();
+        assert_eq!(target.blocks.keys().collect::<Vec<_>>(), ["flag"]);
+    }
+
+    #[test]
+    fn names_invalid_blocks() {
+        let blocks = json!({ "broken": { "opcode": "event_whenflagclicked" } });
+        let error = Target::deserialize(target(blocks)).unwrap_err().to_string();
+        assert!(error.starts_with("invalid block `broken`: "), "{error}");
+    }
 }